@@ -1,19 +1,27 @@
 use rand::Rng;
 
+use crate::db::db_iterator::DbIterator;
+use crate::db::write_batch::WriteBatch;
 use crate::logging::manifest::*;
 use crate::logging::wal::*;
-use crate::storage::block::BLOCK_SIZE;
+use crate::storage::block::{DEFAULT_BITS_PER_KEY, DEFAULT_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use crate::storage::block_cache::BlockCache;
 use crate::storage::iterator::*;
-use crate::storage::keys::{LookupKey, TableKey, TABLE_KEY_SIZE};
+use crate::storage::keys::{LookupKey, TableKey};
 use crate::storage::level::{Level, LevelState};
 use crate::storage::memtable::MemTable;
 use crate::storage::run::Run;
 use crate::storage::sstable::*;
+use crate::storage::table_cache::TableCache;
+use crate::util::error::Error;
 use crate::util::types::*;
-use std::cmp;
-use std::collections::{BinaryHeap, HashSet};
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
 use std::fs::{create_dir, remove_dir_all, remove_file};
+use std::iter;
+use std::mem;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::vec;
 
 /// database configurations.
@@ -24,6 +32,25 @@ pub struct Config {
     pub memtable_size_capacity: usize,
     /// sstable size capacity in bytes.
     pub sstable_size_capacity: usize,
+    /// size in bytes of each block in newly written sstables.
+    // the block size is recorded in the footer of each sstable, so sstables written with different
+    // block sizes could be read side by side.
+    pub block_size: usize,
+    /// compression type of data blocks in sstables written to each level.
+    /// levels beyond the specified ones use the last compression type.
+    pub compression_types: Vec<CompressionType>,
+    /// number of bits per key of the bloom filters in newly written sstables.
+    pub bits_per_key: usize,
+    /// layout of the bloom filters in newly written sstables.
+    pub filter_type: FilterType,
+    /// true to read sstables through memory maps by `get` and `range`.
+    pub use_mmap_reads: bool,
+    /// capacity in bytes of the block cache shared by reads.
+    pub block_cache_capacity: usize,
+    /// max number of sstable files kept open by the table cache.
+    pub max_open_files: usize,
+    /// the in-memory index backing memtables.
+    pub memtable_rep_type: MemTableRepType,
     /// run capacity.
     pub run_capacity: usize,
     /// max number of levels.
@@ -35,19 +62,22 @@ pub struct Config {
 /// database default configuration.
 impl Default for Config {
     /// create a default config.
-    // warning:
-    // currently, a block is of size 4096 bytes and hence could only store at most 240 table keys.
-    // on the other hand, each sstable only allocates one block as the index block which stores fence pointers.
-    // this means an sstable could at most store 240 data blocks which are of size 240 * 4096 = 983040 bytes,
-    // which is 57825 table keys.
-    // this limitation could be easily resolved by increasing the size of one block or let each sstable could allocate
-    // more than one blocks to be used as the index blocks.
-    // in summary, the default configuration does not work currently.
+    // the index of an sstable is partitioned into as many index blocks as needed, so an sstable
+    // could hold an arbitrary number of data blocks.
     fn default() -> Self {
         Self {
             fanout: 10,
             memtable_size_capacity: 4 * 1024 * 1024, // 4MB.
             sstable_size_capacity: 16 * 1024 * 1024, // 16MB.
+            block_size: DEFAULT_BLOCK_SIZE,
+            // sstables at the level 0 are short-lived and hence they're not compressed.
+            compression_types: vec![CompressionType::None, CompressionType::Lz4],
+            bits_per_key: DEFAULT_BITS_PER_KEY,
+            filter_type: FilterType::Full,
+            use_mmap_reads: false,
+            block_cache_capacity: 8 * 1024 * 1024, // 8MB.
+            max_open_files: 1000,
+            memtable_rep_type: MemTableRepType::SkipList,
             run_capacity: 4,
             max_levels: 4,
             recovery: false,
@@ -62,6 +92,14 @@ impl Config {
             fanout: 2,
             memtable_size_capacity: 16 * 1024, // 16KB.
             sstable_size_capacity: 64 * 1024,  // 64KB.
+            block_size: DEFAULT_BLOCK_SIZE,
+            compression_types: vec![CompressionType::None, CompressionType::Lz4],
+            bits_per_key: DEFAULT_BITS_PER_KEY,
+            filter_type: FilterType::Partitioned,
+            use_mmap_reads: false,
+            block_cache_capacity: 64 * 1024, // 64KB.
+            max_open_files: 8,
+            memtable_rep_type: MemTableRepType::SkipList,
             run_capacity: 4,
             max_levels: 4,
             recovery: false,
@@ -71,6 +109,47 @@ impl Config {
     pub fn set_recovery(&mut self, recovery: bool) {
         self.recovery = recovery;
     }
+
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = block_size;
+    }
+
+    pub fn set_bits_per_key(&mut self, bits_per_key: usize) {
+        self.bits_per_key = bits_per_key;
+    }
+
+    pub fn set_filter_type(&mut self, filter_type: FilterType) {
+        self.filter_type = filter_type;
+    }
+
+    pub fn set_use_mmap_reads(&mut self, use_mmap_reads: bool) {
+        self.use_mmap_reads = use_mmap_reads;
+    }
+
+    pub fn set_block_cache_capacity(&mut self, block_cache_capacity: usize) {
+        self.block_cache_capacity = block_cache_capacity;
+    }
+
+    pub fn set_max_open_files(&mut self, max_open_files: usize) {
+        self.max_open_files = max_open_files;
+    }
+
+    pub fn set_memtable_rep_type(&mut self, memtable_rep_type: MemTableRepType) {
+        self.memtable_rep_type = memtable_rep_type;
+    }
+
+    pub fn set_compression_types(&mut self, compression_types: Vec<CompressionType>) {
+        self.compression_types = compression_types;
+    }
+
+    /// return the compression type of data blocks in sstables written to the level.
+    pub fn compression_type(&self, level_num: LevelNum) -> CompressionType {
+        self.compression_types
+            .get(level_num)
+            .or(self.compression_types.last())
+            .copied()
+            .unwrap_or(CompressionType::None)
+    }
 }
 
 pub struct Db {
@@ -78,6 +157,10 @@ pub struct Db {
     pub cfg: Config,
     /// memtable.
     mem: MemTable,
+    /// the full memtable being flushed to level 0 in the background, if any.
+    imm: Option<Arc<MemTable>>,
+    /// the background thread flushing the immutable memtable.
+    flush_handle: Option<JoinHandle<FlushOutput>>,
     /// memtable log writer.
     mem_log_writer: Option<LogWriter>,
     /// all levels in the lsm tree.
@@ -86,10 +169,22 @@ pub struct Db {
     next_seq_num: SeqNum,
     /// the next file number to allocate for a file.
     next_file_num: FileNum,
+    /// the cache of data blocks shared by reads of all sstables.
+    block_cache: Arc<BlockCache>,
+    /// the cache of opened sstables shared by reads.
+    table_cache: Arc<TableCache>,
+    /// sequence numbers of live snapshots and the number of handles to each of them.
+    snapshots: BTreeMap<SeqNum, usize>,
 }
 
 impl Drop for Db {
     fn drop(&mut self) {
+        // wait for the background flush so that it does not write into a removed directory.
+        // the immutable memtable is not installed, and hence its log is replayed on restart.
+        if let Some(flush_handle) = self.flush_handle.take() {
+            let _ = flush_handle.join();
+        }
+
         // do not remove the sstables directory if recovery is turned on.
         if !self.cfg.recovery {
             let _ = remove_dir_all("./sstables");
@@ -99,18 +194,29 @@ impl Drop for Db {
 
 impl Db {
     pub fn new(cfg: Config) -> Db {
+        assert!(cfg.block_size >= MIN_BLOCK_SIZE);
+        assert!(cfg.bits_per_key > 0);
+
         // create a new sstables directory if not exist.
         let _ = create_dir("./sstables");
 
         let recovery = cfg.recovery;
+        let block_cache_capacity = cfg.block_cache_capacity;
+        let max_open_files = cfg.max_open_files;
+        let memtable_rep_type = cfg.memtable_rep_type;
 
         let mut db = Db {
             cfg,
-            mem: MemTable::new(),
+            mem: MemTable::new(memtable_rep_type),
+            imm: None,
+            flush_handle: None,
             mem_log_writer: None,
             levels: Vec::new(),
             next_seq_num: 0,
             next_file_num: 0,
+            block_cache: Arc::new(BlockCache::new(block_cache_capacity)),
+            table_cache: Arc::new(TableCache::new(max_open_files)),
+            snapshots: BTreeMap::new(),
         };
         db.levels.push(db.make_default_level(0));
 
@@ -123,11 +229,11 @@ impl Db {
     }
 
     pub fn make_default_level(&self, level_num: LevelNum) -> Level {
-        // size capacity of the level 0 = run capacity of the level 0 * (memtable size capacity + 3 * BLOCK_SIZE).
-        // where the 3 * BLOCK_SIZE corresponds to the filter, index and footer blocks inherently stored in
-        // one sstable file.
+        // size capacity of the level 0 = run capacity of the level 0 * (memtable size capacity + 4 * block size).
+        // where the 4 * block size corresponds to the filter, index, top-level index and footer blocks
+        // inherently stored in one sstable file flushed from a small memtable.
         let size_capacity_0 =
-            self.cfg.run_capacity * (self.cfg.memtable_size_capacity + 3 * BLOCK_SIZE);
+            self.cfg.run_capacity * (self.cfg.memtable_size_capacity + 4 * self.cfg.block_size);
         Level::new(
             level_num,
             self.cfg.run_capacity,
@@ -141,36 +247,83 @@ impl Db {
         seq_num
     }
 
-    fn snapshot_seq_num(&self) -> SeqNum {
-        self.next_seq_num
+    /// return the sequence number above which table keys are invisible to reads with the read options.
+    fn snapshot_seq_num(&self, read_options: &ReadOptions) -> SeqNum {
+        match read_options.snapshot.as_ref() {
+            Some(snapshot) => snapshot.seq_num,
+            // no table key has the next sequence number, so all written table keys are visible.
+            None => self.next_seq_num,
+        }
+    }
+
+    /// take a snapshot of the database.
+    /// reads with the snapshot set in the read options do not see writes after the snapshot is taken.
+    pub fn snapshot(&mut self) -> Snapshot {
+        // the snapshot consumes a sequence number so that no later write shares the sequence number
+        // of the snapshot and becomes visible to it.
+        let seq_num = self.alloc_seq_num();
+        *self.snapshots.entry(seq_num).or_insert(0) += 1;
+        Snapshot { seq_num }
+    }
+
+    /// release the snapshot so that compactions are free to drop the versions only visible to it.
+    /// releasing a snapshot which is already released has no effect.
+    pub fn release_snapshot(&mut self, snapshot: Snapshot) {
+        if let Some(count) = self.snapshots.get_mut(&snapshot.seq_num) {
+            *count -= 1;
+            if *count == 0 {
+                self.snapshots.remove(&snapshot.seq_num);
+            }
+        }
     }
 }
 
 /// db write implementation.
 impl Db {
-    pub fn put(&mut self, user_key: UserKey, user_val: UserValue) {
-        self.write(user_key, user_val, WriteType::Put);
+    /// return an invalid argument error if the user key exceeds `MAX_USER_KEY_SIZE` bytes.
+    pub fn put(&mut self, user_key: &[u8], user_val: &[u8]) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.put(user_key, user_val)?;
+        self.write_batch(&batch);
+        Ok(())
     }
 
-    pub fn delete(&mut self, user_key: UserKey) {
-        self.write(user_key, UserValue::default(), WriteType::Delete);
+    /// return an invalid argument error if the user key exceeds `MAX_USER_KEY_SIZE` bytes.
+    pub fn delete(&mut self, user_key: &[u8]) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete(user_key)?;
+        self.write_batch(&batch);
+        Ok(())
     }
 
-    fn write(&mut self, user_key: UserKey, user_val: UserValue, write_type: WriteType) {
-        let table_key = TableKey::new(user_key, self.alloc_seq_num(), write_type, user_val);
+    /// apply the writes in the batch atomically.
+    /// the writes are assigned consecutive sequence numbers and logged as one log record, so either
+    /// all or none of them survive a crash. reads never see a part of the batch.
+    pub fn write_batch(&mut self, batch: &WriteBatch) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let table_keys: Vec<TableKey> = batch
+            .writes()
+            .map(|(user_key, write_type, user_val)| {
+                TableKey::new(user_key, self.alloc_seq_num(), write_type, user_val)
+            })
+            .collect();
 
         if self.cfg.recovery {
-            self.mem_log_writer.as_mut().unwrap().push(&table_key);
+            self.mem_log_writer.as_mut().unwrap().push(&table_keys);
+        }
+        // the memtable is checked for a flush only after the whole batch is inserted, so that the
+        // batch is never split across memtables and their logs.
+        for table_key in table_keys {
+            self.mem.put(table_key);
         }
-        self.mem.put(table_key);
 
-        if self.mem.size() > self.cfg.memtable_size_capacity - TABLE_KEY_SIZE {
+        // install the flushed immutable memtable without waiting if the flush is done.
+        self.complete_minor_compaction(false);
+        if self.mem.size() >= self.cfg.memtable_size_capacity {
             self.minor_compaction();
-            self.check_level_state();
-            self.mem = MemTable::new();
-            if self.cfg.recovery {
-                self.mem_log_writer.as_mut().unwrap().reset();
-            }
         }
 
         // currently, recovery only applies on restart and hence we only need to update the manifest after performing write.
@@ -182,107 +335,297 @@ impl Db {
 
 /// db read implementation.
 impl Db {
+    /// return the read options used by reads without explicit read options.
+    fn default_read_options(&self) -> ReadOptions {
+        ReadOptions {
+            use_mmap: self.cfg.use_mmap_reads,
+            ..Default::default()
+        }
+    }
+
+    /// return the read options with the caches of the database attached.
+    fn attach_caches(&self, read_options: &ReadOptions) -> ReadOptions {
+        ReadOptions {
+            block_cache: Some(self.block_cache.clone()),
+            table_cache: Some(self.table_cache.clone()),
+            ..read_options.clone()
+        }
+    }
+
+    /// return the memtable and the immutable memtable if any, from the newest to the oldest.
+    fn memtables(&self) -> impl Iterator<Item = &MemTable> {
+        iter::once(&self.mem).chain(self.imm.as_deref())
+    }
+
+    /// return the iterators of the memtable and the immutable memtable if any.
+    fn memtable_iters(&self) -> Vec<TableKeyIteratorType<'_>> {
+        self.memtables().map(|mem| mem.iter()).collect()
+    }
+
+    /// return the hit and miss statistics of the block cache.
+    pub fn block_cache_stats(&self) -> CacheStats {
+        self.block_cache.stats()
+    }
+
+    /// return the hit and miss statistics of the table cache.
+    /// the usage is the number of open sstable files.
+    pub fn table_cache_stats(&self) -> CacheStats {
+        self.table_cache.stats()
+    }
+
     /// point query the associated value in the database.
-    pub fn get(&mut self, user_key: UserKey) -> Option<UserValue> {
-        let snapshot_seq_num = self.snapshot_seq_num();
+    /// panics if a corrupted sstable is encountered.
+    pub fn get(&mut self, user_key: &[u8]) -> Option<UserValue> {
+        self.get_with_options(&self.default_read_options(), user_key)
+            .unwrap()
+    }
+
+    /// point query the associated value in the database with the read options.
+    pub fn get_with_options(
+        &mut self,
+        read_options: &ReadOptions,
+        user_key: &[u8],
+    ) -> Result<Option<UserValue>, Error> {
+        let read_options = &self.attach_caches(read_options);
+        let snapshot_seq_num = self.snapshot_seq_num(read_options);
         let lookup_key = LookupKey::new(user_key, snapshot_seq_num);
 
-        // search the key in the memtable.
-        match self.mem.get(&lookup_key) {
-            // the key exists and is not deleted.
-            (Some(user_val), false) => return Some(user_val),
-            // the key exists but is deleted.
-            (Some(_), true) => return None,
-            // the key does not exist, proceed to searching in sstables.
-            (None, _) => {}
-        };
+        // search the key in the memtable and then in the immutable memtable.
+        for mem in self.memtables() {
+            match mem.get(&lookup_key) {
+                // the key exists and is not deleted.
+                (Some(user_val), false) => return Ok(Some(user_val)),
+                // the key exists but is deleted.
+                (Some(_), true) => return Ok(None),
+                // the key does not exist, proceed to searching in sstables.
+                (None, _) => {}
+            };
+        }
 
         // search the key in the lsm tree.
         for level in self.levels.iter() {
             // keys in shallower levels shadow keys having the same user keys in deeper levels,
             // and hence the searching terminates as soon as the key is found.
-            match level.get(&lookup_key) {
+            match level.get(&lookup_key, read_options)? {
                 // the key exists and is not deleted.
-                (Some(user_val), false) => return Some(user_val),
+                (Some(user_val), false) => return Ok(Some(user_val)),
                 // the key exists but is deleted.
-                (Some(_), true) => return None,
+                (Some(_), true) => return Ok(None),
                 // the key does not exist, proceed to searching in the next level.
                 (None, _) => {}
             };
         }
 
         // the key does not exist.
-        None
+        Ok(None)
     }
 
-    /// range query the values associated with keys in the key range [start_user_key, end_user_key).
-    pub fn range(&mut self, start_user_key: UserKey, end_user_key: UserKey) -> Vec<UserEntry> {
-        let snapshot_seq_num = self.snapshot_seq_num();
-        let start_lookup_key = LookupKey::new(start_user_key, snapshot_seq_num);
-        let end_lookup_key = LookupKey::new(end_user_key, snapshot_seq_num);
+    /// point query the associated values of a batch of keys in the database.
+    /// the values are returned in the order of the keys.
+    /// panics if a corrupted sstable is encountered.
+    pub fn multi_get(&mut self, user_keys: &[UserKey]) -> Vec<Option<UserValue>> {
+        self.multi_get_with_options(&self.default_read_options(), user_keys)
+            .unwrap()
+    }
 
-        // iterator container to hold iterators from the memtable and all levels of sstables.
-        let mut iters: BinaryHeap<TableKeyIteratorType> = BinaryHeap::new();
-        let mut mem_iter = Box::new(self.mem.iter());
-        mem_iter.next();
-        iters.push(mem_iter);
+    /// point query the associated values of a batch of keys in the database with the read options.
+    pub fn multi_get_with_options(
+        &mut self,
+        read_options: &ReadOptions,
+        user_keys: &[UserKey],
+    ) -> Result<Vec<Option<UserValue>>, Error> {
+        let read_options = &self.attach_caches(read_options);
+        let snapshot_seq_num = self.snapshot_seq_num(read_options);
+        let mut user_vals = vec![None; user_keys.len()];
+
+        // the keys are sorted so that each layer of the lsm tree is searched in one pass.
+        let mut idxs: Vec<usize> = (0..user_keys.len()).collect();
+        idxs.sort_by(|a, b| user_keys[*a].cmp(&user_keys[*b]));
+        let mut lookup_keys: Vec<LookupKey> = idxs
+            .iter()
+            .map(|idx| LookupKey::new(&user_keys[*idx], snapshot_seq_num))
+            .collect();
+
+        // search the keys in the memtable and then in the immutable memtable.
+        for mem in self.memtables() {
+            let results = mem.multi_get(&lookup_keys);
+            Db::resolve_keys(&mut user_vals, &mut idxs, &mut lookup_keys, results);
+        }
 
+        // search the remaining keys in the lsm tree level by level, since keys in shallower levels
+        // shadow keys having the same user keys in deeper levels.
         for level in self.levels.iter() {
-            let mut level_iter = Box::new(level.iter().unwrap());
-            level_iter.next();
-            iters.push(level_iter);
+            if lookup_keys.is_empty() {
+                break;
+            }
+            let results = level.multi_get(&lookup_keys, read_options)?;
+            Db::resolve_keys(&mut user_vals, &mut idxs, &mut lookup_keys, results);
         }
 
-        let mut entries = Vec::new();
-
-        let mut last_user_key = None;
-        // loop inv: there's at least one iterator in the heap.
-        while let Some(mut iter) = iters.pop() {
-            // proceed if the iterator is not exhausted.
-            if iter.valid() {
-                let table_key = iter.curr().unwrap();
+        Ok(user_vals)
+    }
 
-                // early termination: the current key has a user key equal to or greater than the end user key.
-                if table_key.user_key >= end_user_key {
-                    break;
+    /// record the values of keys found in a layer of the lsm tree, and retain the lookup keys not
+    /// found for searching in deeper layers.
+    fn resolve_keys(
+        user_vals: &mut [Option<UserValue>],
+        idxs: &mut Vec<usize>,
+        lookup_keys: &mut Vec<LookupKey>,
+        results: Vec<(Option<UserValue>, bool)>,
+    ) {
+        let mut num_unresolved = 0;
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                // the key exists and is not deleted.
+                (Some(user_val), false) => user_vals[idxs[i]] = Some(user_val),
+                // the key exists but is deleted.
+                (Some(_), true) => {}
+                // the key does not exist, proceed to searching in the next layer.
+                (None, _) => {
+                    idxs.swap(num_unresolved, i);
+                    lookup_keys.swap(num_unresolved, i);
+                    num_unresolved += 1;
                 }
+            }
+        }
+        idxs.truncate(num_unresolved);
+        lookup_keys.truncate(num_unresolved);
+    }
 
-                // ensure the table key is in the query range and.
-                if table_key.user_key >= start_lookup_key.user_key
-                    && table_key.user_key < end_lookup_key.user_key
-                {
-                    // only the latest visible table key for each user key is collected.
-                    if last_user_key.is_none() || table_key.user_key != last_user_key.unwrap() {
-                        last_user_key = Some(table_key.user_key);
-
-                        match table_key.write_type {
-                            // only non-deleted keys are collected.
-                            WriteType::Put => {
-                                entries.push(UserEntry {
-                                    key: table_key.user_key,
-                                    val: table_key.user_val,
-                                });
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+    /// range query the values associated with keys in the key range [start_user_key, end_user_key).
+    /// panics if a corrupted sstable is encountered.
+    pub fn range(&mut self, start_user_key: &[u8], end_user_key: &[u8]) -> Vec<UserEntry> {
+        self.range_with_options(&self.default_read_options(), start_user_key, end_user_key)
+            .unwrap()
+    }
 
-                // push back the iterator into the heap.
-                iter.next();
-                iters.push(iter);
+    /// range query the values associated with keys in the key range [start_user_key, end_user_key)
+    /// with the read options.
+    pub fn range_with_options(
+        &mut self,
+        read_options: &ReadOptions,
+        start_user_key: &[u8],
+        end_user_key: &[u8],
+    ) -> Result<Vec<UserEntry>, Error> {
+        self.fold_range(
+            read_options,
+            start_user_key,
+            end_user_key,
+            Vec::new(),
+            |mut entries, user_key, user_val| {
+                entries.push(UserEntry {
+                    key: user_key.to_vec(),
+                    val: user_val.to_vec(),
+                });
+                entries
+            },
+        )
+    }
+
+    /// aggregate the values associated with keys in the key range [start_user_key, end_user_key).
+    /// return none if no value is aggregated by min or max.
+    /// panics if a corrupted sstable is encountered.
+    pub fn aggregate(
+        &mut self,
+        op: AggregateOp,
+        start_user_key: &[u8],
+        end_user_key: &[u8],
+    ) -> Option<i64> {
+        self.aggregate_with_options(
+            &self.default_read_options(),
+            op,
+            start_user_key,
+            end_user_key,
+        )
+        .unwrap()
+    }
+
+    /// aggregate the values associated with keys in the key range [start_user_key, end_user_key)
+    /// with the read options.
+    /// the aggregate is computed while streaming through the range and no entry is collected.
+    pub fn aggregate_with_options(
+        &mut self,
+        read_options: &ReadOptions,
+        op: AggregateOp,
+        start_user_key: &[u8],
+        end_user_key: &[u8],
+    ) -> Result<Option<i64>, Error> {
+        // values which are not decimal integers are skipped by sum, min and max.
+        let parse = |user_val: &[u8]| std::str::from_utf8(user_val).ok()?.parse::<i64>().ok();
+        let fold = |acc: Option<i64>, user_val: &[u8]| match (op, parse(user_val)) {
+            (AggregateOp::Count, _) => acc.map(|count| count + 1),
+            (_, None) => acc,
+            (AggregateOp::Sum, Some(val)) => acc.map(|sum| sum.saturating_add(val)),
+            (AggregateOp::Min, Some(val)) => Some(acc.map_or(val, |min| min.min(val))),
+            (AggregateOp::Max, Some(val)) => Some(acc.map_or(val, |max| max.max(val))),
+        };
+        let init = match op {
+            AggregateOp::Count | AggregateOp::Sum => Some(0),
+            AggregateOp::Min | AggregateOp::Max => None,
+        };
+        self.fold_range(
+            read_options,
+            start_user_key,
+            end_user_key,
+            init,
+            |acc, _, user_val| fold(acc, user_val),
+        )
+    }
+
+    /// fold the user entries in the key range [start_user_key, end_user_key) in order.
+    fn fold_range<T>(
+        &self,
+        read_options: &ReadOptions,
+        start_user_key: &[u8],
+        end_user_key: &[u8],
+        init: T,
+        mut f: impl FnMut(T, &[u8], &[u8]) -> T,
+    ) -> Result<T, Error> {
+        let read_options = &self.attach_caches(read_options);
+
+        // levels, runs and sstables disjoint from the range are skipped.
+        let mut iters = self.memtable_iters();
+        for level in self.levels.iter() {
+            if level.overlaps(start_user_key, end_user_key) {
+                iters.push(Box::new(level.range_iter(
+                    read_options,
+                    start_user_key,
+                    end_user_key,
+                )));
             }
         }
 
-        entries
+        // the iterator is seeked to the start key so that keys before the range are never read.
+        let mut iter = DbIterator::new(iters, self.snapshot_seq_num(read_options));
+        iter.seek(start_user_key);
+
+        let mut acc = init;
+        while iter.valid() && iter.key() < end_user_key {
+            acc = f(acc, iter.key(), iter.value());
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(acc)
+    }
+
+    /// return an iterator over the user entries of the database with the read options.
+    /// the iterator is invalid until it's positioned by `seek` or `seek_to_first`.
+    pub fn iter(&self, read_options: &ReadOptions) -> DbIterator<'_> {
+        let read_options = &self.attach_caches(read_options);
+        let mut iters = self.memtable_iters();
+        for level in self.levels.iter() {
+            iters.push(Box::new(level.iter(read_options)));
+        }
+        DbIterator::new(iters, self.snapshot_seq_num(read_options))
     }
 }
 
 /// the context of a major compaction.
 struct CompactionContext {
-    /// min user key of the current level.
+    /// min user key of the compaction inputs.
     min_user_key: UserKey,
-    /// max user key of the current level.
+    /// max user key of the compaction inputs.
     max_user_key: UserKey,
     /// compaction inputs, aka. all sstables involved in the compaction.
     inputs: Vec<Rc<SSTable>>,
@@ -291,8 +634,8 @@ struct CompactionContext {
 impl CompactionContext {
     fn from_base_sstable(base: Rc<SSTable>) -> Self {
         Self {
-            min_user_key: base.min_table_key.user_key,
-            max_user_key: base.max_table_key.user_key,
+            min_user_key: base.min_table_key.user_key.clone(),
+            max_user_key: base.max_table_key.user_key.clone(),
             inputs: vec![base],
         }
     }
@@ -300,7 +643,7 @@ impl CompactionContext {
     fn from_base_run(run: &Run) -> Self {
         let mut ctx = CompactionContext::from_base_sstable(run.sstables.first().unwrap().clone());
         for i in 1..run.sstables.len() {
-            ctx.add_input(run.sstables.get(i).unwrap().clone(), true);
+            ctx.add_input(run.sstables.get(i).unwrap().clone());
         }
         ctx
     }
@@ -309,7 +652,7 @@ impl CompactionContext {
         self.inputs.first().unwrap()
     }
 
-    fn range_overlap(min: i32, max: i32, other_min: i32, other_max: i32) -> bool {
+    fn range_overlap(min: &[u8], max: &[u8], other_min: &[u8], other_max: &[u8]) -> bool {
         (min >= other_min && min <= other_max)
             || (max >= other_min && max <= other_max)
             || (min >= other_min && max <= other_max)
@@ -317,92 +660,234 @@ impl CompactionContext {
     }
 
     fn overlap(me: &SSTable, other: &SSTable) -> bool {
-        let (min, max) = (me.min_table_key.user_key, me.max_table_key.user_key);
-        let (other_min, other_max) = (other.min_table_key.user_key, other.max_table_key.user_key);
+        let (min, max) = (&me.min_table_key.user_key, &me.max_table_key.user_key);
+        let (other_min, other_max) = (&other.min_table_key.user_key, &other.max_table_key.user_key);
         CompactionContext::range_overlap(min, max, other_min, other_max)
     }
 
-    /// return true if the key range of the given sstable overlaps with the key range of the base sstable.
-    fn overlap_with_base(&self, other: &SSTable) -> bool {
-        let base = self.get_base();
-        CompactionContext::overlap(base, other)
-    }
-
-    /// return true if the key range of the given sstable overlaps with the key range of the base run.
+    /// return true if the key range of the given run overlaps with the key range of the collected
+    /// runs.
     fn overlap_with_base_run(&self, other: &Run) -> bool {
-        let (min, max) = (self.min_user_key, self.max_user_key);
+        let (min, max) = (&self.min_user_key, &self.max_user_key);
         let (other_min, other_max) = (
-            other.min_table_key.as_ref().unwrap().user_key,
-            other.max_table_key.as_ref().unwrap().user_key,
+            &other.min_table_key.as_ref().unwrap().user_key,
+            &other.max_table_key.as_ref().unwrap().user_key,
         );
         CompactionContext::range_overlap(min, max, other_min, other_max)
     }
 
     /// return true if the key range of the given sstable overlaps with the key range of the current the level.
     fn overlap_with_curr_level(&self, other: &SSTable) -> bool {
-        let (min, max) = (self.min_user_key, self.max_user_key);
-        let (other_min, other_max) = (other.min_table_key.user_key, other.max_table_key.user_key);
+        let (min, max) = (&self.min_user_key, &self.max_user_key);
+        let (other_min, other_max) = (&other.min_table_key.user_key, &other.max_table_key.user_key);
         CompactionContext::range_overlap(min, max, other_min, other_max)
     }
 
-    fn add_input(&mut self, input: Rc<SSTable>, is_curr_level: bool) {
-        // try to extend the key range of the current level.
-        if is_curr_level {
-            self.min_user_key = cmp::min(self.min_user_key, input.min_table_key.user_key);
-            self.max_user_key = cmp::max(self.max_user_key, input.max_table_key.user_key);
+    fn add_input(&mut self, input: Rc<SSTable>) {
+        // try to extend the key range of the compaction.
+        if input.min_table_key.user_key < self.min_user_key {
+            self.min_user_key = input.min_table_key.user_key.clone();
+        }
+        if input.max_table_key.user_key > self.max_user_key {
+            self.max_user_key = input.max_table_key.user_key.clone();
         }
         self.inputs.push(input);
     }
 
     fn add_run(&mut self, run: &Run) {
         for sstable in run.sstables.iter() {
-            self.add_input(sstable.clone(), true);
+            self.add_input(sstable.clone());
+        }
+    }
+
+    /// remove the runs overlapping with the key range of the compaction from the level and add them
+    /// as inputs. return true if any run is collected.
+    fn collect_overlapping_runs(&mut self, level: &mut Level) -> bool {
+        // each collected run may extend the key range, and hence the collecting repeats until no more
+        // runs overlap. otherwise, an uncollected run may overlap the merged run which is added as
+        // the newest run, and lookups would stop at the merged run even if the uncollected run keeps
+        // newer versions of some keys.
+        let mut collected = false;
+        while let Some(run_idx) = level
+            .runs
+            .iter()
+            .position(|run| self.overlap_with_base_run(run))
+        {
+            let run = level.runs.remove(run_idx);
+            self.add_run(&run);
+            collected = true;
         }
+        collected
     }
 
-    fn iters(&self) -> BinaryHeap<TableKeyIteratorType> {
+    fn iters(&self) -> Result<BinaryHeap<TableKeyIteratorType<'_>>, Error> {
         let mut iters: BinaryHeap<TableKeyIteratorType> = BinaryHeap::new();
         for input in self.inputs.iter() {
-            let mut iter = Box::new(input.iter().unwrap());
+            // compaction inputs are always verified so that corrupted table keys are not
+            // persisted into new sstables with valid checksums.
+            // they're read sequentially once and hence positioned reads are used, and the read
+            // data blocks are not cached.
+            let read_options = ReadOptions {
+                verify_checksums: true,
+                ..Default::default()
+            };
+            let mut iter = Box::new(input.iter(&read_options)?);
             iter.next();
             iters.push(iter);
         }
-        iters
+        Ok(iters)
+    }
+}
+
+/// drops the table keys shadowed by newer versions of the same user keys in a compaction.
+/// reads without snapshots see the latest version of a user key, and a live snapshot sees the latest
+/// version not newer than it. so the live snapshots partition the versions of a user key into stripes,
+/// and only the latest version in each stripe is kept.
+struct VersionFilter<'a> {
+    /// sequence numbers of live snapshots.
+    snapshots: &'a BTreeMap<SeqNum, usize>,
+    /// the user key and the stripe of the last kept table key.
+    last: Option<(UserKey, Option<SeqNum>)>,
+}
+
+impl<'a> VersionFilter<'a> {
+    fn new(snapshots: &'a BTreeMap<SeqNum, usize>) -> Self {
+        Self {
+            snapshots,
+            last: None,
+        }
+    }
+
+    /// return true if the table key is the latest version in its stripe.
+    /// table keys must be fed in order, i.e. the versions of a user key from the latest to the oldest.
+    fn keep(&mut self, table_key: &TableKey) -> bool {
+        // a stripe is identified by the oldest snapshot seeing its versions, or none if its versions
+        // are newer than all live snapshots.
+        let stripe = self
+            .snapshots
+            .range(table_key.seq_num..)
+            .next()
+            .map(|(seq_num, _)| *seq_num);
+        if let Some((user_key, last_stripe)) = self.last.as_ref() {
+            if *user_key == table_key.user_key && *last_stripe == stripe {
+                return false;
+            }
+        }
+        self.last = Some((table_key.user_key.clone(), stripe));
+        true
     }
 }
 
+/// the sstables flushed from the immutable memtable by the background thread.
+struct FlushOutput {
+    /// sstables are sent back as plain metadata since `Rc` could not cross threads.
+    sstables: Vec<SSTable>,
+    min_table_key: TableKey,
+    max_table_key: TableKey,
+    next_file_num: FileNum,
+}
+
 /// db compaction implementation.
 impl Db {
-    /// flush the table keys in memtable to a new sstable.
+    /// turn the full memtable into the immutable memtable and flush it to level 0 in the background,
+    /// while writes go to a new memtable.
     fn minor_compaction(&mut self) {
-        let mut sstable_writer_batch =
-            SSTableWriterBatch::new(self.next_file_num, self.cfg.sstable_size_capacity);
-
-        println!("minor compacting...");
-
-        // compact table keys having the same user keys
-        let mut last_user_key = None;
-        let mut iter = self.mem.iter();
-        while let Some(table_key) = iter.next() {
-            if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
-                last_user_key = Some(table_key.user_key);
-                sstable_writer_batch.push(table_key);
+        // there's at most one immutable memtable, so the write stalls only if the previous flush
+        // is not done yet.
+        self.complete_minor_compaction(true);
+
+        let imm = Arc::new(mem::replace(
+            &mut self.mem,
+            MemTable::new(self.cfg.memtable_rep_type),
+        ));
+        if self.cfg.recovery {
+            self.mem_log_writer.as_mut().unwrap().freeze();
+        }
+        self.imm = Some(imm.clone());
+
+        // no file number is allocated by the foreground until the flush is installed, since major
+        // compactions only happen after that. so the flush could allocate file numbers on its own.
+        let next_file_num = self.next_file_num;
+        let sstable_size_capacity = self.cfg.sstable_size_capacity;
+        let block_size = self.cfg.block_size;
+        let compression_type = self.cfg.compression_type(0);
+        let bits_per_key = self.cfg.bits_per_key;
+        let filter_type = self.cfg.filter_type;
+        // snapshots taken after this point see all table keys in the immutable memtable, so they
+        // do not affect which versions are kept.
+        let snapshots = self.snapshots.clone();
+
+        self.flush_handle = Some(thread::spawn(move || {
+            println!("minor compacting...");
+
+            let mut sstable_writer_batch = SSTableWriterBatch::new(
+                next_file_num,
+                sstable_size_capacity,
+                block_size,
+                compression_type,
+                bits_per_key,
+                filter_type,
+            );
+
+            // compact table keys having the same user keys, except for those visible to live snapshots.
+            let mut version_filter = VersionFilter::new(&snapshots);
+            let mut iter = imm.iter();
+            while let Some(table_key) = iter.next() {
+                if version_filter.keep(&table_key) {
+                    sstable_writer_batch.push(table_key);
+                }
             }
+
+            // complete the write.
+            let (sstables, next_file_num) = sstable_writer_batch.done();
+            FlushOutput {
+                sstables: sstables
+                    .iter()
+                    .map(|sstable| SSTable::clone(sstable))
+                    .collect(),
+                min_table_key: sstable_writer_batch.min_table_key.take().unwrap(),
+                max_table_key: sstable_writer_batch.max_table_key.take().unwrap(),
+                next_file_num,
+            }
+        }));
+    }
+
+    /// add the sstables flushed from the immutable memtable as the newest run of level 0, and then
+    /// compact levels exceeding their capacities.
+    /// if `wait` is false, this is a no-op unless the background flush is done.
+    fn complete_minor_compaction(&mut self, wait: bool) {
+        match self.flush_handle.as_ref() {
+            None => return,
+            Some(flush_handle) if !wait && !flush_handle.is_finished() => return,
+            _ => {}
         }
+        let output = self.flush_handle.take().unwrap().join().unwrap();
 
-        // complete the write.
-        let (sstables, next_file_num) = sstable_writer_batch.done();
         // sync the next_file_num.
-        self.next_file_num = next_file_num;
+        self.next_file_num = output.next_file_num;
 
         let run = Run::new(
-            sstables,
-            sstable_writer_batch.min_table_key.as_ref().unwrap().clone(),
-            sstable_writer_batch.max_table_key.as_ref().unwrap().clone(),
+            output.sstables.into_iter().map(Rc::new).collect(),
+            output.min_table_key,
+            output.max_table_key,
         );
 
         // add this run to level 0.
         self.levels.get_mut(0).unwrap().add_run(run);
+        self.imm = None;
+        self.check_level_state();
+
+        // the log of the immutable memtable is removed only after the flushed sstables are
+        // recorded in the manifest.
+        if self.cfg.recovery {
+            self.update_manifest();
+            remove_imm_log();
+        }
+    }
+
+    /// block until the background flush, if any, is done and installed.
+    pub fn wait_for_flush(&mut self) {
+        self.complete_minor_compaction(true);
     }
 
     fn check_level_state(&mut self) {
@@ -413,7 +898,12 @@ impl Db {
                     level.state()
                 {
                     print!("\nBefore compaction:\n{}\n", self.stats());
-                    self.major_compaction(level_num);
+                    // a failed compaction leaves the levels unchanged, and the compaction is retried
+                    // when the next flush is installed.
+                    if let Err(err) = self.major_compaction(level_num) {
+                        println!("Compaction failed: {}", err);
+                        return;
+                    }
                     print!("\nAfter compaction:\n{}\n", self.stats());
                     // do not increment the level number since a level may exceed
                     // the size capacity and the run capacity at the same time.
@@ -442,29 +932,47 @@ impl Db {
         sstable.clone()
     }
 
-    fn major_compaction(&mut self, level_num: LevelNum) {
+    /// compact the level. if the compaction fails, the levels are left unchanged.
+    fn major_compaction(&mut self, level_num: LevelNum) -> Result<(), Error> {
         let curr_level = self.levels.get(level_num).unwrap();
         if let LevelState::ExceedSizeCapacity = curr_level.state() {
-            self.vertical_compaction(level_num);
+            self.vertical_compaction(level_num)
         } else {
-            self.horizontal_compaction(level_num);
+            self.horizontal_compaction(level_num)
         }
     }
 
-    fn merge(&mut self, iters: &mut BinaryHeap<TableKeyIteratorType>) -> Run {
-        let mut sstable_writer_batch =
-            SSTableWriterBatch::new(self.next_file_num, self.cfg.sstable_size_capacity);
+    /// merge the table keys into a new run to be placed at the output level.
+    /// if any input fails, the written sstables are deleted and the error is returned.
+    fn merge(
+        &mut self,
+        iters: &mut BinaryHeap<TableKeyIteratorType>,
+        output_level_num: LevelNum,
+    ) -> Result<Run, Error> {
+        let mut sstable_writer_batch = SSTableWriterBatch::new(
+            self.next_file_num,
+            self.cfg.sstable_size_capacity,
+            self.cfg.block_size,
+            self.cfg.compression_type(output_level_num),
+            self.cfg.bits_per_key,
+            self.cfg.filter_type,
+        );
 
         let mut num_input_keys = 0;
         let mut num_merged_keys = 0;
         let mut num_output_keys = 0;
 
-        let mut last_user_key = None;
+        let mut version_filter = VersionFilter::new(&self.snapshots);
         while let Some(mut iter) = iters.pop() {
+            // abort the compaction on corrupted inputs rather than silently dropping table keys.
+            if let Err(err) = iter.status() {
+                self.next_file_num = sstable_writer_batch.abort();
+                return Err(err);
+            }
+
             if let Some(table_key) = iter.curr() {
                 num_input_keys += 1;
-                if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
-                    last_user_key = Some(table_key.user_key);
+                if version_filter.keep(&table_key) {
                     // println!("push key {} to writer", table_key);
                     sstable_writer_batch.push(table_key);
                     num_output_keys += 1;
@@ -509,15 +1017,15 @@ impl Db {
             }
         }
 
-        Run::new(
+        Ok(Run::new(
             sstables,
             sstable_writer_batch.min_table_key.as_ref().unwrap().clone(),
             sstable_writer_batch.max_table_key.as_ref().unwrap().clone(),
-        )
+        ))
     }
 
     /// merge inputs into a new run and insert this run into the next level.
-    fn vertical_compaction(&mut self, curr_level_num: LevelNum) {
+    fn vertical_compaction(&mut self, curr_level_num: LevelNum) -> Result<(), Error> {
         // select the base sstable in the current level.
         let base = self.select_compaction_base(curr_level_num);
         println!("major compacting level {}", curr_level_num);
@@ -532,54 +1040,73 @@ impl Db {
         let mut ctx = CompactionContext::from_base_sstable(base);
 
         // collect overlapping sstables in the current level.
+        // each collected sstable may extend the key range of the current level, and hence the collecting
+        // repeats until no more sstables overlap. otherwise, an uncollected sstable may keep an older version
+        // of a key whose newer version is moved to the next level, and the older version would shadow it.
         println!("collecting sstables at level {}", curr_level_num);
-        for run in curr_level.runs.iter() {
-            for sstable in run.sstables.iter() {
-                // skip the base sstable itself.
-                if ctx.get_base().file_num == sstable.file_num {
-                    continue;
-                }
+        let mut collected = HashSet::new();
+        collected.insert(ctx.get_base().file_num);
+        loop {
+            let mut expanded = false;
+            for run in curr_level.runs.iter() {
+                for sstable in run.sstables.iter() {
+                    if collected.contains(&sstable.file_num) {
+                        continue;
+                    }
 
-                if ctx.overlap_with_base(sstable) {
-                    ctx.add_input(sstable.clone(), true);
-                    println!(
-                        "collect sstable {}, Min = {}  Max = {}",
-                        sstable.file_num,
-                        sstable.min_table_key.clone(),
-                        sstable.max_table_key.clone()
-                    );
+                    if ctx.overlap_with_curr_level(sstable) {
+                        ctx.add_input(sstable.clone());
+                        collected.insert(sstable.file_num);
+                        expanded = true;
+                        println!(
+                            "collect sstable {}, Min = {}  Max = {}",
+                            sstable.file_num,
+                            sstable.min_table_key.clone(),
+                            sstable.max_table_key.clone()
+                        );
+                    }
                 }
             }
+            if !expanded {
+                break;
+            }
         }
 
         println!(
             "current level key range: Min = {}  Max = {}",
-            ctx.min_user_key, ctx.max_user_key
+            String::from_utf8_lossy(&ctx.min_user_key),
+            String::from_utf8_lossy(&ctx.max_user_key)
         );
 
-        // create the next level if necessary.
-        if self.levels.get(curr_level_num + 1).is_none() {
-            let curr_level = self.levels.get(curr_level_num).unwrap();
-            self.levels.push(Level::new(
-                curr_level_num + 1,
-                curr_level.run_capacity,
-                curr_level.size_capacity * self.cfg.fanout,
-            ))
-        }
-        let next_level = self.levels.get(curr_level_num + 1).unwrap();
-
-        // collect overlapping sstables in the next level.
-        println!("collecting sstables at level {}", next_level.level_num);
-        for run in next_level.runs.iter() {
-            for sstable in run.sstables.iter() {
-                if ctx.overlap_with_curr_level(sstable) {
-                    ctx.add_input(sstable.clone(), false);
-                    println!(
-                        "collect sstable {}, Min = {}  Max = {}",
-                        sstable.file_num,
-                        sstable.min_table_key.clone(),
-                        sstable.max_table_key.clone()
-                    );
+        // collect overlapping sstables in the next level, if any.
+        // the same as the current level, the collecting repeats until no more sstables overlap.
+        // otherwise, an uncollected sstable may overlap the merged run which is added as the newest
+        // run of the next level, and its newer versions of some keys would be shadowed by lookups.
+        if let Some(next_level) = self.levels.get(curr_level_num + 1) {
+            println!("collecting sstables at level {}", next_level.level_num);
+            loop {
+                let mut expanded = false;
+                for run in next_level.runs.iter() {
+                    for sstable in run.sstables.iter() {
+                        if collected.contains(&sstable.file_num) {
+                            continue;
+                        }
+
+                        if ctx.overlap_with_curr_level(sstable) {
+                            ctx.add_input(sstable.clone());
+                            collected.insert(sstable.file_num);
+                            expanded = true;
+                            println!(
+                                "collect sstable {}, Min = {}  Max = {}",
+                                sstable.file_num,
+                                sstable.min_table_key.clone(),
+                                sstable.max_table_key.clone()
+                            );
+                        }
+                    }
+                }
+                if !expanded {
+                    break;
                 }
             }
         }
@@ -588,14 +1115,24 @@ impl Db {
         // that's because an sstable cannot be modified anyway which means even
         // its file name cannot be renamed.
         // hence we must do merging to move keys from the old sstable file to the new sstable file.
-        let run = self.merge(&mut ctx.iters());
+        let run = self.merge(&mut ctx.iters()?, curr_level_num + 1)?;
 
+        // create the next level if necessary.
+        if self.levels.get(curr_level_num + 1).is_none() {
+            let curr_level = self.levels.get(curr_level_num).unwrap();
+            self.levels.push(Level::new(
+                curr_level_num + 1,
+                curr_level.run_capacity,
+                curr_level.size_capacity * self.cfg.fanout,
+            ))
+        }
         self.levels
             .get_mut(curr_level_num + 1)
             .unwrap()
             .add_run(run);
 
         self.remove_obsolete_sstables(&ctx);
+        Ok(())
     }
 
     // randomly select a run in the current level.
@@ -606,7 +1143,7 @@ impl Db {
         base_file_num: Option<FileNum>,
     ) -> Run {
         let curr_level = self.levels.get_mut(curr_level_num).unwrap();
-        assert!(curr_level.runs.len() >= 1);
+        assert!(!curr_level.runs.is_empty());
 
         let mut run_idx;
         loop {
@@ -614,9 +1151,9 @@ impl Db {
             let run = curr_level.runs.get(run_idx).unwrap();
 
             let mut ok = true;
-            if base_file_num.is_some() {
+            if let Some(base_file_num) = base_file_num {
                 for sstable in run.sstables.iter() {
-                    if sstable.file_num == base_file_num.unwrap() {
+                    if sstable.file_num == base_file_num {
                         ok = false;
                         break;
                     }
@@ -654,32 +1191,34 @@ impl Db {
     /// try to collect overlapping runs with the base run.
     /// if no runs are collected, randomly select another run in the current level.
     /// merge this runs into a new run.
-    fn horizontal_compaction(&mut self, curr_level_num: LevelNum) {
+    fn horizontal_compaction(&mut self, curr_level_num: LevelNum) -> Result<(), Error> {
+        // the selected runs are removed from the level before the merge, and hence they're restored
+        // if the merge fails.
+        let runs = self.levels.get(curr_level_num).unwrap().runs.clone();
         let base_run = self.select_compaction_run(curr_level_num, None);
         let mut ctx = CompactionContext::from_base_run(&base_run);
 
         let curr_level = self.levels.get_mut(curr_level_num).unwrap();
-        let mut overlap_runs = Vec::new();
-        for (run_idx, run) in curr_level.runs.iter().enumerate() {
-            if ctx.overlap_with_base_run(run) {
-                overlap_runs.push(run_idx);
-            }
-        }
-
-        if overlap_runs.is_empty() {
+        if !ctx.collect_overlapping_runs(curr_level) {
             let run = self.select_compaction_run(curr_level_num, Some(ctx.get_base().file_num));
             ctx.add_run(&run);
-        } else {
-            for (i, run_idx) in overlap_runs.iter().enumerate() {
-                // `remove` will shift forward runs after the deleted runs, and hence we need to
-                // shift the run_idx as well.
-                let run_idx = run_idx - i;
-                let run = curr_level.runs.remove(run_idx);
-                ctx.add_run(&run);
-            }
+            // the selected run may overlap other runs.
+            let curr_level = self.levels.get_mut(curr_level_num).unwrap();
+            ctx.collect_overlapping_runs(curr_level);
         }
 
-        let merged_run = self.merge(&mut ctx.iters());
+        let merged_run = match ctx
+            .iters()
+            .and_then(|mut iters| self.merge(&mut iters, curr_level_num))
+        {
+            Ok(merged_run) => merged_run,
+            Err(err) => {
+                let curr_level = self.levels.get_mut(curr_level_num).unwrap();
+                curr_level.runs = runs;
+                curr_level.update_key_range();
+                return Err(err);
+            }
+        };
         println!(
             "merged run. Min = {}  Max = {}",
             merged_run.min_table_key.clone().unwrap(),
@@ -693,6 +1232,7 @@ impl Db {
             .add_run(merged_run);
 
         self.remove_obsolete_sstables(&ctx);
+        Ok(())
     }
 
     fn remove_obsolete_sstables(&mut self, ctx: &CompactionContext) {
@@ -727,16 +1267,24 @@ impl Db {
         // delete obsolete sstable files.
         for file_num in obsolete_file_nums.iter() {
             remove_file(sstable_file_name(*file_num)).unwrap();
+            self.block_cache.evict_file(*file_num);
+            self.table_cache.evict(*file_num);
         }
     }
 }
 
 impl Db {
     pub fn stats(&self) -> String {
-        let mut stats = String::new();
+        let mut stats = format!(
+            "memtable: size = {} bytes  memory usage = {} bytes\n",
+            self.mem.size(),
+            self.mem.memory_usage()
+        );
         for level in self.levels.iter() {
             stats += &format!("level {}\n{}", level.level_num, level.stats(1))
         }
+        stats += &format!("block cache: {} bytes\n", self.block_cache.stats());
+        stats += &format!("table cache: {} open files\n", self.table_cache.stats());
         stats
     }
 }
@@ -775,11 +1323,22 @@ impl Db {
             self.apply_manifest(manifest);
         }
 
-        // restore all memtable keys.
+        // restore all memtable keys, including those of the immutable memtable not flushed before
+        // the restart.
         let table_keys = LogReader::read_all();
+        // a crash in the middle of merging the logs leaves some table keys in both logs.
+        let mut restored_seq_nums = HashSet::new();
         for table_key in table_keys {
+            if !restored_seq_nums.insert(table_key.seq_num) {
+                continue;
+            }
+            // the manifest may be updated before the crash only after the logged write, and hence
+            // the sequence numbers of the restored table keys must not be allocated again.
+            self.next_seq_num = self.next_seq_num.max(table_key.seq_num + 1);
             self.mem.put(table_key);
         }
+        // both memtables are restored into the memtable, and hence their logs are merged.
+        merge_imm_log();
     }
 }
 
@@ -788,18 +1347,19 @@ impl Db {
 mod tests {
     // import all names from the being-tested module.
     use super::*;
+    use crate::storage::keys::MAX_USER_KEY_SIZE;
 
     /// write a sequence of user keys in the range [0, max_user_key), and check all these keys are
     /// inserted successfully.
     fn check_sequential_keys(db: &mut Db, num_table_keys: usize) {
         for user_key in 0..num_table_keys {
-            db.put(user_key as i32, 0);
+            db.put(&user_key.to_be_bytes(), b"0").unwrap();
         }
 
         assert_eq!(num_table_keys, db.next_seq_num);
 
         for user_key in 0..num_table_keys {
-            assert_eq!(db.get(user_key as i32).unwrap(), 0);
+            assert_eq!(db.get(&user_key.to_be_bytes()).unwrap(), b"0");
         }
     }
 
@@ -818,13 +1378,78 @@ mod tests {
         check_sequential_keys(&mut db, 1000);
     }
 
+    #[test]
+    fn block_cache() {
+        let mut db = Db::new(Config::test());
+        for i in 0..1000_i32 {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        // the key is read from the immutable memtable until its flush is installed.
+        db.wait_for_flush();
+
+        // the first lookup of a key in sstables misses the block cache.
+        assert_eq!(
+            db.get(&0_i32.to_be_bytes()),
+            Some(0_i32.to_be_bytes().to_vec())
+        );
+        let stats = db.block_cache_stats();
+        assert!(stats.misses > 0);
+        assert!(stats.usage > 0 && stats.usage <= stats.capacity);
+
+        // the following lookups of the key hit the block cache.
+        for _ in 0..10 {
+            assert_eq!(
+                db.get(&0_i32.to_be_bytes()),
+                Some(0_i32.to_be_bytes().to_vec())
+            );
+        }
+        let new_stats = db.block_cache_stats();
+        assert_eq!(new_stats.misses, stats.misses);
+        assert!(new_stats.hits >= stats.hits + 10);
+    }
+
+    #[test]
+    fn table_cache() {
+        let mut cfg = Config::test();
+        cfg.set_max_open_files(4);
+        let mut db = Db::new(cfg);
+        for i in 0..5000_i32 {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+
+        // repeated lookups reuse the opened sstables and at most max_open_files sstables are open.
+        for _ in 0..2 {
+            for i in 0..5000_i32 {
+                assert_eq!(db.get(&i.to_be_bytes()), Some(i.to_be_bytes().to_vec()));
+            }
+        }
+        let stats = db.table_cache_stats();
+        assert!(stats.hits > 0 && stats.misses > 0);
+        assert!(stats.usage > 0 && stats.usage <= 4);
+
+        // the sstables deleted by compactions are evicted and the remaining ones stay readable.
+        for i in 5000..10000_i32 {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        assert!(db.table_cache_stats().usage <= 4);
+        for i in 0..10000_i32 {
+            assert_eq!(db.get(&i.to_be_bytes()), Some(i.to_be_bytes().to_vec()));
+        }
+    }
+
     #[test]
     fn merge() {
         let mut db = Db::new(Config::test());
 
         // write [0, 962], [5778, 6740] to an sstable.
-        let mut sstable_writer_batch =
-            SSTableWriterBatch::new(db.next_file_num, db.cfg.sstable_size_capacity);
+        let mut sstable_writer_batch = SSTableWriterBatch::new(
+            db.next_file_num,
+            db.cfg.sstable_size_capacity,
+            db.cfg.block_size,
+            db.cfg.compression_type(0),
+            db.cfg.bits_per_key,
+            db.cfg.filter_type,
+        );
         let num_table_keys = 963;
         for i in 0..num_table_keys {
             let table_key = TableKey::identity(i);
@@ -839,8 +1464,14 @@ mod tests {
         let a = sstables.first().unwrap();
 
         // write [963, 1925] to an sstable.
-        sstable_writer_batch =
-            SSTableWriterBatch::new(db.next_file_num, db.cfg.sstable_size_capacity);
+        sstable_writer_batch = SSTableWriterBatch::new(
+            db.next_file_num,
+            db.cfg.sstable_size_capacity,
+            db.cfg.block_size,
+            db.cfg.compression_type(0),
+            db.cfg.bits_per_key,
+            db.cfg.filter_type,
+        );
         for i in 0..num_table_keys {
             let table_key = TableKey::identity(i + 963);
             sstable_writer_batch.push(table_key)
@@ -850,8 +1481,14 @@ mod tests {
         let b = sstables.first().unwrap();
 
         // write [2889, 4814] to an sstable.
-        sstable_writer_batch =
-            SSTableWriterBatch::new(db.next_file_num, db.cfg.sstable_size_capacity);
+        sstable_writer_batch = SSTableWriterBatch::new(
+            db.next_file_num,
+            db.cfg.sstable_size_capacity,
+            db.cfg.block_size,
+            db.cfg.compression_type(0),
+            db.cfg.bits_per_key,
+            db.cfg.filter_type,
+        );
         for i in 0..num_table_keys * 2 {
             let table_key = TableKey::identity(i + 2889);
             sstable_writer_batch.push(table_key)
@@ -864,11 +1501,11 @@ mod tests {
         let mut iters: BinaryHeap<TableKeyIteratorType> = BinaryHeap::new();
 
         // init iterators.
-        let mut a_iter = Box::new(a.iter().unwrap());
+        let mut a_iter = Box::new(a.iter(&ReadOptions::default()).unwrap());
         a_iter.next();
-        let mut b_iter = Box::new(b.iter().unwrap());
+        let mut b_iter = Box::new(b.iter(&ReadOptions::default()).unwrap());
         b_iter.next();
-        let mut c_iter = Box::new(c.iter().unwrap());
+        let mut c_iter = Box::new(c.iter(&ReadOptions::default()).unwrap());
         c_iter.next();
 
         iters.push(a_iter);
@@ -877,7 +1514,57 @@ mod tests {
 
         println!("merging...");
         // assertion is done inside `merge`.
-        db.merge(&mut iters);
+        db.merge(&mut iters, 1).unwrap();
+    }
+
+    /// a compaction reading a corrupted sstable fails, leaving the levels unchanged and deleting the
+    /// sstables it has written.
+    #[test]
+    fn compaction_failure() {
+        let mut db = Db::new(Config::test());
+        let mut i = 0_i32;
+        while db.levels[0].runs.len() < 2 {
+            db.put(&(i % 1000).to_be_bytes(), &i.to_be_bytes()).unwrap();
+            i += 1;
+            db.wait_for_flush();
+        }
+        let file_nums = |db: &Db| -> Vec<Vec<Vec<FileNum>>> {
+            db.levels
+                .iter()
+                .map(|level| {
+                    level
+                        .runs
+                        .iter()
+                        .map(|run| {
+                            run.sstables
+                                .iter()
+                                .map(|sstable| sstable.file_num)
+                                .collect()
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let expected_file_nums = file_nums(&db);
+
+        // corrupt a data block of an sstable other than the first one, so that the compaction fails
+        // after some table keys are written.
+        let file_name = sstable_file_name(db.levels[0].runs[0].sstables[0].file_num);
+        let mut bytes = std::fs::read(&file_name).unwrap();
+        let offset = bytes.len() / 4;
+        bytes[offset] ^= 1;
+        std::fs::write(&file_name, bytes).unwrap();
+
+        let next_file_num = db.next_file_num;
+        assert!(matches!(
+            db.major_compaction(0),
+            Err(Error::Corruption { .. })
+        ));
+        assert_eq!(file_nums(&db), expected_file_nums);
+        assert!(db.next_file_num > next_file_num);
+        for file_num in next_file_num..db.next_file_num {
+            assert!(!std::path::Path::new(&sstable_file_name(file_num)).exists());
+        }
     }
 
     /// configures the #writes such that a major compaction is triggered.
@@ -889,19 +1576,87 @@ mod tests {
         check_sequential_keys(&mut db, 10000);
     }
 
+    #[test]
+    fn range_seeks_start_key() {
+        let mut db = Db::new(Config::test());
+        let num_table_keys: i32 = 20000;
+        for i in 0..num_table_keys {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+
+        // a short range reads a few data blocks per run instead of all data blocks before the range.
+        let stats = db.block_cache_stats();
+        let entries = db.range(&15000_i32.to_be_bytes(), &15010_i32.to_be_bytes());
+        assert_eq!(entries.len(), 10);
+        for (entry, i) in entries.iter().zip(15000_i32..) {
+            assert_eq!(entry.key, i.to_be_bytes());
+        }
+        let new_stats = db.block_cache_stats();
+        let num_block_reads = new_stats.hits + new_stats.misses - stats.hits - stats.misses;
+        let num_runs: usize = db.levels.iter().map(|level| level.runs.len()).sum();
+        assert!(num_block_reads <= 2 * num_runs);
+    }
+
+    #[test]
+    fn range_prunes_sstables() {
+        let mut db = Db::new(Config::test());
+        let num_table_keys: i32 = 20000;
+        for i in 0..num_table_keys {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+
+        // only the sstables intersecting the range are opened.
+        let start_user_key = 15000_i32.to_be_bytes();
+        let end_user_key = 15010_i32.to_be_bytes();
+        let mut num_overlapping_sstables = 0;
+        for level in db.levels.iter() {
+            for run in level.runs.iter() {
+                num_overlapping_sstables += run
+                    .sstables
+                    .iter()
+                    .filter(|sstable| {
+                        sstable.min_table_key.user_key.as_slice() < end_user_key.as_slice()
+                            && sstable.max_table_key.user_key.as_slice()
+                                >= start_user_key.as_slice()
+                    })
+                    .count();
+            }
+        }
+        let num_sstables: usize = db
+            .levels
+            .iter()
+            .flat_map(|level| level.runs.iter())
+            .map(|run| run.sstables.len())
+            .sum();
+        assert!(num_overlapping_sstables < num_sstables);
+
+        let stats = db.table_cache_stats();
+        assert_eq!(db.range(&start_user_key, &end_user_key).len(), 10);
+        let new_stats = db.table_cache_stats();
+        let num_opens = new_stats.hits + new_stats.misses - stats.hits - stats.misses;
+        assert_eq!(num_opens, num_overlapping_sstables);
+
+        // a range disjoint from all keys opens no sstable.
+        let stats = db.table_cache_stats();
+        assert!(db
+            .range(&num_table_keys.to_be_bytes(), &i32::MAX.to_be_bytes())
+            .is_empty());
+        assert_eq!(db.table_cache_stats(), stats);
+    }
+
     #[test]
     fn mem_only_range_no_delete() {
         let mut db = Db::new(Config::test());
-        let num_table_keys = 100;
+        let num_table_keys: i32 = 100;
         for i in 0..num_table_keys {
-            db.put(i, i);
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
         }
 
-        let entries = db.range(0, num_table_keys);
+        let entries = db.range(&0i32.to_be_bytes(), &num_table_keys.to_be_bytes());
         assert_eq!(entries.len(), num_table_keys as usize);
 
         for i in 0..num_table_keys {
-            assert_eq!(entries[i as usize].val, i);
+            assert_eq!(entries[i as usize].val, i.to_be_bytes());
         }
     }
 
@@ -913,7 +1668,7 @@ mod tests {
     fn range_with_delete(num_table_keys: i32) {
         let mut db = Db::new(Config::test());
         for i in 0..num_table_keys {
-            db.put(i, i);
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
         }
 
         let max_num_deletes = 200;
@@ -926,7 +1681,7 @@ mod tests {
         while deleted_keys.len() < max_num_deletes {
             let i = rng.gen_range(0..num_table_keys);
             if !deleted_keys.contains(&i) {
-                db.delete(i);
+                db.delete(&i.to_be_bytes()).unwrap();
                 deleted_keys.insert(i);
             }
         }
@@ -934,25 +1689,19 @@ mod tests {
         let seq_num = num_table_keys as usize + deleted_keys.len();
         assert_eq!(seq_num, db.next_seq_num);
 
-        let entries = db.range(0, num_table_keys);
+        let entries = db.range(&0i32.to_be_bytes(), &num_table_keys.to_be_bytes());
         assert_eq!(entries.len(), num_table_keys as usize - deleted_keys.len());
 
         for i in 0..num_table_keys {
-            let mut exist = false;
-            let mut val = None;
-            for entry in entries.iter() {
-                if entry.key == i {
-                    exist = true;
-                    val = Some(entry.val);
-                    break;
-                }
-            }
+            let val = entries
+                .iter()
+                .find(|entry| entry.key == i.to_be_bytes())
+                .map(|entry| entry.val.clone());
 
             if deleted_keys.contains(&i) {
-                assert_eq!(exist, false);
+                assert!(val.is_none());
             } else {
-                assert_eq!(exist, true);
-                assert_eq!(val.unwrap(), i);
+                assert_eq!(val.unwrap(), i.to_be_bytes());
             }
         }
     }
@@ -981,9 +1730,9 @@ mod tests {
     #[test]
     fn compaction() {
         let mut db = Db::new(Config::test());
-        let num_puts = 10000;
+        let num_puts: i32 = 10000;
         for i in 0..num_puts {
-            db.put(i, i);
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
         }
 
         let max_num_deletes = 2000;
@@ -992,7 +1741,7 @@ mod tests {
         for _ in 0..max_num_deletes {
             let i = rng.gen_range(0..num_puts);
             if !deleted_keys.contains(&i) {
-                db.delete(i);
+                db.delete(&i.to_be_bytes()).unwrap();
                 deleted_keys.insert(i);
             }
         }
@@ -1003,14 +1752,15 @@ mod tests {
             let i = rng.gen_range(0..num_puts);
             // do not update keys that were deleted.
             if !deleted_keys.contains(&i) && !updated_keys.contains(&i) {
-                db.put(i, i + num_puts);
+                db.put(&i.to_be_bytes(), &(i + num_puts).to_be_bytes())
+                    .unwrap();
                 updated_keys.insert(i);
             }
         }
 
         let num_puts_2 = 2000;
         for i in num_puts..num_puts + num_puts_2 {
-            db.put(i, i);
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
         }
 
         let seq_num =
@@ -1027,14 +1777,437 @@ mod tests {
                 println!("key {} is not changed", i);
             }
 
-            let val = db.get(i);
+            let val = db.get(&i.to_be_bytes());
             if deleted_keys.contains(&i) {
                 assert!(val.is_none());
             } else if updated_keys.contains(&i) {
-                assert_eq!(val.unwrap(), i + num_puts);
+                assert_eq!(val.unwrap(), (i + num_puts).to_be_bytes());
+            } else {
+                assert_eq!(val.unwrap(), i.to_be_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn version_filter() {
+        // live snapshots at 10 and 20 partition the versions into stripes [0, 10], [11, 20] and [21, ...).
+        let snapshots = BTreeMap::from([(10, 1), (20, 2)]);
+        let mut version_filter = VersionFilter::new(&snapshots);
+        let kept: Vec<_> = [
+            (0, 30),
+            (0, 25),
+            (0, 20),
+            (0, 15),
+            (0, 9),
+            (0, 3),
+            (1, 12),
+            (2, 1),
+        ]
+        .into_iter()
+        .filter(|(user_key, seq_num)| {
+            version_filter.keep(&TableKey::new(&[*user_key], *seq_num, WriteType::Put, &[]))
+        })
+        .collect();
+        assert_eq!(kept, vec![(0, 30), (0, 20), (0, 9), (1, 12), (2, 1)]);
+
+        // only the latest version is kept without live snapshots.
+        let snapshots = BTreeMap::new();
+        let mut version_filter = VersionFilter::new(&snapshots);
+        assert!(version_filter.keep(&TableKey::new(&[0], 30, WriteType::Put, &[])));
+        assert!(!version_filter.keep(&TableKey::new(&[0], 9, WriteType::Delete, &[])));
+    }
+
+    /// take snapshots between overwrites and deletes, and check reads with the snapshots see the
+    /// database as of the time the snapshots are taken even after compactions.
+    #[test]
+    fn snapshot() {
+        let mut db = Db::new(Config::test());
+        let num_keys = 3000_i32;
+        for i in 0..num_keys {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        let snapshot_1 = db.snapshot();
+
+        for i in 0..num_keys {
+            if i % 3 == 0 {
+                db.delete(&i.to_be_bytes()).unwrap();
+            } else {
+                db.put(&i.to_be_bytes(), &(i + num_keys).to_be_bytes())
+                    .unwrap();
+            }
+        }
+        let snapshot_2 = db.snapshot();
+
+        // overwrite all keys many times to push former versions through a set of compactions.
+        for round in 2..6 {
+            for i in 0..num_keys {
+                db.put(&i.to_be_bytes(), &(i + round * num_keys).to_be_bytes())
+                    .unwrap();
+            }
+        }
+
+        let expected_1 = |i: i32| Some(i.to_be_bytes().to_vec());
+        let expected_2 = |i: i32| (i % 3 != 0).then(|| (i + num_keys).to_be_bytes().to_vec());
+        let read_options = |snapshot| ReadOptions {
+            snapshot: Some(snapshot),
+            ..Default::default()
+        };
+        for i in 0..num_keys {
+            let user_key = i.to_be_bytes();
+            let val = db
+                .get_with_options(&read_options(snapshot_1), &user_key)
+                .unwrap();
+            assert_eq!(val, expected_1(i));
+            let val = db
+                .get_with_options(&read_options(snapshot_2), &user_key)
+                .unwrap();
+            assert_eq!(val, expected_2(i));
+            assert_eq!(
+                db.get(&user_key),
+                Some((i + 5 * num_keys).to_be_bytes().to_vec())
+            );
+        }
+
+        // range queries and iterators are pinned to the snapshot as well.
+        let entries = db
+            .range_with_options(
+                &read_options(snapshot_2),
+                &0_i32.to_be_bytes(),
+                &num_keys.to_be_bytes(),
+            )
+            .unwrap();
+        let expected: Vec<_> = (0..num_keys).filter(|i| i % 3 != 0).collect();
+        assert_eq!(entries.len(), expected.len());
+        for (entry, i) in entries.iter().zip(expected) {
+            assert_eq!(entry.key, i.to_be_bytes());
+            assert_eq!(Some(entry.val.clone()), expected_2(i));
+        }
+        let mut iter = db.iter(&read_options(snapshot_1));
+        iter.seek_to_first();
+        for i in 0..num_keys {
+            assert_eq!(iter.key(), i.to_be_bytes());
+            assert_eq!(iter.value(), i.to_be_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+        drop(iter);
+
+        // a released snapshot does not pin its versions, while other snapshots are still intact.
+        db.release_snapshot(snapshot_1);
+        for i in 0..num_keys {
+            db.put(&i.to_be_bytes(), &(i + 6 * num_keys).to_be_bytes())
+                .unwrap();
+        }
+        for i in 0..num_keys {
+            let user_key = i.to_be_bytes();
+            let val = db
+                .get_with_options(&read_options(snapshot_2), &user_key)
+                .unwrap();
+            assert_eq!(val, expected_2(i));
+        }
+        db.release_snapshot(snapshot_2);
+        assert!(db.snapshots.is_empty());
+        // releasing a snapshot again is a no-op.
+        db.release_snapshot(snapshot_2);
+        assert!(db.snapshots.is_empty());
+    }
+
+    /// check a batch of unsorted keys, including missing, deleted and duplicate keys, are resolved
+    /// the same as point queries, and each sstable is opened at most once per batch.
+    #[test]
+    fn multi_get() {
+        let mut db = Db::new(Config::test());
+        let mut rng = rand::thread_rng();
+        for i in 0..10000_i32 {
+            let user_key = rng.gen_range(0..4000_i32).to_be_bytes();
+            if rng.gen_range(0..4) == 0 {
+                db.delete(&user_key).unwrap();
             } else {
-                assert_eq!(val.unwrap(), i);
+                db.put(&user_key, &i.to_be_bytes()).unwrap();
+            }
+        }
+
+        let user_keys: Vec<UserKey> = (0..500)
+            .map(|_| rng.gen_range(0..5000_i32).to_be_bytes().to_vec())
+            .collect();
+        let num_sstables: usize = db
+            .levels
+            .iter()
+            .flat_map(|level| level.runs.iter())
+            .map(|run| run.sstables.len())
+            .sum();
+        let stats = db.table_cache_stats();
+        let user_vals = db.multi_get(&user_keys);
+        let new_stats = db.table_cache_stats();
+        assert!(new_stats.hits + new_stats.misses - stats.hits - stats.misses <= num_sstables);
+
+        assert_eq!(user_vals.len(), user_keys.len());
+        for (user_key, user_val) in user_keys.iter().zip(user_vals) {
+            assert_eq!(user_val, db.get(user_key));
+        }
+        assert!(db.multi_get(&[]).is_empty());
+    }
+
+    /// check aggregates of random ranges against a btree map, where some values are not integers.
+    #[test]
+    fn aggregate() {
+        let mut db = Db::new(Config::test());
+        let mut expected = BTreeMap::new();
+        let mut rng = rand::thread_rng();
+        for _ in 0..10000 {
+            let user_key = rng.gen_range(0..3000_i32).to_be_bytes();
+            match rng.gen_range(0..8) {
+                0 => {
+                    db.delete(&user_key).unwrap();
+                    expected.remove(&user_key);
+                }
+                1 => {
+                    db.put(&user_key, b"NaN").unwrap();
+                    expected.insert(user_key, None);
+                }
+                _ => {
+                    let user_val = rng.gen_range(-1000..1000_i64);
+                    db.put(&user_key, user_val.to_string().as_bytes()).unwrap();
+                    expected.insert(user_key, Some(user_val));
+                }
+            }
+        }
+
+        for _ in 0..20 {
+            let start = rng.gen_range(0..3000_i32);
+            let end = rng.gen_range(start..3100_i32);
+            let (start_user_key, end_user_key) = (start.to_be_bytes(), end.to_be_bytes());
+            let entries: Vec<_> = expected.range(start_user_key..end_user_key).collect();
+            let vals = entries.iter().filter_map(|(_, user_val)| **user_val);
+
+            let aggregate = |db: &mut Db, op| db.aggregate(op, &start_user_key, &end_user_key);
+            assert_eq!(
+                aggregate(&mut db, AggregateOp::Count),
+                Some(entries.len() as i64)
+            );
+            assert_eq!(
+                aggregate(&mut db, AggregateOp::Sum),
+                Some(vals.clone().sum())
+            );
+            assert_eq!(aggregate(&mut db, AggregateOp::Min), vals.clone().min());
+            assert_eq!(aggregate(&mut db, AggregateOp::Max), vals.max());
+        }
+
+        // an empty range has no min or max.
+        let user_key = 0_i32.to_be_bytes();
+        assert_eq!(
+            db.aggregate(AggregateOp::Count, &user_key, &user_key),
+            Some(0)
+        );
+        assert_eq!(db.aggregate(AggregateOp::Max, &user_key, &user_key), None);
+    }
+
+    /// check writes go to a new memtable while the full one is flushed in the background, and reads
+    /// see the keys in both memtables.
+    #[test]
+    fn immutable_memtable() {
+        let mut db = Db::new(Config::test());
+        let mut num_table_keys = 0_i32;
+        while db.imm.is_none() {
+            db.put(&num_table_keys.to_be_bytes(), &num_table_keys.to_be_bytes())
+                .unwrap();
+            num_table_keys += 1;
+        }
+
+        // overwrite and delete some keys of the immutable memtable in the new memtable.
+        for i in 0..10_i32 {
+            db.delete(&i.to_be_bytes()).unwrap();
+            db.put(&(i + 10).to_be_bytes(), b"new").unwrap();
+        }
+        let check = |db: &mut Db| {
+            for i in 0..num_table_keys {
+                let expected = match i {
+                    0..=9 => None,
+                    10..=19 => Some(b"new".to_vec()),
+                    _ => Some(i.to_be_bytes().to_vec()),
+                };
+                assert_eq!(db.get(&i.to_be_bytes()), expected);
+            }
+            let entries = db.range(&0_i32.to_be_bytes(), &num_table_keys.to_be_bytes());
+            assert_eq!(entries.len(), num_table_keys as usize - 10);
+            assert_eq!(entries[0].key, 10_i32.to_be_bytes());
+            assert_eq!(entries[0].val, b"new");
+        };
+        check(&mut db);
+
+        // the flushed sstables become the newest run of the level 0.
+        db.wait_for_flush();
+        assert!(db.imm.is_none());
+        assert_eq!(db.levels[0].runs.len(), 1);
+        check(&mut db);
+    }
+
+    #[test]
+    fn write_batch() {
+        let mut db = Db::new(Config::test());
+        db.put(&0_i32.to_be_bytes(), b"old").unwrap();
+        let snapshot = db.snapshot();
+
+        // later writes of a user key in the batch override earlier ones.
+        let mut batch = WriteBatch::new();
+        for i in 0..100_i32 {
+            batch.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        batch.delete(&1_i32.to_be_bytes()).unwrap();
+        batch.put(&2_i32.to_be_bytes(), b"new").unwrap();
+        // a too large user key is rejected without changing the batch or the database.
+        let large_user_key = vec![0; MAX_USER_KEY_SIZE + 1];
+        assert!(matches!(
+            batch.put(&large_user_key, b"large"),
+            Err(Error::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            db.delete(&large_user_key),
+            Err(Error::InvalidArgument { .. })
+        ));
+        let next_seq_num = db.next_seq_num;
+        db.write_batch(&batch);
+        // the writes are assigned consecutive sequence numbers.
+        assert_eq!(db.next_seq_num, next_seq_num + batch.len());
+
+        for i in 0..100_i32 {
+            let expected = match i {
+                1 => None,
+                2 => Some(b"new".to_vec()),
+                _ => Some(i.to_be_bytes().to_vec()),
+            };
+            assert_eq!(db.get(&i.to_be_bytes()), expected);
+        }
+
+        // no write of the batch is visible to the snapshot taken before the batch.
+        let read_options = ReadOptions {
+            snapshot: Some(snapshot),
+            ..Default::default()
+        };
+        assert_eq!(
+            db.get_with_options(&read_options, &0_i32.to_be_bytes())
+                .unwrap(),
+            Some(b"old".to_vec())
+        );
+        assert_eq!(
+            db.range_with_options(&read_options, &[], &[u8::MAX])
+                .unwrap()
+                .len(),
+            1
+        );
+        db.release_snapshot(snapshot);
+
+        // a batch larger than the memtable capacity is not split across memtables.
+        let mut batch = WriteBatch::new();
+        for i in 100..3000_i32 {
+            batch.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        db.write_batch(&batch);
+        assert!(db.imm.is_some());
+        assert_eq!(db.mem.size(), 0);
+        db.wait_for_flush();
+        for i in 100..3000_i32 {
+            assert_eq!(db.get(&i.to_be_bytes()), Some(i.to_be_bytes().to_vec()));
+        }
+
+        // an empty batch is a no-op.
+        let next_seq_num = db.next_seq_num;
+        db.write_batch(&WriteBatch::new());
+        assert_eq!(db.next_seq_num, next_seq_num);
+    }
+
+    /// write and read through each memtable rep. the flushed sstables are only readable if each rep
+    /// emits sorted table keys to minor compactions.
+    #[test]
+    fn memtable_reps() {
+        for rep_type in [
+            MemTableRepType::SkipList,
+            MemTableRepType::Tree,
+            MemTableRepType::Vector,
+            MemTableRepType::Hash,
+        ] {
+            let mut cfg = Config::test();
+            cfg.set_memtable_rep_type(rep_type);
+            let mut db = Db::new(cfg);
+            let mut expected = BTreeMap::new();
+            let mut rng = rand::thread_rng();
+            for i in 0..5000_i32 {
+                let user_key = rng.gen_range(0..2000_i32).to_be_bytes();
+                if rng.gen_range(0..4) == 0 {
+                    db.delete(&user_key).unwrap();
+                    expected.remove(&user_key);
+                } else {
+                    db.put(&user_key, &i.to_be_bytes()).unwrap();
+                    expected.insert(user_key, i.to_be_bytes());
+                }
+            }
+
+            for i in 0..2000_i32 {
+                let user_key = i.to_be_bytes();
+                assert_eq!(
+                    db.get(&user_key),
+                    expected.get(&user_key).map(|val| val.to_vec()),
+                    "{:?}",
+                    rep_type
+                );
+            }
+            let entries = db.range(&0_i32.to_be_bytes(), &2000_i32.to_be_bytes());
+            assert_eq!(entries.len(), expected.len());
+            for (entry, (user_key, user_val)) in entries.iter().zip(expected.iter()) {
+                assert_eq!(entry.key, user_key);
+                assert_eq!(entry.val, user_val);
+            }
+        }
+    }
+
+    /// user values larger than the block size, the memtable capacity and the sstable capacity are
+    /// written, flushed, compacted and read back.
+    #[test]
+    fn large_values() {
+        let mut db = Db::new(Config::test());
+        let user_val = |i: i32| vec![i as u8; 1000 << (i % 8)];
+        for i in 0..64_i32 {
+            db.put(&i.to_be_bytes(), &user_val(i)).unwrap();
+        }
+        db.wait_for_flush();
+        for i in 0..64_i32 {
+            assert_eq!(db.get(&i.to_be_bytes()), Some(user_val(i)));
+        }
+        let entries = db.range(&0_i32.to_be_bytes(), &64_i32.to_be_bytes());
+        assert_eq!(entries.len(), 64);
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.val, user_val(i as i32));
+        }
+    }
+
+    /// reopen the database after a crash which leaves the table keys of the immutable memtable in
+    /// both the merged log and the log of the immutable memtable.
+    #[test]
+    fn restart_with_leftover_imm_log() {
+        let _ = remove_file(MANIFEST_FILE_PATH);
+        let _ = remove_file(LOG_FILE_PATH);
+        let _ = remove_file(IMM_LOG_FILE_PATH);
+
+        let mut cfg = Config::test();
+        cfg.set_recovery(true);
+        let mut db = Db::new(cfg);
+        for i in 0..100_i32 {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        drop(db);
+        // the state right after the merged log replaces the log and before the immutable log is
+        // removed.
+        std::fs::copy(LOG_FILE_PATH, IMM_LOG_FILE_PATH).unwrap();
+
+        for _ in 0..2 {
+            let mut cfg = Config::test();
+            cfg.set_recovery(true);
+            let mut db = Db::new(cfg);
+            assert!(!std::path::Path::new(IMM_LOG_FILE_PATH).exists());
+            for i in 0..100_i32 {
+                assert_eq!(db.get(&i.to_be_bytes()), Some(i.to_be_bytes().to_vec()));
             }
+            assert_eq!(db.next_seq_num, 100);
         }
     }
 }
@@ -0,0 +1,304 @@
+use crate::storage::iterator::{MergingIterator, TableKeyIterator, TableKeyIteratorType};
+use crate::storage::keys::LookupKey;
+use crate::util::error::Error;
+use crate::util::types::*;
+
+/// a cursor over the user entries of the database.
+/// the memtable and the levels are merged lazily by a merging iterator: for each user key, only
+/// the latest version visible to the iterator is emitted and deleted keys are hidden.
+pub struct DbIterator<'a> {
+    /// the merging iterator of the memtable and levels.
+    /// on forward iterations, it points to the table key of the current user entry.
+    /// on reverse iterations, it points to the table key right before all versions of the user key
+    /// of the current user entry.
+    iter: MergingIterator<'a>,
+    /// true if the iterator moves forward.
+    forward: bool,
+    /// table keys with higher sequence numbers are invisible to the iterator.
+    snapshot_seq_num: SeqNum,
+    /// currently pointed-to user entry.
+    curr: Option<UserEntry>,
+    /// the error encountered by any iterator if any.
+    status: Result<(), Error>,
+}
+
+impl<'a> DbIterator<'a> {
+    pub(crate) fn new(iters: Vec<TableKeyIteratorType<'a>>, snapshot_seq_num: SeqNum) -> Self {
+        Self {
+            iter: MergingIterator::new(iters),
+            forward: true,
+            snapshot_seq_num,
+            curr: None,
+            status: Ok(()),
+        }
+    }
+
+    /// move the cursor to the first user entry whose user key is greater than or equal to the user key.
+    pub fn seek(&mut self, user_key: &[u8]) {
+        // the versions of the user key invisible to the iterator are skipped by the seek.
+        self.iter
+            .seek(&LookupKey::new(user_key, self.snapshot_seq_num));
+        self.find_next_entry(None);
+    }
+
+    /// move the cursor to the first user entry in the database.
+    pub fn seek_to_first(&mut self) {
+        // the empty user key is the min user key.
+        self.seek(&[]);
+    }
+
+    /// move the cursor to the last user entry whose user key is less than or equal to the user key.
+    pub fn seek_for_prev(&mut self, user_key: &[u8]) {
+        // the version with the sequence number 0 is the last version of the user key.
+        self.iter.seek_for_prev(&LookupKey::new(user_key, 0));
+        self.find_prev_entry();
+    }
+
+    /// move the cursor to the last user entry in the database.
+    pub fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+        self.find_prev_entry();
+    }
+
+    /// move the cursor to the next user entry.
+    pub fn next(&mut self) {
+        let Some(curr) = self.curr.take() else {
+            return;
+        };
+        if self.forward {
+            self.iter.next();
+        } else {
+            // switch the direction: move to the latest visible version of the current user key.
+            self.iter
+                .seek(&LookupKey::new(&curr.key, self.snapshot_seq_num));
+        }
+        self.find_next_entry(Some(curr.key));
+    }
+
+    /// move the cursor to the previous user entry.
+    pub fn prev(&mut self) {
+        let Some(curr) = self.curr.take() else {
+            return;
+        };
+        if self.forward {
+            // switch the direction: move to the table key right before all versions of the current
+            // user key, including the invisible ones.
+            self.iter.seek(&LookupKey::new(&curr.key, SeqNum::MAX));
+            if self.iter.valid() {
+                self.iter.prev();
+            } else if self.iter.status().is_ok() {
+                self.iter.seek_to_last();
+            }
+        }
+        self.find_prev_entry();
+    }
+
+    /// return true if the cursor points to a user entry.
+    /// an invalid iterator is either exhausted or has encountered an error.
+    pub fn valid(&self) -> bool {
+        self.curr.is_some()
+    }
+
+    /// return the user key of the current user entry.
+    /// panics if the iterator is invalid.
+    pub fn key(&self) -> &[u8] {
+        &self.curr.as_ref().unwrap().key
+    }
+
+    /// return the user value of the current user entry.
+    /// panics if the iterator is invalid.
+    pub fn value(&self) -> &[u8] {
+        &self.curr.as_ref().unwrap().val
+    }
+
+    /// return the error encountered by the iterator if any.
+    pub fn status(&self) -> Result<(), Error> {
+        self.status.clone()
+    }
+
+    /// find the next visible and non-deleted user entry whose user key differs from the skipped one.
+    fn find_next_entry(&mut self, mut skipped_user_key: Option<UserKey>) {
+        self.forward = true;
+        self.curr = None;
+        while let Some(table_key) = self.iter.curr() {
+            // only the latest visible version of each user key is considered.
+            if table_key.seq_num <= self.snapshot_seq_num
+                && skipped_user_key.as_ref() != Some(&table_key.user_key)
+            {
+                if let WriteType::Put = table_key.write_type {
+                    self.curr = Some(UserEntry {
+                        key: table_key.user_key,
+                        val: table_key.user_val,
+                    });
+                    break;
+                }
+                // deleted keys are hidden.
+                skipped_user_key = Some(table_key.user_key);
+            }
+            self.iter.next();
+        }
+        self.status = self.iter.status();
+    }
+
+    /// find the previous visible and non-deleted user entry.
+    // versions of a user key are visited from the oldest to the latest on reverse iterations, so
+    // the latest visible version is known only after a table key of a less user key is visited.
+    fn find_prev_entry(&mut self) {
+        self.forward = false;
+        self.curr = None;
+        // the latest visible version of the user key visited so far, where a deleted key has no value.
+        let mut saved: Option<(UserKey, Option<UserValue>)> = None;
+        while let Some(table_key) = self.iter.curr() {
+            if table_key.seq_num <= self.snapshot_seq_num {
+                if let Some((user_key, Some(_))) = saved.as_ref() {
+                    if table_key.user_key < *user_key {
+                        break;
+                    }
+                }
+                let user_val = match table_key.write_type {
+                    WriteType::Put => Some(table_key.user_val),
+                    _ => None,
+                };
+                saved = Some((table_key.user_key, user_val));
+            }
+            self.iter.prev();
+        }
+        self.status = self.iter.status();
+
+        if let Some((key, Some(val))) = saved {
+            if self.status.is_ok() {
+                self.curr = Some(UserEntry { key, val });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::db::{Config, Db};
+    use crate::util::types::ReadOptions;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+
+    /// put, update and delete random keys, and check the iterator emits the same entries as a btree map.
+    #[test]
+    fn iterate_all() {
+        let mut db = Db::new(Config::test());
+        let mut expected = BTreeMap::new();
+        let mut rng = rand::thread_rng();
+        for i in 0..10000_i32 {
+            let user_key = rng.gen_range(0..3000_i32).to_be_bytes();
+            if rng.gen_range(0..4) == 0 {
+                db.delete(&user_key).unwrap();
+                expected.remove(&user_key);
+            } else {
+                db.put(&user_key, &i.to_be_bytes()).unwrap();
+                expected.insert(user_key, i.to_be_bytes());
+            }
+        }
+
+        let mut iter = db.iter(&ReadOptions::default());
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        for (user_key, user_val) in expected.iter() {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), user_key);
+            assert_eq!(iter.value(), user_val);
+            iter.next();
+        }
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn seek() {
+        let mut db = Db::new(Config::test());
+        for i in (0..5000_i32).step_by(2) {
+            db.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        for i in (0..5000_i32).step_by(10) {
+            db.delete(&i.to_be_bytes()).unwrap();
+        }
+
+        let mut iter = db.iter(&ReadOptions::default());
+
+        // a missing key is seeked to the next existing key, and deleted keys are skipped.
+        iter.seek(&1001_i32.to_be_bytes());
+        for i in [1002_i32, 1004, 1006, 1008, 1012] {
+            assert_eq!(iter.key(), i.to_be_bytes());
+            assert_eq!(iter.value(), i.to_be_bytes());
+            iter.next();
+        }
+
+        // a seek may move backwards.
+        iter.seek(&10_i32.to_be_bytes());
+        assert_eq!(iter.key(), 12_i32.to_be_bytes());
+
+        // seeking beyond the last key exhausts the iterator, and the iterator could be re-seeked.
+        iter.seek(&5000_i32.to_be_bytes());
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        assert_eq!(iter.key(), 2_i32.to_be_bytes());
+        assert!(iter.status().is_ok());
+    }
+
+    /// iterate backwards over random puts and deletes, and switch the direction in the middle of scans.
+    #[test]
+    fn reverse() {
+        let mut db = Db::new(Config::test());
+        let mut expected = BTreeMap::new();
+        let mut rng = rand::thread_rng();
+        for i in 0..10000_i32 {
+            let user_key = rng.gen_range(0..3000_i32).to_be_bytes();
+            if rng.gen_range(0..4) == 0 {
+                db.delete(&user_key).unwrap();
+                expected.remove(&user_key);
+            } else {
+                db.put(&user_key, &i.to_be_bytes()).unwrap();
+                expected.insert(user_key, i.to_be_bytes());
+            }
+        }
+
+        let mut iter = db.iter(&ReadOptions::default());
+        iter.seek_to_last();
+        for (user_key, user_val) in expected.iter().rev() {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), user_key);
+            assert_eq!(iter.value(), user_val);
+            iter.prev();
+        }
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+
+        // seek the last existing key less than or equal to each key.
+        for i in (0..3100_i32).step_by(37) {
+            let user_key = i.to_be_bytes();
+            iter.seek_for_prev(&user_key);
+            match expected.range(..=user_key).next_back() {
+                Some((expected_key, _)) => assert_eq!(iter.key(), expected_key),
+                None => assert!(!iter.valid()),
+            }
+        }
+
+        // randomly move forwards and backwards from a random key.
+        let keys: Vec<_> = expected.keys().collect();
+        let mut idx = keys.len() / 2;
+        iter.seek(keys[idx]);
+        for _ in 0..2000 {
+            if rng.gen_bool(0.5) {
+                iter.next();
+                idx += 1;
+            } else {
+                iter.prev();
+                idx = idx.wrapping_sub(1);
+            }
+            if idx >= keys.len() {
+                assert!(!iter.valid());
+                idx = keys.len() / 2;
+                iter.seek(keys[idx]);
+            }
+            assert_eq!(iter.key(), keys[idx]);
+        }
+        assert!(iter.status().is_ok());
+    }
+}
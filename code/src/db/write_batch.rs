@@ -0,0 +1,63 @@
+use crate::storage::keys::MAX_USER_KEY_SIZE;
+use crate::util::error::Error;
+use crate::util::types::{UserKey, UserValue, WriteType};
+
+/// a batch of puts and deletes applied atomically by `Db::write_batch`.
+/// the writes are applied in the order they're added, so a later write of a user key in the batch
+/// overrides an earlier one.
+#[derive(Clone, Default)]
+pub struct WriteBatch {
+    writes: Vec<(UserKey, WriteType, UserValue)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, user_key: &[u8], user_val: &[u8]) -> Result<(), Error> {
+        self.add(user_key, WriteType::Put, user_val)
+    }
+
+    pub fn delete(&mut self, user_key: &[u8]) -> Result<(), Error> {
+        self.add(user_key, WriteType::Delete, &[])
+    }
+
+    /// return the number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+
+    /// return the writes in the batch in the order they're added.
+    pub(crate) fn writes(&self) -> impl Iterator<Item = (&[u8], WriteType, &[u8])> {
+        self.writes.iter().map(|(user_key, write_type, user_val)| {
+            (user_key.as_slice(), *write_type, user_val.as_slice())
+        })
+    }
+
+    /// add a write to the batch.
+    /// return an invalid argument error and leave the batch unchanged if the user key is too large.
+    fn add(
+        &mut self,
+        user_key: &[u8],
+        write_type: WriteType,
+        user_val: &[u8],
+    ) -> Result<(), Error> {
+        if user_key.len() > MAX_USER_KEY_SIZE {
+            return Err(Error::InvalidArgument {
+                reason: format!("user key exceeds {} bytes", MAX_USER_KEY_SIZE),
+            });
+        }
+        self.writes
+            .push((user_key.to_vec(), write_type, user_val.to_vec()));
+        Ok(())
+    }
+}
@@ -1,22 +1,38 @@
 // defines the module tree.
 pub mod db {
+    #[allow(clippy::module_inception)]
     pub mod db;
+    pub mod db_iterator;
+    pub mod write_batch;
 }
 mod storage {
+    pub mod arena;
     pub mod block;
+    pub mod block_cache;
     pub mod bloom_filter;
+    pub mod compression;
     pub mod iterator;
     pub mod keys;
     pub mod level;
+    pub mod lru;
     pub mod memtable;
+    pub mod memtable_rep;
     pub mod run;
+    pub mod skiplist;
     pub mod sstable;
+    pub mod table_cache;
+    pub mod table_file;
+    #[cfg(test)]
+    pub mod test_util;
 }
 pub mod util {
+    pub mod crc32c;
+    pub mod error;
     pub mod types;
 }
 pub mod server {
     pub mod cmd;
+    #[allow(clippy::module_inception)]
     pub mod server;
 }
 pub mod logging {
@@ -9,16 +9,12 @@ use crate::util::types::*;
 pub const MANIFEST_FILE_PATH: &str = "manifest";
 
 fn read_min_max_table_keys(reader: &mut &[u8]) -> (TableKey, TableKey) {
-    let mut buf = make_table_key_buf();
-
-    reader.read_exact(&mut buf).unwrap();
-    let min_table_key = TableKey::decode_from_bytes(&buf).unwrap();
-    reader.read_exact(&mut buf).unwrap();
-    let max_table_key = TableKey::decode_from_bytes(&buf).unwrap();
-
+    let min_table_key = TableKey::decode_from_reader(reader).unwrap();
+    let max_table_key = TableKey::decode_from_reader(reader).unwrap();
     (min_table_key, max_table_key)
 }
 
+#[derive(Default)]
 pub struct LevelManifest {
     /// level number.
     pub level_num: LevelNum,
@@ -37,20 +33,6 @@ pub struct LevelManifest {
     pub run_manifests: Vec<RunManifest>,
 }
 
-impl Default for LevelManifest {
-    fn default() -> Self {
-        Self {
-            level_num: LevelNum::default(),
-            run_capacity: usize::default(),
-            size_capacity: usize::default(),
-            num_runs: usize::default(),
-            min_table_key: None,
-            max_table_key: None,
-            run_manifests: Vec::new(),
-        }
-    }
-}
-
 impl LevelManifest {
     fn encode_to_bytes(&self) -> Vec<u8> {
         let mut encoded = Vec::new();
@@ -58,9 +40,11 @@ impl LevelManifest {
         encoded.write_fixedint(self.run_capacity).unwrap();
         encoded.write_fixedint(self.size_capacity).unwrap();
         encoded.write_fixedint(self.num_runs).unwrap();
-        if self.min_table_key.is_some() {
-            encoded.append(&mut self.min_table_key.as_ref().unwrap().encode_to_bytes());
-            encoded.append(&mut self.max_table_key.as_ref().unwrap().encode_to_bytes());
+        if let (Some(min_table_key), Some(max_table_key)) =
+            (self.min_table_key.as_ref(), self.max_table_key.as_ref())
+        {
+            encoded.append(&mut min_table_key.encode_to_bytes());
+            encoded.append(&mut max_table_key.encode_to_bytes());
         }
         for run_manifest in self.run_manifests.iter() {
             encoded.append(&mut run_manifest.encode_to_bytes());
@@ -69,6 +53,7 @@ impl LevelManifest {
     }
 }
 
+#[derive(Default)]
 pub struct RunManifest {
     /// min table key stored in the run.
     pub min_table_key: TableKey,
@@ -81,17 +66,6 @@ pub struct RunManifest {
     pub sstable_manifests: Vec<SSTableManifest>,
 }
 
-impl Default for RunManifest {
-    fn default() -> Self {
-        Self {
-            min_table_key: TableKey::default(),
-            max_table_key: TableKey::default(),
-            num_sstables: usize::default(),
-            sstable_manifests: Vec::new(),
-        }
-    }
-}
-
 impl RunManifest {
     fn encode_to_bytes(&self) -> Vec<u8> {
         let mut encoded = Vec::new();
@@ -105,6 +79,7 @@ impl RunManifest {
     }
 }
 
+#[derive(Default)]
 pub struct SSTableManifest {
     /// sstable file number from which the corresponding sstable file could be located.
     pub file_num: FileNum,
@@ -116,17 +91,6 @@ pub struct SSTableManifest {
     pub max_table_key: TableKey,
 }
 
-impl Default for SSTableManifest {
-    fn default() -> Self {
-        Self {
-            file_num: FileNum::default(),
-            file_size: usize::default(),
-            min_table_key: TableKey::default(),
-            max_table_key: TableKey::default(),
-        }
-    }
-}
-
 impl SSTableManifest {
     fn encode_to_bytes(&self) -> Vec<u8> {
         let mut encoded = Vec::new();
@@ -140,6 +104,7 @@ impl SSTableManifest {
 
 /// database manifest.
 /// each manifest corresponds to one version of the database.
+#[derive(Default)]
 pub struct Manifest {
     /// the next sequence number to allocate for a write.
     pub next_seq_num: SeqNum,
@@ -151,17 +116,6 @@ pub struct Manifest {
     pub level_manifests: Vec<LevelManifest>,
 }
 
-impl Default for Manifest {
-    fn default() -> Self {
-        Self {
-            next_seq_num: SeqNum::default(),
-            next_file_num: FileNum::default(),
-            num_levels: usize::default(),
-            level_manifests: Vec::new(),
-        }
-    }
-}
-
 impl Manifest {
     // replace the old manifest (if any) with the latest manifest.
     pub fn set(manifest: Manifest) {
@@ -176,7 +130,7 @@ impl Manifest {
             .unwrap();
 
         let mut writer = BufWriter::new(file);
-        writer.write(&manifest.encode_to_bytes()).unwrap();
+        writer.write_all(&manifest.encode_to_bytes()).unwrap();
         writer.flush().unwrap();
     }
 
@@ -201,11 +155,13 @@ impl Manifest {
 
             // read level manifests.
             for _ in 0..manifest.num_levels {
-                let mut level_manifest = LevelManifest::default();
-                level_manifest.level_num = reader.read_fixedint().unwrap();
-                level_manifest.run_capacity = reader.read_fixedint().unwrap();
-                level_manifest.size_capacity = reader.read_fixedint().unwrap();
-                level_manifest.num_runs = reader.read_fixedint().unwrap();
+                let mut level_manifest = LevelManifest {
+                    level_num: reader.read_fixedint().unwrap(),
+                    run_capacity: reader.read_fixedint().unwrap(),
+                    size_capacity: reader.read_fixedint().unwrap(),
+                    num_runs: reader.read_fixedint().unwrap(),
+                    ..Default::default()
+                };
 
                 if level_manifest.num_runs > 0 {
                     let (min_table_key, max_table_key) = read_min_max_table_keys(&mut reader);
@@ -222,9 +178,11 @@ impl Manifest {
 
                     // read sstable manifests.
                     for _ in 0..run_manifest.num_sstables {
-                        let mut sstable_manifest = SSTableManifest::default();
-                        sstable_manifest.file_num = reader.read_fixedint().unwrap();
-                        sstable_manifest.file_size = reader.read_fixedint().unwrap();
+                        let mut sstable_manifest = SSTableManifest {
+                            file_num: reader.read_fixedint().unwrap(),
+                            file_size: reader.read_fixedint().unwrap(),
+                            ..Default::default()
+                        };
                         (
                             sstable_manifest.min_table_key,
                             sstable_manifest.max_table_key,
@@ -269,8 +227,8 @@ mod tests {
         SSTable::new(
             i as FileNum,
             i as usize,
-            TableKey::identity(i as i32),
-            TableKey::identity(i as i32),
+            TableKey::identity(i),
+            TableKey::identity(i),
         )
     }
 
@@ -296,7 +254,7 @@ mod tests {
         let run = Run::new(
             sstables,
             TableKey::identity(0),
-            TableKey::identity((num_sstables - 1) as i32),
+            TableKey::identity(num_sstables - 1),
         );
 
         let manifest = run.manifest();
@@ -347,8 +305,8 @@ mod tests {
             assert_eq!(run.sstables.len(), run2.sstables.len());
 
             for i in 0..run.sstables.len() {
-                let sstable = run.sstables.get(i as usize).unwrap();
-                let sstable2 = run2.sstables.get(i as usize).unwrap();
+                let sstable = run.sstables.get(i).unwrap();
+                let sstable2 = run2.sstables.get(i).unwrap();
 
                 assert_eq!(sstable.file_num, sstable2.file_num);
                 assert_eq!(sstable.file_size, sstable2.file_size);
@@ -1,22 +1,35 @@
-use std::fs::{remove_file, File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::{self, remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
 
-use crate::storage::keys::{TableKey, TABLE_KEY_SIZE};
+use crate::storage::keys::TableKey;
+use crate::util::crc32c::crc32c;
+use integer_encoding::*;
 
 pub const LOG_FILE_PATH: &str = "log";
+/// the log of the immutable memtable which is being flushed.
+pub const IMM_LOG_FILE_PATH: &str = "log.imm";
+/// size in bytes of the header of a log record.
+const RECORD_HEADER_SIZE: usize = 4 + 4;
 
 pub struct LogWriter {
     /// file writer.
     writer: BufWriter<File>,
 }
 
+impl Default for LogWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LogWriter {
     pub fn new() -> Self {
         let file = OpenOptions::new()
             // open the existing file or create a new one if it does not exist.
             .create(true)
-            // acquire write permission.
-            .write(true)
+            // keep the table keys logged before a restart.
+            .append(true)
             .open(LOG_FILE_PATH)
             .unwrap();
 
@@ -25,8 +38,21 @@ impl LogWriter {
         }
     }
 
-    pub fn push(&mut self, table_key: &TableKey) {
-        self.writer.write(&table_key.encode_to_bytes()).unwrap();
+    /// append the table keys of a write to the log as one record.
+    /// the record is checksummed, so a record torn by a crash is discarded as a whole on restart.
+    /// | payload len (4B) | crc32c of payload (4B) | payload: encoded table keys |
+    pub fn push(&mut self, table_keys: &[TableKey]) {
+        let mut payload = Vec::new();
+        for table_key in table_keys.iter() {
+            payload.extend_from_slice(&table_key.encode_to_bytes());
+        }
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+        record.write_fixedint(payload.len() as u32).unwrap();
+        record.write_fixedint(crc32c(&payload)).unwrap();
+        record.extend_from_slice(&payload);
+
+        self.writer.write_all(&record).unwrap();
         self.writer.flush().unwrap();
     }
 
@@ -35,19 +61,62 @@ impl LogWriter {
         remove_file(LOG_FILE_PATH).unwrap();
         *self = LogWriter::new();
     }
+
+    /// turn the log into the log of the immutable memtable and create a new log.
+    /// the log of the immutable memtable is kept until the immutable memtable is flushed.
+    pub fn freeze(&mut self) {
+        rename(LOG_FILE_PATH, IMM_LOG_FILE_PATH).unwrap();
+        *self = LogWriter::new();
+    }
+}
+
+/// delete the log of the immutable memtable once its table keys are persisted in sstables.
+/// a log which is already removed is not an error.
+pub fn remove_imm_log() {
+    if let Err(err) = remove_file(IMM_LOG_FILE_PATH) {
+        if err.kind() != ErrorKind::NotFound {
+            panic!("failed to remove {}: {}", IMM_LOG_FILE_PATH, err);
+        }
+    }
+}
+
+/// prepend the log of the immutable memtable, if any, to the log.
+/// called on restart since the table keys of both logs are restored into the memtable.
+pub fn merge_imm_log() {
+    if !Path::new(IMM_LOG_FILE_PATH).exists() {
+        return;
+    }
+    let mut bytes = fs::read(IMM_LOG_FILE_PATH).unwrap();
+    if let Ok(log_bytes) = fs::read(LOG_FILE_PATH) {
+        bytes.extend_from_slice(&log_bytes);
+    }
+    // the merged log replaces the log atomically. a crash after the replacement but before the
+    // immutable log is removed leaves the table keys of the immutable memtable in both logs, so
+    // `Db::recover` skips the table keys whose sequence numbers are already restored.
+    let tmp_path = format!("{}.tmp", LOG_FILE_PATH);
+    fs::write(&tmp_path, &bytes).unwrap();
+    rename(&tmp_path, LOG_FILE_PATH).unwrap();
+    remove_imm_log();
 }
 
 pub struct LogReader;
 
 impl LogReader {
+    /// read the table keys in the log of the immutable memtable, if any, and then in the log.
     pub fn read_all() -> Vec<TableKey> {
         let mut table_keys = Vec::new();
 
-        if let Ok(file) = File::open(LOG_FILE_PATH) {
-            let mut reader = BufReader::new(file);
-            let mut buf = [0; TABLE_KEY_SIZE];
-            while reader.read_exact(&mut buf).is_ok() {
-                table_keys.push(TableKey::decode_from_bytes(&buf.to_vec()).unwrap());
+        for path in [IMM_LOG_FILE_PATH, LOG_FILE_PATH] {
+            if let Ok(file) = File::open(path) {
+                let mut reader = BufReader::new(file);
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).unwrap();
+
+                // a torn record at the tail of the log, if any, is discarded.
+                let mut reader = buf.as_slice();
+                while let Some(record_table_keys) = read_record(&mut reader) {
+                    table_keys.extend(record_table_keys);
+                }
             }
         }
 
@@ -55,16 +124,38 @@ impl LogReader {
     }
 }
 
+/// decode a record from the head of the reader and advance the reader past it.
+/// return None if the record is truncated or corrupted.
+fn read_record(reader: &mut &[u8]) -> Option<Vec<TableKey>> {
+    if reader.len() < RECORD_HEADER_SIZE {
+        return None;
+    }
+    let payload_len = u32::decode_fixed(&reader[..4]) as usize;
+    let checksum = u32::decode_fixed(&reader[4..RECORD_HEADER_SIZE]);
+    let mut payload = reader.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload_len)?;
+    if crc32c(payload) != checksum {
+        return None;
+    }
+    *reader = &reader[RECORD_HEADER_SIZE + payload_len..];
+
+    let mut table_keys = Vec::new();
+    while !payload.is_empty() {
+        table_keys.push(TableKey::decode_from_reader(&mut payload).ok()?);
+    }
+    Some(table_keys)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn log_writer_reader() {
+        let _ = remove_file(LOG_FILE_PATH);
         let mut writer = LogWriter::new();
         let num_keys = 100;
         for i in 0..num_keys {
-            writer.push(&TableKey::identity(i));
+            writer.push(&[TableKey::identity(i)]);
         }
 
         let table_keys = LogReader::read_all();
@@ -72,8 +163,46 @@ mod tests {
             assert_eq!(table_keys.get(i as usize).unwrap(), &TableKey::identity(i));
         }
 
+        // the keys in the log of the immutable memtable are read before those in the new log.
+        writer.freeze();
+        for i in num_keys..2 * num_keys {
+            writer.push(&[TableKey::identity(i)]);
+        }
+        for _ in 0..2 {
+            let table_keys = LogReader::read_all();
+            assert_eq!(table_keys.len(), 2 * num_keys as usize);
+            for i in 0..2 * num_keys {
+                assert_eq!(table_keys.get(i as usize).unwrap(), &TableKey::identity(i));
+            }
+            // merging the logs does not change the keys read.
+            merge_imm_log();
+            assert!(!Path::new(IMM_LOG_FILE_PATH).exists());
+        }
+        // removing a missing log of the immutable memtable does not panic.
+        remove_imm_log();
+
         writer.reset();
         let table_keys = LogReader::read_all();
         assert_eq!(table_keys.len(), 0);
+
+        // a record torn at any byte is discarded as a whole, while the preceding records are kept.
+        writer.push(&[TableKey::identity(0)]);
+        let batch: Vec<TableKey> = (1..4).map(TableKey::identity).collect();
+        writer.push(&batch);
+        let bytes = fs::read(LOG_FILE_PATH).unwrap();
+        assert_eq!(LogReader::read_all().len(), 4);
+        let record_size = RECORD_HEADER_SIZE + batch.iter().map(|k| k.size()).sum::<usize>();
+        for torn_len in 1..=record_size {
+            fs::write(LOG_FILE_PATH, &bytes[..bytes.len() - torn_len]).unwrap();
+            assert_eq!(LogReader::read_all(), vec![TableKey::identity(0)]);
+        }
+
+        // a record with a corrupted payload is discarded.
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        fs::write(LOG_FILE_PATH, &corrupted).unwrap();
+        assert_eq!(LogReader::read_all(), vec![TableKey::identity(0)]);
+
+        writer.reset();
     }
 }
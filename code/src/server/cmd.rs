@@ -1,4 +1,5 @@
-use crate::util::types::{UserKey, UserValue};
+use crate::storage::keys::MAX_USER_KEY_SIZE;
+use crate::util::types::{AggregateOp, UserKey, UserValue};
 use std::path::Path;
 
 // commands provided by the server.
@@ -11,31 +12,33 @@ pub enum Command {
     PrintStats,              // print the key range in all levels of the lsm tree.
     Quit,                    // terminate the session.
     Help,                    // print help options.
+    // aggregate values in the key range [start_key, end_key).
+    Aggregate(AggregateOp, UserKey, UserKey),
 }
 
 impl Command {
     // construct a cmd from tokens parsed from command line input.
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<Command> {
+    pub fn from_tokens(tokens: &[&str]) -> Option<Command> {
         match tokens[0] {
             "p" | "put" => {
-                if tokens.len() == 3 && is_valid_key(tokens[1]) && is_valid_value(tokens[2]) {
+                if tokens.len() == 3 && is_valid_key(tokens[1]) {
                     return Some(Command::Put(
-                        tokens[1].parse().unwrap(),
-                        tokens[2].parse().unwrap(),
+                        tokens[1].as_bytes().to_vec(),
+                        tokens[2].as_bytes().to_vec(),
                     ));
                 }
                 None
             }
             "g" | "get" => {
                 if tokens.len() == 2 && is_valid_key(tokens[1]) {
-                    return Some(Command::Get(tokens[1].parse().unwrap()));
+                    return Some(Command::Get(tokens[1].as_bytes().to_vec()));
                 }
                 None
             }
             "r" | "range" => {
                 if tokens.len() == 3 && is_valid_key(tokens[1]) && is_valid_key(tokens[2]) {
-                    let start_key = tokens[1].parse().unwrap();
-                    let end_key = tokens[2].parse().unwrap();
+                    let start_key = tokens[1].as_bytes().to_vec();
+                    let end_key = tokens[2].as_bytes().to_vec();
                     // ensure the range is valid.
                     if start_key <= end_key {
                         return Some(Command::Range(start_key, end_key));
@@ -43,9 +46,26 @@ impl Command {
                 }
                 None
             }
+            "c" | "count" | "sum" | "min" | "max" => {
+                if tokens.len() == 3 && is_valid_key(tokens[1]) && is_valid_key(tokens[2]) {
+                    let op = match tokens[0] {
+                        "sum" => AggregateOp::Sum,
+                        "min" => AggregateOp::Min,
+                        "max" => AggregateOp::Max,
+                        _ => AggregateOp::Count,
+                    };
+                    let start_key = tokens[1].as_bytes().to_vec();
+                    let end_key = tokens[2].as_bytes().to_vec();
+                    // ensure the range is valid.
+                    if start_key <= end_key {
+                        return Some(Command::Aggregate(op, start_key, end_key));
+                    }
+                }
+                None
+            }
             "d" | "delete" => {
                 if tokens.len() == 2 && is_valid_key(tokens[1]) {
-                    return Some(Command::Delete(tokens[1].parse().unwrap()));
+                    return Some(Command::Delete(tokens[1].as_bytes().to_vec()));
                 }
                 None
             }
@@ -78,14 +98,9 @@ impl Command {
     }
 }
 
-/// return true if the int_str str can be casted to the key type without error.
-fn is_valid_key(int_str: &str) -> bool {
-    int_str.parse::<UserKey>().is_ok()
-}
-
-/// return true if the int_str str can be casted to the value type without error.
-fn is_valid_value(int_str: &str) -> bool {
-    int_str.parse::<UserValue>().is_ok()
+/// return true if the key_str str is a non-empty key within the key size limit.
+fn is_valid_key(key_str: &str) -> bool {
+    !key_str.is_empty() && key_str.len() <= MAX_USER_KEY_SIZE
 }
 
 /// print help options.
@@ -93,21 +108,29 @@ pub fn print_help() {
     static PUT: &str = "p | put <key> <value>";
     static GET: &str = "g | get <key>";
     static RANGE: &str = "r | range <start_key> <end_key>";
+    static COUNT: &str = "c | count <start_key> <end_key>";
+    static AGGREGATE: &str = "sum | min | max <start_key> <end_key>";
     static DELETE: &str = "d | delete <key>";
     static LOAD: &str = "l | load <command_batch_file>";
     static PRINT_STATS: &str = "s | print";
     static QUIT: &str = "q | quit";
     static HELP: &str = "h | help";
 
-    print!(
-        "  Usage:\n\t{:<35}{}\n\t{:<35}{}\n\t{:<35}{}\n\t{:<35}{}\n\t{:<35}{}\n\t{:<35}{}\n\t{:<35}{}\n\t{:<35}{}\n",
-        PUT, "upsert a key-value pair to the database",
-        GET, "fetch the associated value of the given key",
-        RANGE, "fetch values associated with the keys in the key range [start_key, end_key)",
-        DELETE, "delete the key-value pair associated with the given key",
-        LOAD, "upsert a sequence of key-value pairs stored in the file to the database",
-        PRINT_STATS, "print the current state of the database",
-        QUIT, "terminate the session",
-        HELP, "print this help message"
-    );
+    let options = [
+        (PUT, "upsert a key-value pair to the database"),
+        (GET, "fetch the associated value of the given key"),
+        (RANGE, "fetch values associated with the keys in the key range [start_key, end_key) in byte-wise order"),
+        (COUNT, "count the keys in the key range [start_key, end_key)"),
+        (AGGREGATE, "aggregate the integer values associated with the keys in the key range [start_key, end_key)"),
+        (DELETE, "delete the key-value pair associated with the given key"),
+        (LOAD, "upsert a sequence of key-value pairs stored in the file to the database"),
+        (PRINT_STATS, "print the current state of the database"),
+        (QUIT, "terminate the session"),
+        (HELP, "print this help message"),
+    ];
+
+    println!("  Usage:");
+    for (usage, description) in options {
+        println!("\t{:<35}{}", usage, description);
+    }
 }
@@ -1,12 +1,17 @@
 use crate::db::db::Db;
 use crate::server::cmd::{print_help, Command};
-use crate::util::types::{UserEntry, UserKey, UserValue};
+
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::mem;
 
+/// format a user key or a user value for printing.
+fn display(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
 /// key-value server.
 pub struct Server {
     /// key-value database.
@@ -22,7 +27,7 @@ impl Server {
         // set a cmd_history file to store history commands.
         let history_path = format!(
             "{}/.cmd_history",
-            std::env::current_dir().unwrap().display().to_string()
+            std::env::current_dir().unwrap().display()
         );
         // create an editor for reading lines.
         let mut editor = Editor::<()>::new().unwrap();
@@ -59,7 +64,7 @@ impl Server {
             match self.editor.readline(PROMPT) {
                 Ok(line) => {
                     // skip empty lines.
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
                         continue;
                     }
 
@@ -94,28 +99,39 @@ impl Server {
     fn handle_cmd(&mut self, cmd: Command) {
         match cmd {
             Command::Put(key, val) => {
-                self.db.put(key, val);
+                if let Err(err) = self.db.put(&key, &val) {
+                    println!("{}", err);
+                }
             }
             Command::Get(key) => {
-                if let Some(val) = self.db.get(key) {
+                if let Some(val) = self.db.get(&key) {
                     // print the value.
-                    println!("{}", val);
+                    println!("{}", display(&val));
                 }
             }
             Command::Range(start_key, end_key) => {
-                let entries = self.db.range(start_key, end_key);
+                let entries = self.db.range(&start_key, &end_key);
                 if entries.is_empty() {
                     // print an empty line.
                     println!();
                 } else {
                     // print each kv entry in the format key:value.
                     for entry in entries.iter() {
-                        println!("{}:{}", entry.key, entry.val);
+                        println!("{}:{}", display(&entry.key), display(&entry.val));
                     }
                 }
             }
+            Command::Aggregate(op, start_key, end_key) => {
+                // print an empty line if no value is aggregated.
+                match self.db.aggregate(op, &start_key, &end_key) {
+                    Some(aggregate) => println!("{}", aggregate),
+                    None => println!(),
+                }
+            }
             Command::Delete(key) => {
-                self.db.delete(key);
+                if let Err(err) = self.db.delete(&key) {
+                    println!("{}", err);
+                }
             }
             Command::Load(cmd_batch_file) => {
                 // open the file.
@@ -127,18 +143,21 @@ impl Server {
                 reader.read_to_end(&mut buf).unwrap();
 
                 // read kv entries entry by entry.
-                static ENTRY_SIZE: usize = mem::size_of::<UserEntry>();
-                static KEY_SIZE: usize = mem::size_of::<UserKey>();
+                // the command batch file stores native-endian integer key-value pairs, and each integer is
+                // converted to its decimal string so that it agrees with the same key typed in a put command.
+                static KEY_SIZE: usize = mem::size_of::<i32>();
+                static ENTRY_SIZE: usize = 2 * KEY_SIZE;
                 assert_eq!(buf.len() % ENTRY_SIZE, 0);
 
-                for i in (0..buf.len()).step_by(ENTRY_SIZE) {
-                    let key_bytes = &buf[i..i + KEY_SIZE];
-                    let val_bytes = &buf[i + KEY_SIZE..i + ENTRY_SIZE];
-                    let key = unsafe { *(key_bytes.as_ptr()) } as UserKey;
-                    let val = unsafe { *(val_bytes.as_ptr()) } as UserValue;
+                for entry in buf.chunks_exact(ENTRY_SIZE) {
+                    let key = i32::from_ne_bytes(entry[..KEY_SIZE].try_into().unwrap());
+                    let val = i32::from_ne_bytes(entry[KEY_SIZE..].try_into().unwrap());
 
-                    // insert the kv entry into the db.
-                    self.db.put(key, val);
+                    // insert the kv entry into the db. a decimal integer key never exceeds the key
+                    // size limit.
+                    self.db
+                        .put(key.to_string().as_bytes(), val.to_string().as_bytes())
+                        .unwrap();
                 }
             }
             Command::PrintStats => {
@@ -166,13 +185,13 @@ mod tests {
         let mut server = Server::new(db);
         let num_table_keys = 1000;
         for i in 0..num_table_keys {
-            let put = Command::Put(i, i);
+            let put = Command::Put(i.to_string().into_bytes(), i.to_string().into_bytes());
             server.handle_cmd(put);
             server.handle_cmd(Command::PrintStats);
         }
 
         for i in 0..num_table_keys {
-            let get = Command::Get(i);
+            let get = Command::Get(i.to_string().into_bytes());
             server.handle_cmd(get);
         }
     }
@@ -190,7 +209,7 @@ mod tests {
         let mut server = Server::new(db);
         let mut inserted = Vec::new();
         for i in 0..num_keys {
-            let put = Command::Put(i, i);
+            let put = Command::Put(i.to_string().into_bytes(), i.to_string().into_bytes());
             server.handle_cmd(put);
             inserted.push(i);
         }
@@ -203,7 +222,8 @@ mod tests {
         cfg.set_recovery(true);
         let mut db = Db::new(cfg);
         for i in 0..num_keys {
-            assert_eq!(db.get(i).unwrap(), i);
+            let user_key = i.to_string().into_bytes();
+            assert_eq!(db.get(&user_key).unwrap(), user_key);
         }
     }
 
@@ -0,0 +1,95 @@
+/// size in bytes of each block allocated by an arena.
+const BLOCK_SIZE: usize = 4096;
+/// all allocations are aligned to this many bytes.
+const ALIGN: usize = 8;
+
+/// a bump allocator carving small allocations out of large blocks.
+/// memory allocated by an arena is never moved or freed until the arena is dropped, so pointers
+/// handed out by an arena stay valid for the lifetime of the arena.
+pub struct Arena {
+    /// allocated blocks. a block of u64s is 8-byte aligned.
+    blocks: Vec<Box<[u64]>>,
+    /// start of the unused bytes of the current block.
+    ptr: *mut u8,
+    /// number of unused bytes of the current block.
+    remaining: usize,
+    /// total size in bytes of the allocated blocks.
+    memory_usage: usize,
+}
+
+// safety: the arena exclusively owns its blocks and the raw pointer only points into them.
+unsafe impl Send for Arena {}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            ptr: std::ptr::null_mut(),
+            remaining: 0,
+            memory_usage: 0,
+        }
+    }
+
+    /// allocate `size` zeroed bytes aligned to 8 bytes.
+    pub fn allocate(&mut self, size: usize) -> *mut u8 {
+        let size = (size + ALIGN - 1) & !(ALIGN - 1);
+        if size > self.remaining {
+            // a large allocation gets a block of its own so that the unused bytes of the current
+            // block are not wasted.
+            if size > BLOCK_SIZE / 4 {
+                return self.allocate_block(size);
+            }
+            self.ptr = self.allocate_block(BLOCK_SIZE);
+            self.remaining = BLOCK_SIZE;
+        }
+
+        let ptr = self.ptr;
+        // safety: the current block has at least `size` unused bytes after the pointer.
+        self.ptr = unsafe { self.ptr.add(size) };
+        self.remaining -= size;
+        ptr
+    }
+
+    /// return the total size in bytes of the memory allocated by the arena.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    fn allocate_block(&mut self, size: usize) -> *mut u8 {
+        let mut block = vec![0_u64; size / ALIGN].into_boxed_slice();
+        // moving the box into the vector does not move the heap memory it points to.
+        let ptr = block.as_mut_ptr() as *mut u8;
+        self.blocks.push(block);
+        self.memory_usage += size;
+        ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate() {
+        let mut arena = Arena::new();
+        let mut allocations = Vec::new();
+        for (i, size) in [1, 7, 8, 100, 2000, 3, BLOCK_SIZE, 500].iter().enumerate() {
+            let ptr = arena.allocate(*size);
+            assert_eq!(ptr as usize % ALIGN, 0);
+            // safety: the allocation has `size` bytes.
+            unsafe { std::ptr::write_bytes(ptr, i as u8, *size) };
+            allocations.push((ptr, *size, i as u8));
+        }
+
+        // later allocations do not overwrite or move earlier ones.
+        for (ptr, size, byte) in allocations {
+            // safety: the allocation has `size` bytes and the arena is alive.
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, size) };
+            assert!(bytes.iter().all(|b| *b == byte));
+        }
+
+        // the allocation of BLOCK_SIZE bytes gets a block of its own, and the following small
+        // allocation still fits in the first block.
+        assert_eq!(arena.memory_usage(), 2 * BLOCK_SIZE);
+    }
+}
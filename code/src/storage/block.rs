@@ -1,119 +1,489 @@
-use crate::util::types::UserKey;
-
-use super::bloom_filter::BloomFilter;
+use super::bloom_filter::{BloomFilter, KeyHash};
+use super::compression::{compress, decompress, decompress_unsized};
 use super::iterator::TableKeyIterator;
-use super::keys::{LookupKey, TableKey, TABLE_KEY_SIZE};
+use super::keys::{LookupKey, TableKey};
+use crate::util::crc32c::crc32c;
+use crate::util::types::{CompressionType, FilterType, SeqNum, WriteType};
 use integer_encoding::*;
-use std::mem;
+use std::sync::Arc;
 use std::{cmp, io};
 
-pub const BLOCK_SIZE: usize = 4 * 1024; // 4KB.
-pub const KEYS_PER_BLOCK: usize = BLOCK_SIZE / TABLE_KEY_SIZE;
+/// default block size.
+pub const DEFAULT_BLOCK_SIZE: usize = 4 * 1024; // 4KB.
+/// min block size.
+// a block must be large enough to hold the largest table key and the footer.
+pub const MIN_BLOCK_SIZE: usize = 4 * 1024; // 4KB.
+/// default number of bits per key of bloom filters, i.e. a false positive rate of about 1%.
+pub const DEFAULT_BITS_PER_KEY: usize = 10;
+/// size in bytes of a crc32c checksum.
+pub const CHECKSUM_SIZE: usize = 4;
+/// the footer ends with the format version, the block size, the magic number and the checksum,
+/// i.e. they're the tail of an sstable file, so that the footer could be located and decoded
+/// without knowing the block size and the format beforehand.
+pub const FOOTER_TAIL_SIZE: usize = 4 + 8 + 8 + CHECKSUM_SIZE;
+/// magic number identifying an sstable file.
+pub const SSTABLE_MAGIC: u64 = u64::from_le_bytes(*b"lsmdbsst");
+/// format version of the sstables written by this build.
+// bump it on any incompatible change of the sstable layout and keep reading the older versions.
+pub const FORMAT_VERSION: u32 = 4;
+/// number of entries between two restart points in a data block.
+pub const RESTART_INTERVAL: usize = 16;
+/// a data block on disk is followed by a trailer storing its compression type and checksum.
+pub const BLOCK_TRAILER_SIZE: usize = 1 + CHECKSUM_SIZE;
+
+/// pad the encoded block to `size` bytes and append the checksum of all preceding bytes.
+/// | contents | padding | crc32c (4B) |
+fn pad_and_checksum(bytes: &mut Vec<u8>, size: usize) {
+    assert!(bytes.len() + CHECKSUM_SIZE <= size);
+    bytes.resize(size - CHECKSUM_SIZE, 0);
+    let checksum = crc32c(bytes);
+    bytes.write_fixedint(checksum).unwrap();
+}
 
-fn maybe_pad(bytes: &mut Vec<u8>) {
-    if bytes.len() < BLOCK_SIZE {
-        bytes.append(&mut vec![0; BLOCK_SIZE - bytes.len()]);
+/// return true if the checksum at the tail of the block matches the preceding bytes.
+pub fn verify_checksum(bytes: &[u8]) -> bool {
+    if bytes.len() < CHECKSUM_SIZE {
+        return false;
     }
+    let (contents, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    crc32c(contents) == u32::decode_fixed(checksum)
 }
 
-pub fn table_keys_to_blocks(num_table_keys: usize) -> usize {
-    let num_data_blocks = (num_table_keys + KEYS_PER_BLOCK - 1) / KEYS_PER_BLOCK;
-    num_data_blocks
+/// size in bytes of the write type and the sequence number appended to a user key in a data block entry.
+const KEY_SUFFIX_SIZE: usize = 8 + 1;
+
+/// encode the key of a table key in a data block entry.
+/// | user key | seq num (8B) | write type (1B) |
+fn encode_entry_key(table_key: &TableKey) -> Vec<u8> {
+    let mut key = Vec::with_capacity(table_key.user_key.len() + KEY_SUFFIX_SIZE);
+    key.extend_from_slice(&table_key.user_key);
+    key.write_fixedint(table_key.seq_num as u64).unwrap();
+    key.write_fixedint(table_key.write_type as u8).unwrap();
+    key
 }
 
-pub struct DataBlock {
-    pub table_keys: Vec<TableKey>,
+/// return the length of the common prefix of the two byte strings.
+fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+/// a builder for building a data block.
+/// table keys must be added in ascending order.
+///
+/// each table key is stored as an entry whose key shares a prefix with the previous entry's key:
+/// | shared len (varint) | unshared len (varint) | val len (varint) | unshared key bytes | val |
+/// every `RESTART_INTERVAL` entries, an entry stores its full key and its offset is recorded as a restart point.
+/// the encoded data block is:
+/// | entry | entry | ... | restart point (4B) | ... | num restart points (4B) |
+pub struct DataBlockBuilder {
+    /// encoded entries.
+    bytes: Vec<u8>,
+    /// offsets of the restart points.
+    restarts: Vec<u32>,
+    /// key of the last added entry.
+    last_key: Vec<u8>,
+    /// number of entries added since the last restart point.
+    counter: usize,
     max_table_key: Option<TableKey>,
 }
 
-impl DataBlock {
+impl DataBlockBuilder {
     pub fn new() -> Self {
         Self {
-            table_keys: Vec::new(),
+            bytes: Vec::new(),
+            restarts: vec![0],
+            last_key: Vec::new(),
+            counter: 0,
             max_table_key: None,
         }
     }
 
     pub fn add(&mut self, table_key: TableKey) {
+        let key = encode_entry_key(&table_key);
+
+        let shared = if self.counter < RESTART_INTERVAL {
+            shared_prefix_len(&self.last_key, &key)
+        } else {
+            // start a new restart point which stores the full key.
+            self.restarts.push(self.bytes.len() as u32);
+            self.counter = 0;
+            0
+        };
+
+        self.bytes.write_varint(shared).unwrap();
+        self.bytes.write_varint(key.len() - shared).unwrap();
+        self.bytes.write_varint(table_key.user_val.len()).unwrap();
+        self.bytes.extend_from_slice(&key[shared..]);
+        self.bytes.extend_from_slice(&table_key.user_val);
+
+        self.last_key = key;
+        self.counter += 1;
+
         if self.max_table_key.is_none() {
-            self.max_table_key = Some(table_key.clone());
+            self.max_table_key = Some(table_key);
         } else {
-            self.max_table_key = Some(cmp::max(
-                self.max_table_key.as_ref().unwrap().clone(),
-                table_key.clone(),
-            ));
+            self.max_table_key = Some(cmp::max(self.max_table_key.take().unwrap(), table_key));
         }
-        self.table_keys.push(table_key);
     }
 
-    pub fn fence_pointer(&self) -> TableKey {
-        self.max_table_key.as_ref().unwrap().clone()
+    /// a fence pointer is the lookup key of the max table key in the data block.
+    pub fn fence_pointer(&self) -> LookupKey {
+        self.max_table_key.as_ref().unwrap().as_lookup_key()
     }
 
+    /// return the size in bytes of the encoded data block.
     pub fn size(&self) -> usize {
-        self.table_keys.len() * TABLE_KEY_SIZE
+        self.bytes.len() + (self.restarts.len() + 1) * 4
     }
 
     pub fn encode_to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(BLOCK_SIZE);
-        for table_key in self.table_keys.iter() {
-            bytes.append(&mut table_key.encode_to_bytes());
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(&self.bytes);
+        for restart in self.restarts.iter() {
+            bytes.write_fixedint(*restart).unwrap();
         }
-        maybe_pad(&mut bytes);
-        assert_eq!(bytes.len(), BLOCK_SIZE);
+        bytes.write_fixedint(self.restarts.len() as u32).unwrap();
         bytes
     }
+}
 
-    // num_table_keys: #table keys in the data block.
-    pub fn decode_from_bytes(bytes: &Vec<u8>, num_table_keys: usize) -> Result<Self, io::Error> {
-        let mut data_block = DataBlock::new();
-        for i in 0..num_table_keys {
-            let offset = i * TABLE_KEY_SIZE;
-            let table_key =
-                TableKey::decode_from_bytes(&bytes[offset..offset + TABLE_KEY_SIZE].to_owned())?;
-            data_block.add(table_key);
+/// a data block read from an sstable file.
+/// entries are decoded lazily by iterators.
+// the bytes are shared by clones of the data block, e.g. the ones in the block cache.
+#[derive(Clone)]
+pub struct DataBlock {
+    bytes: Arc<[u8]>,
+    /// offset of the restart point array which also marks the end of entries.
+    restarts_offset: usize,
+    num_restarts: usize,
+}
+
+impl DataBlock {
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid restart points");
+        if bytes.len() < 4 {
+            return Err(invalid());
+        }
+        let num_restarts = u32::decode_fixed(&bytes[bytes.len() - 4..]) as usize;
+        let restarts_size = (num_restarts + 1) * 4;
+        if num_restarts == 0 || restarts_size > bytes.len() {
+            return Err(invalid());
         }
-        Ok(data_block)
+
+        Ok(Self {
+            bytes: Arc::from(bytes),
+            restarts_offset: bytes.len() - restarts_size,
+            num_restarts,
+        })
+    }
+
+    /// return the size in bytes of the decompressed data block.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
     }
 
     pub fn iter(&self) -> DataBlockIterator {
         DataBlockIterator {
-            table_keys: self.table_keys.clone(),
-            cursor: -1,
+            bytes: self.bytes.clone(),
+            restarts_offset: self.restarts_offset,
+            num_restarts: self.num_restarts,
+            curr_offset: 0,
+            next_offset: 0,
+            key: Vec::new(),
+            curr_table_key: None,
+            corrupted: false,
         }
     }
 }
 
 pub struct DataBlockIterator {
-    table_keys: Vec<TableKey>,
-    cursor: isize,
+    bytes: Arc<[u8]>,
+    restarts_offset: usize,
+    num_restarts: usize,
+    /// offset of the current entry.
+    curr_offset: usize,
+    /// offset of the next entry.
+    next_offset: usize,
+    /// key of the current entry.
+    key: Vec<u8>,
+    curr_table_key: Option<TableKey>,
+    /// true if a malformed entry is encountered.
+    corrupted: bool,
+}
+
+impl DataBlockIterator {
+    /// return true if the iterator stops because of a malformed entry.
+    pub fn is_corrupted(&self) -> bool {
+        self.corrupted
+    }
+
+    fn restart_point(&self, i: usize) -> usize {
+        let offset = self.restarts_offset + i * 4;
+        u32::decode_fixed(&self.bytes[offset..offset + 4]) as usize
+    }
+
+    /// move the cursor right before the entry at the i-th restart point.
+    fn seek_to_restart_point(&mut self, i: usize) {
+        self.next_offset = self.restart_point(i);
+        self.key.clear();
+        self.curr_table_key = None;
+    }
+
+    /// decode the entry at the next offset and move the cursor to it.
+    fn decode_next_entry(&mut self) -> Result<Option<TableKey>, io::Error> {
+        if self.next_offset >= self.restarts_offset {
+            return Ok(None);
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed entry");
+        let mut reader = &self.bytes[self.next_offset..self.restarts_offset];
+        let shared: usize = reader.read_varint()?;
+        let unshared: usize = reader.read_varint()?;
+        let val_len: usize = reader.read_varint()?;
+        if shared > self.key.len() || unshared + val_len > reader.len() {
+            return Err(invalid());
+        }
+
+        self.curr_offset = self.next_offset;
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&reader[..unshared]);
+        let user_val = reader[unshared..unshared + val_len].to_vec();
+        if self.key.len() < KEY_SUFFIX_SIZE {
+            return Err(invalid());
+        }
+
+        let user_key_len = self.key.len() - KEY_SUFFIX_SIZE;
+        let seq_num = u64::decode_fixed(&self.key[user_key_len..user_key_len + 8]) as SeqNum;
+        let write_type = match self.key[self.key.len() - 1] {
+            0 => WriteType::Empty,
+            1 => WriteType::Put,
+            2 => WriteType::Delete,
+            _ => return Err(invalid()),
+        };
+
+        self.next_offset = self.restarts_offset - reader.len() + unshared + val_len;
+        Ok(Some(TableKey {
+            user_key: self.key[..user_key_len].to_vec(),
+            seq_num,
+            write_type,
+            user_val,
+        }))
+    }
 }
 
 impl TableKeyIterator for DataBlockIterator {
-    fn seek(&mut self, lookup_key: &super::keys::LookupKey) {
+    fn seek(&mut self, lookup_key: &LookupKey) {
+        let target = lookup_key.as_table_key();
+
+        // binary search the last restart point whose key is less than the target.
+        // entries before the restart point are all less than the target.
+        let mut lo = 0;
+        let mut hi = self.num_restarts - 1;
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            self.seek_to_restart_point(mid);
+            match self.next() {
+                Some(table_key) if table_key < target => lo = mid,
+                Some(_) => hi = mid - 1,
+                None => return,
+            }
+        }
+
+        // linear search the first entry which is greater than or equal to the target.
+        self.seek_to_restart_point(lo);
         while let Some(table_key) = self.next() {
-            if table_key >= lookup_key.as_table_key() {
+            if table_key >= target {
                 break;
             }
         }
     }
 
+    fn seek_to_last(&mut self) {
+        // decode entries from the last restart point till the end.
+        self.seek_to_restart_point(self.num_restarts - 1);
+        while self.next_offset < self.restarts_offset && self.next().is_some() {}
+    }
+
     fn next(&mut self) -> Option<TableKey> {
-        self.cursor += 1;
+        if self.corrupted {
+            return None;
+        }
+        match self.decode_next_entry() {
+            Ok(table_key) => self.curr_table_key = table_key,
+            Err(_) => {
+                self.corrupted = true;
+                self.curr_table_key = None;
+            }
+        }
+        self.curr()
+    }
+
+    fn prev(&mut self) -> Option<TableKey> {
+        if self.corrupted || self.curr_table_key.is_none() {
+            return None;
+        }
+
+        // entries could only be decoded forward from a restart point, so decode entries from the
+        // last restart point before the current entry till the entry right before it.
+        let curr_offset = self.curr_offset;
+        let mut lo = 0;
+        let mut hi = self.num_restarts;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.restart_point(mid) < curr_offset {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let num_restarts_before = lo;
+        if num_restarts_before == 0 {
+            // the current entry is the first one.
+            self.seek_to_restart_point(0);
+            return None;
+        }
+        self.seek_to_restart_point(num_restarts_before - 1);
+        while self.next().is_some() && self.next_offset < curr_offset {}
         self.curr()
     }
 
     fn valid(&self) -> bool {
-        self.cursor >= 0 && (self.cursor as usize) < self.table_keys.len()
+        self.curr_table_key.is_some()
     }
 
     fn curr(&self) -> Option<TableKey> {
-        // warning: tolerate integer underflow.
-        if let Some(table_key) = self.table_keys.get(self.cursor as usize) {
-            Some(table_key.clone())
-        } else {
-            None
+        self.curr_table_key.clone()
+    }
+}
+
+/// a handle locating a variable-size block in the sstable file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockHandle {
+    /// offset of the block in the sstable file.
+    pub offset: usize,
+    /// size in bytes of the block contents, excluding the trailer.
+    pub size: usize,
+}
+
+impl BlockHandle {
+    /// size in bytes of an encoded block handle.
+    pub const ENCODED_SIZE: usize = 2 * 8;
+
+    pub fn encode_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BlockHandle::ENCODED_SIZE);
+        bytes.write_fixedint(self.offset as u64).unwrap();
+        bytes.write_fixedint(self.size as u64).unwrap();
+        bytes
+    }
+
+    pub fn decode_from_reader(reader: &mut &[u8]) -> Result<Self, io::Error> {
+        Ok(Self {
+            offset: reader.read_fixedint::<u64>()? as usize,
+            size: reader.read_fixedint::<u64>()? as usize,
+        })
+    }
+}
+
+/// compress an encoded block and append the trailer.
+/// | block contents | compression type (1B) | crc32c (4B) |
+/// the checksum covers the block contents and the compression type.
+/// the block is stored uncompressed if the compression does not save at least 1/8 of the space.
+pub fn compress_block(bytes: &[u8], compression_type: CompressionType) -> Vec<u8> {
+    let (mut contents, compression_type) = match compression_type {
+        CompressionType::None => (bytes.to_vec(), CompressionType::None),
+        _ => {
+            let compressed = compress(bytes, compression_type);
+            if compressed.len() < bytes.len() - bytes.len() / 8 {
+                (compressed, compression_type)
+            } else {
+                (bytes.to_vec(), CompressionType::None)
+            }
+        }
+    };
+    contents.push(compression_type as u8);
+    let checksum = crc32c(&contents);
+    contents.write_fixedint(checksum).unwrap();
+    contents
+}
+
+/// decompress a block read with its trailer from an sstable of the given format version.
+/// the checksum is not verified here.
+pub fn decompress_block(bytes: &[u8], format_version: u32) -> Result<Vec<u8>, io::Error> {
+    if bytes.len() < BLOCK_TRAILER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated block",
+        ));
+    }
+    let (contents, trailer) = bytes.split_at(bytes.len() - BLOCK_TRAILER_SIZE);
+    let compression_type = match trailer[0] {
+        0 => CompressionType::None,
+        1 => CompressionType::Lz4,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown compression type",
+            ))
         }
+    };
+    // the uncompressed size is prefixed to compressed blocks since format version 4.
+    if format_version >= 4 {
+        decompress(contents, compression_type)
+    } else {
+        decompress_unsized(contents, compression_type)
+    }
+}
+
+/// return the number of blocks spanned by a metadata block of the given size, with the checksum
+/// placed at the tail of the last block.
+pub fn num_blocks_of(size: usize, block_size: usize) -> usize {
+    (size + CHECKSUM_SIZE).div_ceil(block_size)
+}
+
+/// a builder for building a filter block.
+/// the hashes of user keys are collected and the bloom filter is sized by the number of keys on finish.
+pub struct FilterBlockBuilder {
+    bits_per_key: usize,
+    key_hashes: Vec<KeyHash>,
+}
+
+impl FilterBlockBuilder {
+    pub fn new(bits_per_key: usize) -> Self {
+        Self {
+            bits_per_key,
+            key_hashes: Vec::new(),
+        }
+    }
+
+    // versions of the same user key are adjacent and they're counted only once.
+    // keys with the same hash set the same bits, so it's safe to deduplicate by hashes.
+    fn push_hash(&mut self, key_hash: KeyHash) {
+        if self.key_hashes.last() != Some(&key_hash) {
+            self.key_hashes.push(key_hash);
+        }
+    }
+
+    pub fn insert(&mut self, user_key: &[u8]) {
+        self.push_hash(BloomFilter::key_hash(user_key));
+    }
+
+    /// move the keys of the other builder into this builder.
+    pub fn append(&mut self, other: &mut FilterBlockBuilder) {
+        for key_hash in other.key_hashes.drain(..) {
+            self.push_hash(key_hash);
+        }
+    }
+
+    pub fn num_keys(&self) -> usize {
+        self.key_hashes.len()
+    }
+
+    pub fn finish(&self) -> FilterBlock {
+        let mut bloom_filter = BloomFilter::new(self.key_hashes.len(), self.bits_per_key);
+        for key_hash in self.key_hashes.iter() {
+            bloom_filter.insert_hash(*key_hash);
+        }
+        FilterBlock { bloom_filter }
     }
 }
 
@@ -122,156 +492,415 @@ pub struct FilterBlock {
 }
 
 impl FilterBlock {
+    pub fn maybe_contain(&self, user_key: &[u8]) -> bool {
+        self.bloom_filter.maybe_contain(user_key)
+    }
+
+    /// the filter block spans as many blocks as needed.
+    /// | bloom filter | padding | crc32c (4B) |
+    pub fn encode_to_bytes(&self, block_size: usize) -> Vec<u8> {
+        let mut bytes = self.bloom_filter.encode_to_bytes();
+        // the checksum is only placed at the tail of the last block.
+        let num_blocks = num_blocks_of(bytes.len(), block_size);
+        pad_and_checksum(&mut bytes, num_blocks * block_size);
+        bytes
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        Ok(Self {
+            bloom_filter: BloomFilter::decode_from_bytes(bytes)?,
+        })
+    }
+
+    /// decode a filter block written by format version 1 which has a fixed-size bloom filter.
+    pub fn decode_v1(bytes: &[u8]) -> Result<Self, io::Error> {
+        Ok(Self {
+            bloom_filter: BloomFilter::decode_v1(bytes)?,
+        })
+    }
+}
+
+/// an index of filter partitions.
+/// the i-th filter partition filters the keys indexed by the i-th index block.
+pub struct FilterIndexBlock {
+    handles: Vec<BlockHandle>,
+}
+
+impl FilterIndexBlock {
     pub fn new() -> Self {
         Self {
-            bloom_filter: BloomFilter::new(),
+            handles: Vec::new(),
         }
     }
 
-    pub fn insert(&mut self, user_key: UserKey) {
-        self.bloom_filter.insert(user_key);
+    pub fn add(&mut self, handle: BlockHandle) {
+        self.handles.push(handle);
     }
 
-    pub fn maybe_contain(&self, user_key: UserKey) -> bool {
-        self.bloom_filter.maybe_contain(user_key)
+    pub fn num_partitions(&self) -> usize {
+        self.handles.len()
     }
 
-    pub fn encode_to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.bloom_filter.encode_to_bytes();
-        maybe_pad(&mut bytes);
-        assert_eq!(bytes.len(), BLOCK_SIZE);
+    pub fn get(&self, i: usize) -> &BlockHandle {
+        &self.handles[i]
+    }
+
+    /// return the size in bytes of an encoded filter index block with the given number of
+    /// partitions without padding.
+    pub fn size_of(num_partitions: usize) -> usize {
+        4 + num_partitions * BlockHandle::ENCODED_SIZE
+    }
+
+    /// the filter index block spans as many blocks as needed.
+    /// | num partitions (4B) | block handle | ... | padding | crc32c (4B) |
+    pub fn encode_to_bytes(&self, block_size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.write_fixedint(self.handles.len() as u32).unwrap();
+        for handle in self.handles.iter() {
+            bytes.append(&mut handle.encode_to_bytes());
+        }
+        let num_blocks = num_blocks_of(bytes.len(), block_size);
+        pad_and_checksum(&mut bytes, num_blocks * block_size);
         bytes
     }
 
-    pub fn decode_from_bytes(bytes: &Vec<u8>) -> Self {
-        Self {
-            bloom_filter: BloomFilter::decode_from_bytes(bytes),
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let mut reader = bytes;
+        let num_partitions = reader.read_fixedint::<u32>()?;
+        let mut filter_index_block = FilterIndexBlock::new();
+        for _ in 0..num_partitions {
+            filter_index_block.add(BlockHandle::decode_from_reader(&mut reader)?);
+        }
+        Ok(filter_index_block)
+    }
+}
+
+/// lower-bound binary search the first fence pointer whose user key is greater than or equal to the
+/// lookup key's user key.
+/// returns Some(i) if the key might exist in the blocks the fence pointers point to.
+fn lower_bound<'a, F>(len: usize, fence_pointer_at: F, lookup_key: &LookupKey) -> Option<usize>
+where
+    F: Fn(usize) -> &'a LookupKey,
+{
+    let mut lo = 0; // start of the search space.
+    let mut len = len; // search space length.
+    let num_fence_pointers = len;
+
+    // loop inv: the search space is not empty.
+    while len > 0 {
+        let half = len / 2; // the length of the left half of the search space.
+        let mid = lo + half;
+        let fence_pointer = fence_pointer_at(mid);
+
+        // if adjacent blocks contain the same user key, only the left block might be target block.
+        // so the lower-bound binary searching is applied here.
+        if fence_pointer.user_key < lookup_key.user_key {
+            // proceed searching in the right half.
+            lo = mid + 1;
+            len -= half + 1;
+        } else {
+            // proceed searching in the left half.
+            len = half;
         }
     }
+
+    // further check that this block maybe contain the target key.
+    if lo < num_fence_pointers && fence_pointer_at(lo).user_key >= lookup_key.user_key {
+        Some(lo)
+    } else {
+        None
+    }
 }
 
+/// an index block is one partition of the sstable's index.
+/// it stores the fence pointers and the handles of a consecutive range of data blocks.
 pub struct IndexBlock {
-    fence_pointers: Vec<TableKey>,
+    fence_pointers: Vec<LookupKey>,
+    block_handles: Vec<BlockHandle>,
+    /// size in bytes of the encoded index block without padding.
+    size: usize,
 }
 
 impl IndexBlock {
     pub fn new() -> Self {
         Self {
             fence_pointers: Vec::new(),
+            block_handles: Vec::new(),
+            size: 0,
         }
     }
 
-    pub fn add(&mut self, fence_pointer: TableKey) {
+    /// return the size in bytes of an encoded entry having the given fence pointer.
+    pub fn entry_size(fence_pointer: &LookupKey) -> usize {
+        fence_pointer.size() + BlockHandle::ENCODED_SIZE
+    }
+
+    pub fn add(&mut self, fence_pointer: LookupKey, block_handle: BlockHandle) {
+        self.size += IndexBlock::entry_size(&fence_pointer);
         self.fence_pointers.push(fence_pointer);
+        self.block_handles.push(block_handle);
     }
 
-    /// returns Some(i) if the key might exist in the sstable.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// return the number of data blocks indexed by this index block.
+    pub fn len(&self) -> usize {
+        self.fence_pointers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fence_pointers.is_empty()
+    }
+
+    /// return the fence pointer of the last data block, i.e. the fence pointer of this index block.
+    pub fn fence_pointer(&self) -> &LookupKey {
+        self.fence_pointers.last().unwrap()
+    }
+
+    /// return the handle of the i-th data block indexed by this index block.
+    pub fn block_handle(&self, i: usize) -> &BlockHandle {
+        &self.block_handles[i]
+    }
+
+    /// returns Some(i) if the key might exist in the i-th data block indexed by this index block.
     pub fn binary_search(&self, lookup_key: &LookupKey) -> Option<usize> {
-        let mut lo = 0; // start of the search space.
-        let mut len = self.fence_pointers.len(); // search space length.
-
-        // loop inv: the search space is not empty.
-        while len > 0 {
-            let half = len / 2; // the length of the left half of the search space.
-            let mid = lo + half;
-            let fence_pointer = self.fence_pointers.get(mid).unwrap();
-
-            // if adjacent sstables contain the same user key, only the left sstable might be target sstable.
-            // so the lower-bound binary searching is applied here.
-            if fence_pointer.user_key < lookup_key.user_key {
-                // proceed searching in the right half.
-                lo = mid + 1;
-                len -= half + 1;
-            } else {
-                // proceed searching in the left half.
-                len = half;
-            }
+        lower_bound(
+            self.fence_pointers.len(),
+            |i| &self.fence_pointers[i],
+            lookup_key,
+        )
+    }
+
+    /// | fence pointer | block handle | fence pointer | block handle | ... | padding |
+    pub fn encode_to_bytes(&self, block_size: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (fence_pointer, block_handle) in
+            self.fence_pointers.iter().zip(self.block_handles.iter())
+        {
+            bytes.append(&mut fence_pointer.encode_to_bytes());
+            bytes.append(&mut block_handle.encode_to_bytes());
         }
+        pad_and_checksum(&mut bytes, block_size);
+        assert_eq!(bytes.len(), block_size);
+        bytes
+    }
 
-        // further check that this sstable maybe contain the target key.
-        let fence_pointer = self.fence_pointers.get(lo).unwrap();
-        if fence_pointer.user_key >= lookup_key.user_key {
-            Some(lo)
-        } else {
-            None
+    pub fn decode_from_bytes(bytes: &[u8], num_data_blocks: usize) -> Result<Self, io::Error> {
+        let mut reader = bytes;
+        let mut index_block = IndexBlock::new();
+        for _ in 0..num_data_blocks {
+            let fence_pointer = LookupKey::decode_from_reader(&mut reader)?;
+            let block_handle = BlockHandle::decode_from_reader(&mut reader)?;
+            index_block.add(fence_pointer, block_handle);
         }
+        Ok(index_block)
+    }
+}
+
+/// a handle locating an index block in the sstable file.
+pub struct IndexBlockHandle {
+    /// the fence pointer of the last data block indexed by the index block.
+    pub fence_pointer: LookupKey,
+    /// offset of the index block in the sstable file.
+    pub offset: usize,
+    /// number of data blocks indexed by the index block.
+    pub num_data_blocks: usize,
+}
+
+impl IndexBlockHandle {
+    /// return the size in bytes of the encoded handle having the given fence pointer.
+    pub fn size_of(fence_pointer: &LookupKey) -> usize {
+        fence_pointer.size() + 2 * 8
+    }
+
+    pub fn size(&self) -> usize {
+        IndexBlockHandle::size_of(&self.fence_pointer)
     }
 
     pub fn encode_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.fence_pointer.encode_to_bytes();
+        bytes.write_fixedint(self.offset as u64).unwrap();
+        bytes.write_fixedint(self.num_data_blocks as u64).unwrap();
+        bytes
+    }
+
+    pub fn decode_from_reader(reader: &mut &[u8]) -> Result<Self, io::Error> {
+        Ok(Self {
+            fence_pointer: LookupKey::decode_from_reader(reader)?,
+            offset: reader.read_fixedint::<u64>()? as usize,
+            num_data_blocks: reader.read_fixedint::<u64>()? as usize,
+        })
+    }
+}
+
+/// the top-level index of an sstable.
+/// the index of an sstable is partitioned into multiple index blocks, and the top-level index block
+/// stores a handle for each of them. a lookup first searches the top-level index block and then
+/// reads only the index block that might contain the key.
+/// the top-level index block may span multiple blocks.
+pub struct TopIndexBlock {
+    handles: Vec<IndexBlockHandle>,
+    /// size in bytes of the encoded top-level index block without padding.
+    size: usize,
+}
+
+impl TopIndexBlock {
+    pub fn new() -> Self {
+        Self {
+            handles: Vec::new(),
+            size: 0,
+        }
+    }
+
+    pub fn add(&mut self, handle: IndexBlockHandle) {
+        self.size += handle.size();
+        self.handles.push(handle);
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// return the number of blocks the encoded top-level index block spans.
+    pub fn num_blocks(&self, block_size: usize) -> usize {
+        num_blocks_of(self.size, block_size)
+    }
+
+    pub fn num_index_blocks(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn get(&self, i: usize) -> &IndexBlockHandle {
+        &self.handles[i]
+    }
+
+    /// returns Some(i) if the key might exist in the data blocks indexed by the i-th index block.
+    pub fn binary_search(&self, lookup_key: &LookupKey) -> Option<usize> {
+        lower_bound(
+            self.handles.len(),
+            |i| &self.handles[i].fence_pointer,
+            lookup_key,
+        )
+    }
+
+    pub fn encode_to_bytes(&self, block_size: usize) -> Vec<u8> {
         let mut bytes = Vec::new();
-        for fence_pointer in self.fence_pointers.iter() {
-            bytes.append(&mut fence_pointer.encode_to_bytes());
+        for handle in self.handles.iter() {
+            bytes.append(&mut handle.encode_to_bytes());
         }
-        maybe_pad(&mut bytes);
-        assert_eq!(bytes.len(), BLOCK_SIZE);
+        // the checksum is only placed at the tail of the last block.
+        pad_and_checksum(&mut bytes, self.num_blocks(block_size) * block_size);
         bytes
     }
 
-    pub fn decode_from_bytes(bytes: &Vec<u8>, num_data_blocks: usize) -> Result<Self, io::Error> {
-        let mut index_block = IndexBlock::new();
-        for i in 0..num_data_blocks {
-            let offset = i * TABLE_KEY_SIZE;
-            // a fence pointer is literally the max table key of a data block.
-            let fence_pointer =
-                TableKey::decode_from_bytes(&bytes[offset..offset + TABLE_KEY_SIZE].to_owned())?;
-            index_block.add(fence_pointer);
+    pub fn decode_from_bytes(bytes: &[u8], num_index_blocks: usize) -> Result<Self, io::Error> {
+        let mut reader = bytes;
+        let mut top_index_block = TopIndexBlock::new();
+        for _ in 0..num_index_blocks {
+            top_index_block.add(IndexBlockHandle::decode_from_reader(&mut reader)?);
         }
-        Ok(index_block)
+        Ok(top_index_block)
     }
 }
 
+/// the tail of a footer.
+pub struct FooterTail {
+    pub format_version: u32,
+    pub block_size: usize,
+    pub magic: u64,
+}
+
 pub struct Footer {
+    /// format version of the sstable.
+    pub format_version: u32,
+    /// the filter block is either a full filter or a filter index block depending on the filter type.
+    // sstables of format versions before 3 only have full filters.
+    pub filter_type: FilterType,
+    /// size in bytes of each block in the sstable.
+    pub block_size: usize,
     pub num_table_keys: usize,
+    pub num_data_blocks: usize,
+    pub num_index_blocks: usize,
     pub filter_block_offset: usize,
+    /// offset of the top-level index block which spans the blocks up to the footer.
     pub index_block_offset: usize,
     pub min_table_key: TableKey,
     pub max_table_key: TableKey,
 }
 
 impl Footer {
-    pub fn new(
-        num_table_keys: usize,
-        filter_block_offset: usize,
-        index_block_offset: usize,
-        min_table_key: TableKey,
-        max_table_key: TableKey,
-    ) -> Self {
-        Self {
-            num_table_keys,
-            filter_block_offset,
-            index_block_offset,
-            min_table_key,
-            max_table_key,
-        }
-    }
-
     pub fn encode_to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.write_fixedint(self.num_table_keys).unwrap();
+        bytes.write_fixedint(self.num_data_blocks).unwrap();
+        bytes.write_fixedint(self.num_index_blocks).unwrap();
         bytes.write_fixedint(self.filter_block_offset).unwrap();
         bytes.write_fixedint(self.index_block_offset).unwrap();
+        bytes.write_fixedint(self.filter_type as u8).unwrap();
         bytes.append(&mut self.min_table_key.encode_to_bytes());
         bytes.append(&mut self.max_table_key.encode_to_bytes());
-        maybe_pad(&mut bytes);
-        assert_eq!(bytes.len(), BLOCK_SIZE);
+        // | fields | padding | format version (4B) | block size (8B) | magic (8B) | crc32c (4B) |
+        assert!(bytes.len() + FOOTER_TAIL_SIZE <= self.block_size);
+        bytes.resize(self.block_size - FOOTER_TAIL_SIZE, 0);
+        bytes.write_fixedint(self.format_version).unwrap();
+        bytes.write_fixedint(self.block_size as u64).unwrap();
+        bytes.write_fixedint(SSTABLE_MAGIC).unwrap();
+        pad_and_checksum(&mut bytes, self.block_size);
         bytes
     }
 
-    pub fn decode_from_bytes(bytes: &Vec<u8>) -> Result<Self, io::Error> {
-        let mut reader = bytes.as_slice();
+    /// decode the tail of the footer.
+    /// the magic number is not checked.
+    pub fn decode_tail(bytes: &[u8]) -> Result<FooterTail, io::Error> {
+        let mut reader = &bytes[bytes.len() - FOOTER_TAIL_SIZE..];
+        Ok(FooterTail {
+            format_version: reader.read_fixedint()?,
+            block_size: reader.read_fixedint::<u64>()? as usize,
+            magic: reader.read_fixedint()?,
+        })
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let tail = Footer::decode_tail(bytes)?;
+        if tail.magic != SSTABLE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad magic number",
+            ));
+        }
+        let mut reader = bytes;
 
         let num_table_keys = reader.read_fixedint()?;
+        let num_data_blocks = reader.read_fixedint()?;
+        let num_index_blocks = reader.read_fixedint()?;
         let filter_block_offset = reader.read_fixedint()?;
         let index_block_offset = reader.read_fixedint()?;
-        let offset = 3 * mem::size_of::<usize>();
-        let min_table_key =
-            TableKey::decode_from_bytes(&bytes[offset..offset + TABLE_KEY_SIZE].to_owned())?;
-        let max_table_key = TableKey::decode_from_bytes(
-            &bytes[offset + TABLE_KEY_SIZE..offset + 2 * TABLE_KEY_SIZE].to_owned(),
-        )?;
+        let filter_type = if tail.format_version >= 3 {
+            match reader.read_fixedint::<u8>()? {
+                0 => FilterType::Full,
+                1 => FilterType::Partitioned,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unknown filter type",
+                    ))
+                }
+            }
+        } else {
+            FilterType::Full
+        };
+        let min_table_key = TableKey::decode_from_reader(&mut reader)?;
+        let max_table_key = TableKey::decode_from_reader(&mut reader)?;
 
         Ok(Self {
+            format_version: tail.format_version,
+            filter_type,
+            block_size: tail.block_size,
             num_table_keys,
+            num_data_blocks,
+            num_index_blocks,
             filter_block_offset,
             index_block_offset,
             min_table_key,
@@ -283,49 +912,251 @@ impl Footer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::types::*;
+    use crate::storage::bloom_filter;
 
     #[test]
     fn data_block_encode_decode() {
-        let mut data_block = DataBlock::new();
+        let mut builder = DataBlockBuilder::new();
         let num_table_keys = 200;
         for i in 0..num_table_keys {
             let table_key = TableKey::identity(i);
-            data_block.add(table_key);
+            builder.add(table_key);
         }
-
-        let bytes = data_block.encode_to_bytes();
-        let decoded_data_block =
-            DataBlock::decode_from_bytes(&bytes, num_table_keys as usize).unwrap();
-
         assert_eq!(
-            data_block.max_table_key.as_ref().unwrap().clone(),
-            decoded_data_block.max_table_key.as_ref().unwrap().clone()
+            builder.fence_pointer(),
+            TableKey::identity(num_table_keys - 1).as_lookup_key()
         );
 
+        let bytes = builder.encode_to_bytes();
+        assert_eq!(bytes.len(), builder.size());
+        let data_block = DataBlock::decode_from_bytes(&bytes).unwrap();
+
+        let mut iter = data_block.iter();
         for i in 0..num_table_keys {
-            assert_eq!(
-                data_block.table_keys[i as usize],
-                decoded_data_block.table_keys[i as usize]
-            );
+            assert_eq!(iter.next(), Some(TableKey::identity(i)));
         }
+        assert!(iter.next().is_none());
+        assert!(!iter.is_corrupted());
+    }
+
+    #[test]
+    fn data_block_prefix_compression() {
+        let table_key = |i: usize| TableKey {
+            user_key: format!("user_key_with_a_long_shared_prefix_{:08}", i).into_bytes(),
+            seq_num: i,
+            write_type: WriteType::Put,
+            user_val: vec![7; 8],
+        };
+
+        let mut builder = DataBlockBuilder::new();
+        let mut raw_size = 0;
+        for i in 0..100 {
+            raw_size += table_key(i).size();
+            builder.add(table_key(i));
+        }
+        // shared prefixes are stored once per restart interval.
+        assert!(builder.size() < raw_size / 2);
+        assert_eq!(builder.restarts.len(), 100_usize.div_ceil(RESTART_INTERVAL));
+    }
+
+    #[test]
+    fn data_block_seek() {
+        // each user key has several versions which may span restart points.
+        let mut table_keys = Vec::new();
+        for i in 0..100 {
+            for seq_num in (0..5).rev() {
+                table_keys.push(TableKey {
+                    user_key: format!("key{:04}", i * 2).into_bytes(),
+                    seq_num: i * 10 + seq_num,
+                    write_type: WriteType::Put,
+                    user_val: vec![seq_num as u8],
+                });
+            }
+        }
+
+        let mut builder = DataBlockBuilder::new();
+        for table_key in table_keys.iter() {
+            builder.add(table_key.clone());
+        }
+        let data_block = DataBlock::decode_from_bytes(&builder.encode_to_bytes()).unwrap();
+
+        // seek every table key.
+        for table_key in table_keys.iter() {
+            let mut iter = data_block.iter();
+            iter.seek(&table_key.as_lookup_key());
+            assert_eq!(iter.curr().as_ref(), Some(table_key));
+        }
+
+        // a lookup key sees the latest version not newer than its seq num.
+        let mut iter = data_block.iter();
+        iter.seek(&LookupKey::new(b"key0010", 53));
+        assert_eq!(iter.curr().unwrap().seq_num, 53);
+        iter.seek(&LookupKey::new(b"key0010", 100));
+        assert_eq!(iter.curr().unwrap().seq_num, 54);
+
+        // a missing user key is positioned at the next user key.
+        iter.seek(&LookupKey::new(b"key0011", 100));
+        assert_eq!(iter.curr().unwrap().user_key, b"key0012".to_vec());
+
+        // seeking beyond the last table key exhausts the iterator.
+        iter.seek(&LookupKey::new(b"key9999", 0));
+        assert!(!iter.valid());
+
+        // the iterator continues from the seeked position.
+        iter.seek(&LookupKey::new(b"key0000", 100));
+        for table_key in table_keys.iter().skip(1) {
+            assert_eq!(iter.next().as_ref(), Some(table_key));
+        }
+    }
+
+    #[test]
+    fn data_block_reverse() {
+        let mut builder = DataBlockBuilder::new();
+        let num_table_keys = 100;
+        for i in 0..num_table_keys {
+            builder.add(TableKey::identity(i));
+        }
+        let data_block = DataBlock::decode_from_bytes(&builder.encode_to_bytes()).unwrap();
+
+        // iterate backwards across restart points.
+        let mut iter = data_block.iter();
+        iter.seek_to_last();
+        for i in (0..num_table_keys).rev() {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.prev();
+        }
+        assert!(!iter.valid());
+        assert!(iter.prev().is_none());
+
+        // the direction could be switched in the middle of a scan.
+        iter.seek(&TableKey::identity(50).as_lookup_key());
+        assert_eq!(iter.prev(), Some(TableKey::identity(49)));
+        assert_eq!(iter.prev(), Some(TableKey::identity(48)));
+        assert_eq!(iter.next(), Some(TableKey::identity(49)));
+        assert_eq!(iter.prev(), Some(TableKey::identity(48)));
+
+        // seek the last table key less than or equal to the lookup key.
+        iter.seek_for_prev(&TableKey::identity(16).as_lookup_key());
+        assert_eq!(iter.curr(), Some(TableKey::identity(16)));
+        iter.seek_for_prev(&LookupKey::new(&16_i32.to_be_bytes(), 0));
+        assert_eq!(iter.curr(), Some(TableKey::identity(16)));
+        iter.seek_for_prev(&LookupKey::new(&17_i32.to_be_bytes(), usize::MAX));
+        assert_eq!(iter.curr(), Some(TableKey::identity(16)));
+        iter.seek_for_prev(&TableKey::identity(1000).as_lookup_key());
+        assert_eq!(iter.curr(), Some(TableKey::identity(num_table_keys - 1)));
+        iter.seek_for_prev(&LookupKey::new(b"", 0));
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn data_block_corrupted() {
+        let mut builder = DataBlockBuilder::new();
+        for i in 0..50 {
+            builder.add(TableKey::identity(i));
+        }
+        let mut bytes = builder.encode_to_bytes();
+
+        // a truncated block has an invalid restart point array.
+        assert!(DataBlock::decode_from_bytes(&bytes[..2]).is_err());
+        let num_restarts_offset = bytes.len() - 4;
+        assert!(DataBlock::decode_from_bytes(&bytes[num_restarts_offset..]).is_err());
+
+        // an entry whose shared len exceeds the previous key is malformed.
+        bytes[0] = 100;
+        let data_block = DataBlock::decode_from_bytes(&bytes).unwrap();
+        let mut iter = data_block.iter();
+        assert!(iter.next().is_none());
+        assert!(iter.is_corrupted());
+    }
+
+    #[test]
+    fn compress_decompress_block() {
+        let mut builder = DataBlockBuilder::new();
+        for i in 0..200 {
+            builder.add(TableKey::identity(i));
+        }
+        let bytes = builder.encode_to_bytes();
+
+        for compression_type in [CompressionType::None, CompressionType::Lz4] {
+            let block = compress_block(&bytes, compression_type);
+            assert_eq!(decompress_block(&block, FORMAT_VERSION).unwrap(), bytes);
+        }
+
+        // lz4 blocks of format version 3 are not prefixed with the uncompressed size.
+        let mut block = lz4_flex::block::compress(&bytes);
+        block.push(CompressionType::Lz4 as u8);
+        let checksum = crc32c(&block);
+        block.write_fixedint(checksum).unwrap();
+        assert_eq!(decompress_block(&block, 3).unwrap(), bytes);
+
+        // incompressible bytes are stored uncompressed.
+        let bytes: Vec<u8> = (0..=255).collect();
+        let block = compress_block(&bytes, CompressionType::Lz4);
+        assert_eq!(block.len(), bytes.len() + BLOCK_TRAILER_SIZE);
+        assert_eq!(
+            block[block.len() - BLOCK_TRAILER_SIZE],
+            CompressionType::None as u8
+        );
+
+        // a flipped byte fails the checksum verification.
+        let mut block = compress_block(&bytes, CompressionType::None);
+        assert!(verify_checksum(&block));
+        block[10] ^= 1;
+        assert!(!verify_checksum(&block));
+
+        // an unknown compression type is rejected.
+        let mut block = compress_block(&bytes, CompressionType::None);
+        let trailer_offset = block.len() - BLOCK_TRAILER_SIZE;
+        block[trailer_offset] = 42;
+        assert!(decompress_block(&block, FORMAT_VERSION).is_err());
     }
 
     #[test]
     fn filter_block_encode_decode() {
-        let mut filter_block = FilterBlock::new();
+        let mut builder = FilterBlockBuilder::new(10);
         let num_table_keys = 500;
         for i in 0..num_table_keys {
             let table_key = TableKey::identity(i);
-            filter_block.insert(table_key.user_key);
+            // versions of the same user key are counted once.
+            builder.insert(&table_key.user_key);
+            builder.insert(&table_key.user_key);
         }
+        assert_eq!(builder.num_keys(), 500);
+
+        let bytes1 = builder.finish().encode_to_bytes(DEFAULT_BLOCK_SIZE);
+        assert_eq!(bytes1.len(), DEFAULT_BLOCK_SIZE);
+        assert!(verify_checksum(&bytes1));
+        let filter_block = FilterBlock::decode_from_bytes(&bytes1).unwrap();
+        let bytes2 = filter_block.encode_to_bytes(DEFAULT_BLOCK_SIZE);
+        assert_eq!(bytes1, bytes2);
 
-        let bytes1 = filter_block.encode_to_bytes();
-        let bytes2 = FilterBlock::decode_from_bytes(&bytes1).encode_to_bytes();
+        for i in 0..num_table_keys {
+            assert!(filter_block.maybe_contain(&TableKey::identity(i).user_key));
+        }
 
-        for i in 0..BLOCK_SIZE {
-            assert_eq!(bytes1[i], bytes2[i]);
+        // a large filter spans multiple blocks.
+        let mut builder = FilterBlockBuilder::new(10);
+        for i in 0..10000 {
+            builder.insert(&TableKey::identity(i).user_key);
         }
+        let bytes = builder.finish().encode_to_bytes(DEFAULT_BLOCK_SIZE);
+        assert_eq!(
+            bytes.len(),
+            num_blocks_of(BloomFilter::encoded_size(10000, 10), DEFAULT_BLOCK_SIZE)
+                * DEFAULT_BLOCK_SIZE
+        );
+        assert!(bytes.len() > DEFAULT_BLOCK_SIZE);
+        let filter_block = FilterBlock::decode_from_bytes(&bytes).unwrap();
+        for i in 0..10000 {
+            assert!(filter_block.maybe_contain(&TableKey::identity(i).user_key));
+        }
+
+        // a filter block of format version 1 has a fixed-size bloom filter.
+        let mut bloom_filter = BloomFilter::with_num_bits(10000, 7);
+        bloom_filter.insert(b"key");
+        let bytes = bloom_filter.encode_to_bytes();
+        let filter_block = FilterBlock::decode_v1(&bytes[bloom_filter::HEADER_SIZE..]).unwrap();
+        assert!(filter_block.maybe_contain(b"key"));
     }
 
     #[test]
@@ -333,14 +1164,16 @@ mod tests {
         let mut index_block = IndexBlock::new();
         let num_table_keys: usize = 10;
         for i in 0..num_table_keys {
-            index_block.add(TableKey::new(
-                i as UserKey,
-                i,
-                WriteType::Put,
-                i as UserValue,
-            ));
+            let block_handle = BlockHandle {
+                offset: i * 100,
+                size: 100 - BLOCK_TRAILER_SIZE,
+            };
+            index_block.add(
+                LookupKey::new(format!("key{}", i).as_bytes(), i),
+                block_handle,
+            );
         }
-        let bytes = index_block.encode_to_bytes();
+        let bytes = index_block.encode_to_bytes(DEFAULT_BLOCK_SIZE);
         let decoded_index_block = IndexBlock::decode_from_bytes(&bytes, num_table_keys).unwrap();
 
         assert_eq!(
@@ -353,6 +1186,52 @@ mod tests {
                 index_block.fence_pointers[i],
                 decoded_index_block.fence_pointers[i]
             );
+            assert_eq!(
+                index_block.block_handles[i],
+                decoded_index_block.block_handles[i]
+            );
         }
     }
+
+    #[test]
+    fn footer_encode_decode() {
+        let block_size = 16 * 1024;
+        let footer = Footer {
+            format_version: FORMAT_VERSION,
+            filter_type: FilterType::Partitioned,
+            block_size,
+            num_table_keys: 100,
+            num_data_blocks: 10,
+            num_index_blocks: 1,
+            filter_block_offset: 10 * block_size,
+            index_block_offset: 12 * block_size,
+            min_table_key: TableKey::new(b"a", 1, WriteType::Empty, b""),
+            max_table_key: TableKey::new(b"z", 2, WriteType::Empty, b""),
+        };
+        let bytes = footer.encode_to_bytes();
+        assert_eq!(bytes.len(), block_size);
+        assert!(verify_checksum(&bytes));
+        let tail = Footer::decode_tail(&bytes).unwrap();
+        assert_eq!(tail.format_version, FORMAT_VERSION);
+        assert_eq!(tail.block_size, block_size);
+        assert_eq!(tail.magic, SSTABLE_MAGIC);
+
+        let decoded_footer = Footer::decode_from_bytes(&bytes).unwrap();
+        assert_eq!(decoded_footer.format_version, FORMAT_VERSION);
+        assert_eq!(decoded_footer.filter_type, FilterType::Partitioned);
+        assert_eq!(decoded_footer.block_size, block_size);
+        assert_eq!(decoded_footer.num_table_keys, 100);
+        assert_eq!(decoded_footer.num_data_blocks, 10);
+        assert_eq!(decoded_footer.num_index_blocks, 1);
+        assert_eq!(decoded_footer.filter_block_offset, 10 * block_size);
+        assert_eq!(decoded_footer.index_block_offset, 12 * block_size);
+        assert_eq!(decoded_footer.min_table_key, footer.min_table_key);
+        assert_eq!(decoded_footer.max_table_key, footer.max_table_key);
+
+        // a footer with a bad magic number is rejected.
+        let mut bytes = bytes;
+        let magic_offset = block_size - CHECKSUM_SIZE - 8;
+        bytes[magic_offset] ^= 1;
+        assert!(Footer::decode_from_bytes(&bytes).is_err());
+    }
 }
@@ -0,0 +1,119 @@
+use super::block::DataBlock;
+use super::lru::Lru;
+use crate::util::types::{CacheStats, FileNum};
+use std::sync::Mutex;
+
+/// a cached data block is identified by the file number of its sstable and its offset in the file.
+type CacheKey = (FileNum, usize);
+
+/// a block cache shared by all sstables of a database.
+/// decoded data blocks are cached and the least recently used ones are evicted once the total size
+/// of cached blocks exceeds the capacity in bytes.
+pub struct BlockCache {
+    lru: Mutex<Lru<CacheKey, DataBlock>>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Mutex::new(Lru::new(capacity, DataBlock::size)),
+        }
+    }
+
+    /// return the cached data block at the offset of the sstable file if any.
+    pub fn get(&self, file_num: FileNum, offset: usize) -> Option<DataBlock> {
+        self.lru.lock().unwrap().get(&(file_num, offset))
+    }
+
+    /// cache the data block at the offset of the sstable file.
+    /// a data block larger than the capacity is not cached.
+    pub fn insert(&self, file_num: FileNum, offset: usize, data_block: DataBlock) {
+        self.lru
+            .lock()
+            .unwrap()
+            .insert((file_num, offset), data_block);
+    }
+
+    /// evict all cached data blocks of the sstable file.
+    /// called when the sstable file is deleted.
+    pub fn evict_file(&self, file_num: FileNum) {
+        self.lru
+            .lock()
+            .unwrap()
+            .remove_if(|(key_file_num, _)| *key_file_num == file_num);
+    }
+
+    /// return the statistics of the cache where the usage is the total size in bytes of cached
+    /// data blocks.
+    pub fn stats(&self) -> CacheStats {
+        self.lru.lock().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::block::DataBlockBuilder;
+    use crate::storage::iterator::TableKeyIterator;
+    use crate::storage::keys::TableKey;
+
+    fn make_data_block(i: i32) -> DataBlock {
+        let mut builder = DataBlockBuilder::new();
+        builder.add(TableKey::identity(i));
+        DataBlock::decode_from_bytes(&builder.encode_to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let charge = make_data_block(0).size();
+        let cache = BlockCache::new(3 * charge);
+
+        for i in 0..3 {
+            cache.insert(1, i as usize, make_data_block(i));
+        }
+        assert_eq!(cache.stats().usage, 3 * charge);
+
+        // access the block at offset 0 so that the block at offset 1 becomes the lru one.
+        let mut iter = cache.get(1, 0).unwrap().iter();
+        assert_eq!(iter.next(), Some(TableKey::identity(0)));
+
+        cache.insert(1, 3, make_data_block(3));
+        assert_eq!(cache.stats().usage, 3 * charge);
+        assert!(cache.get(1, 1).is_none());
+        for i in [0, 2, 3] {
+            assert!(cache.get(1, i).is_some());
+        }
+
+        // blocks are keyed by both the file number and the offset.
+        assert!(cache.get(2, 0).is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 2);
+
+        // a block larger than the capacity is not cached.
+        let cache = BlockCache::new(charge - 1);
+        cache.insert(1, 0, make_data_block(0));
+        assert!(cache.get(1, 0).is_none());
+        assert_eq!(cache.stats().usage, 0);
+    }
+
+    #[test]
+    fn evict_file() {
+        let cache = BlockCache::new(1024 * 1024);
+        for file_num in 0..3 {
+            for offset in 0..10 {
+                cache.insert(file_num, offset, make_data_block(offset as i32));
+            }
+        }
+        let usage = cache.stats().usage;
+
+        cache.evict_file(1);
+        assert_eq!(cache.stats().usage, usage / 3 * 2);
+        for offset in 0..10 {
+            assert!(cache.get(0, offset).is_some());
+            assert!(cache.get(1, offset).is_none());
+            assert!(cache.get(2, offset).is_some());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::util::types::CompressionType;
+use std::io;
+
+/// size in bytes of the uncompressed size prefixed to lz4 compressed bytes.
+const SIZE_PREFIX_SIZE: usize = 4;
+/// lz4 outputs at most this many bytes per input byte. it bounds the output of the bytes whose
+/// uncompressed size is unknown or corrupted.
+const LZ4_MAX_RATIO: usize = 255;
+
+/// compress the bytes with the given compression type.
+/// lz4 compressed bytes are prefixed with the uncompressed size.
+/// | uncompressed size (4B) | lz4 block |
+pub fn compress(bytes: &[u8], compression_type: CompressionType) -> Vec<u8> {
+    match compression_type {
+        CompressionType::None => bytes.to_vec(),
+        CompressionType::Lz4 => lz4_flex::block::compress_prepend_size(bytes),
+    }
+}
+
+/// decompress the bytes compressed with the given compression type.
+/// fails if the decompressed bytes do not match the uncompressed size prefixed by `compress`.
+pub fn decompress(bytes: &[u8], compression_type: CompressionType) -> Result<Vec<u8>, io::Error> {
+    match compression_type {
+        CompressionType::None => Ok(bytes.to_vec()),
+        CompressionType::Lz4 => {
+            let (size, encoded) = bytes
+                .split_first_chunk::<SIZE_PREFIX_SIZE>()
+                .ok_or_else(|| corrupted("truncated size"))?;
+            let size = u32::from_le_bytes(*size) as usize;
+            // a corrupted size must not cause a huge allocation.
+            if size > lz4_max_output_size(encoded) {
+                return Err(corrupted("invalid size"));
+            }
+            let decompressed = lz4_decompress(encoded, size)?;
+            if decompressed.len() != size {
+                return Err(corrupted("size mismatch"));
+            }
+            Ok(decompressed)
+        }
+    }
+}
+
+/// decompress the bytes compressed by the builds writing sstables of format version 3 or lower,
+/// where lz4 compressed bytes are not prefixed with the uncompressed size.
+pub fn decompress_unsized(
+    bytes: &[u8],
+    compression_type: CompressionType,
+) -> Result<Vec<u8>, io::Error> {
+    match compression_type {
+        CompressionType::None => Ok(bytes.to_vec()),
+        CompressionType::Lz4 => lz4_decompress(bytes, lz4_max_output_size(bytes)),
+    }
+}
+
+fn corrupted(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn lz4_max_output_size(encoded: &[u8]) -> usize {
+    encoded.len().saturating_mul(LZ4_MAX_RATIO)
+}
+
+/// decompress the lz4 block. fails if the output exceeds max_size bytes.
+fn lz4_decompress(encoded: &[u8], max_size: usize) -> Result<Vec<u8>, io::Error> {
+    lz4_flex::block::decompress(encoded, max_size)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn check_roundtrip(bytes: &[u8]) -> Vec<u8> {
+        let encoded = compress(bytes, CompressionType::Lz4);
+        assert_eq!(decompress(&encoded, CompressionType::Lz4).unwrap(), bytes);
+        encoded
+    }
+
+    #[test]
+    fn lz4_roundtrip() {
+        // short inputs are stored as literals only.
+        check_roundtrip(b"");
+        check_roundtrip(b"a");
+        check_roundtrip(b"abcdabcdabcd");
+
+        // repetitive inputs are compressed well, including long overlapping matches.
+        let bytes = vec![7; 10000];
+        assert!(check_roundtrip(&bytes).len() < 100);
+
+        let mut bytes = Vec::new();
+        for i in 0..1000 {
+            bytes.extend_from_slice(format!("key{:08}", i).as_bytes());
+        }
+        assert!(check_roundtrip(&bytes).len() < bytes.len() / 2);
+
+        // random inputs are barely compressible but still roundtrip.
+        let mut rng = rand::thread_rng();
+        for len in [13, 100, 4096, 70000] {
+            let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            check_roundtrip(&bytes);
+        }
+
+        // lz4 blocks without the size prefix are still readable.
+        let encoded = lz4_flex::block::compress(&bytes);
+        assert_eq!(
+            decompress_unsized(&encoded, CompressionType::Lz4).unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn lz4_corrupted() {
+        let bytes = vec![7; 10000];
+        let encoded = compress(&bytes, CompressionType::Lz4);
+
+        // a truncated input is not decompressable.
+        assert!(decompress(&encoded[..encoded.len() - 1], CompressionType::Lz4).is_err());
+        assert!(decompress(&[], CompressionType::Lz4).is_err());
+
+        // an offset pointing before the start of the output is invalid.
+        assert!(decompress(&[1, 0, 0, 0, 0x10, b'a', 2, 0], CompressionType::Lz4).is_err());
+
+        // the output never exceeds the prefixed size, and it must match the prefixed size.
+        for size in [100, bytes.len() - 1, bytes.len() + 1] {
+            let mut corrupted = encoded.clone();
+            corrupted[..SIZE_PREFIX_SIZE].copy_from_slice(&(size as u32).to_le_bytes());
+            assert!(decompress(&corrupted, CompressionType::Lz4).is_err());
+        }
+
+        // a huge prefixed size is rejected before anything is allocated.
+        let mut corrupted = encoded.clone();
+        corrupted[..SIZE_PREFIX_SIZE].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress(&corrupted, CompressionType::Lz4).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
 
 use super::keys::{LookupKey, TableKey};
+use crate::util::error::Error;
 
 // note, to use a table key iterator, you must first call `next` once to init the iterator.
 // then you must use the iterator with such pattern:
@@ -9,24 +10,218 @@ use super::keys::{LookupKey, TableKey};
 //     do something ...
 //     iter.next();
 // }
+// alternatively, an iterator could be positioned by `seek`, `seek_to_last` or `seek_for_prev`.
+// `next` and `prev` could be interleaved to switch the direction in the middle of a scan.
+// an iterator moved past either end becomes invalid and it must be repositioned by a seek.
 pub trait TableKeyIterator {
     /// `seek` will move the cursor to point to the greater than or equal table key.
     /// a greater table key could be a table key with a higher user key or with the identical
     /// user key but having a higher sequence number.
     fn seek(&mut self, lookup_key: &LookupKey);
+    /// move the cursor to point to the last table key.
+    fn seek_to_last(&mut self);
+    /// move the cursor to point to the last table key less than or equal to the lookup key.
+    fn seek_for_prev(&mut self, lookup_key: &LookupKey) {
+        self.seek(lookup_key);
+        if self.status().is_err() {
+            return;
+        }
+        match self.curr() {
+            Some(table_key) if table_key != lookup_key.as_table_key() => {
+                self.prev();
+            }
+            Some(_) => {}
+            // all table keys are less than the lookup key.
+            None => self.seek_to_last(),
+        }
+    }
     fn next(&mut self) -> Option<TableKey>;
+    /// move the cursor to point to the previous table key.
+    /// the iterator becomes invalid if there's no previous table key, and an invalid iterator
+    /// stays invalid.
+    fn prev(&mut self) -> Option<TableKey>;
     fn curr(&self) -> Option<TableKey>;
     fn valid(&self) -> bool;
+    /// return the error encountered by the iterator if any.
+    /// an iterator becomes invalid once it encounters an error, so `status` shall be checked
+    /// to tell an error from exhaustion.
+    fn status(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub type TableKeyIteratorType<'a> = Box<dyn TableKeyIterator + 'a>;
 
+/// an iterator merging the table keys of a set of iterators in both directions.
+// the set of iterators is small, e.g. the runs of a level or the levels of a database, and hence
+// the iterator pointing to the current table key is found by a linear scan, which is also able to
+// find the greatest table key on reverse iterations unlike a binary heap of one order.
+pub struct MergingIterator<'a> {
+    iters: Vec<TableKeyIteratorType<'a>>,
+    /// the current table key of each iterator, cached to avoid cloning on comparisons.
+    heads: Vec<Option<TableKey>>,
+    /// index of the iterator pointing to the current table key if any.
+    curr_idx: Option<usize>,
+    /// true if the iterator moves forward, i.e. other iterators point to greater table keys.
+    /// otherwise, other iterators point to less table keys.
+    forward: bool,
+    /// true if the iterators have been positioned by either `next` or a seek.
+    started: bool,
+    /// the error encountered by any iterator if any.
+    status: Result<(), Error>,
+}
+
+impl<'a> MergingIterator<'a> {
+    pub fn new(iters: Vec<TableKeyIteratorType<'a>>) -> Self {
+        let heads = vec![None; iters.len()];
+        Self {
+            iters,
+            heads,
+            curr_idx: None,
+            forward: true,
+            started: false,
+            status: Ok(()),
+        }
+    }
+
+    /// refresh the cached current table key of the i-th iterator and check its status.
+    /// return false if an error is encountered.
+    fn refresh_head(&mut self, i: usize) -> bool {
+        self.heads[i] = self.iters[i].curr();
+        if let Err(err) = self.iters[i].status() {
+            self.status = Err(err);
+            self.curr_idx = None;
+            return false;
+        }
+        true
+    }
+
+    fn refresh_heads(&mut self) -> bool {
+        (0..self.iters.len()).all(|i| self.refresh_head(i))
+    }
+
+    /// point to the iterator with the least table key if forward, or with the greatest otherwise.
+    fn find_curr(&mut self) {
+        let heads = self.heads.iter().enumerate();
+        let curr = if self.forward {
+            heads
+                .filter_map(|(i, head)| head.as_ref().map(|head| (i, head)))
+                .min_by(|(_, a), (_, b)| a.cmp(b))
+        } else {
+            heads
+                .filter_map(|(i, head)| head.as_ref().map(|head| (i, head)))
+                .max_by(|(_, a), (_, b)| a.cmp(b))
+        };
+        self.curr_idx = curr.map(|(i, _)| i);
+    }
+
+    /// position all iterators by the operation and then point to the current table key.
+    fn position(&mut self, forward: bool, op: impl Fn(&mut TableKeyIteratorType<'a>)) {
+        if self.status.is_err() {
+            return;
+        }
+        self.started = true;
+        self.forward = forward;
+        self.iters.iter_mut().for_each(op);
+        if self.refresh_heads() {
+            self.find_curr();
+        }
+    }
+}
+
+impl<'a> TableKeyIterator for MergingIterator<'a> {
+    fn seek(&mut self, lookup_key: &LookupKey) {
+        self.position(true, |iter| iter.seek(lookup_key));
+    }
+
+    fn seek_to_last(&mut self) {
+        self.position(false, |iter| iter.seek_to_last());
+    }
+
+    fn next(&mut self) -> Option<TableKey> {
+        if !self.started {
+            self.position(true, |iter| {
+                iter.next();
+            });
+            return self.curr();
+        }
+        let curr_idx = self.curr_idx?;
+
+        if !self.forward {
+            // switch the direction: move other iterators to the table keys after the current one.
+            let table_key = self.heads[curr_idx].clone().unwrap();
+            let lookup_key = table_key.as_lookup_key();
+            for (i, iter) in self.iters.iter_mut().enumerate() {
+                if i != curr_idx {
+                    iter.seek(&lookup_key);
+                    if iter.curr().as_ref() == Some(&table_key) {
+                        iter.next();
+                    }
+                }
+            }
+            self.forward = true;
+            if !self.refresh_heads() {
+                return None;
+            }
+        }
+
+        self.iters[curr_idx].next();
+        if self.refresh_head(curr_idx) {
+            self.find_curr();
+        }
+        self.curr()
+    }
+
+    fn prev(&mut self) -> Option<TableKey> {
+        if self.status.is_err() {
+            return None;
+        }
+        let curr_idx = self.curr_idx?;
+
+        if self.forward {
+            // switch the direction: move other iterators to the table keys before the current one.
+            let table_key = self.heads[curr_idx].clone().unwrap();
+            let lookup_key = table_key.as_lookup_key();
+            for (i, iter) in self.iters.iter_mut().enumerate() {
+                if i != curr_idx {
+                    iter.seek_for_prev(&lookup_key);
+                    if iter.curr().as_ref() == Some(&table_key) {
+                        iter.prev();
+                    }
+                }
+            }
+            self.forward = false;
+            if !self.refresh_heads() {
+                return None;
+            }
+        }
+
+        self.iters[curr_idx].prev();
+        if self.refresh_head(curr_idx) {
+            self.find_curr();
+        }
+        self.curr()
+    }
+
+    fn curr(&self) -> Option<TableKey> {
+        self.curr_idx.and_then(|i| self.heads[i].clone())
+    }
+
+    fn valid(&self) -> bool {
+        self.curr_idx.is_some()
+    }
+
+    fn status(&self) -> Result<(), Error> {
+        self.status.clone()
+    }
+}
+
 impl<'a> PartialEq for TableKeyIteratorType<'a> {
     fn eq(&self, other: &Self) -> bool {
         match (self.curr(), other.curr()) {
-            (Some(head), Some(other_head)) => return head == other_head,
-            (Some(_), None) | (None, Some(_)) => return false,
-            (None, None) => return true,
+            (Some(head), Some(other_head)) => head == other_head,
+            (Some(_), None) | (None, Some(_)) => false,
+            (None, None) => true,
         }
     }
 }
@@ -34,35 +229,22 @@ impl<'a> PartialEq for TableKeyIteratorType<'a> {
 impl<'a> Eq for TableKeyIteratorType<'a> {}
 
 impl<'a> PartialOrd for TableKeyIteratorType<'a> {
-    // binary heap in rust is a max-heap, and hence this method is defined such that greater values swim up.
-    // note that the returned order is the reversed table key order.
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self.curr(), other.curr()) {
-            // for two keys [0,0], [0, 100], partial_cmp of TableKey will return Ordering::Greater since
-            // we want to keys with higher sequence numbers to be placed first.
-            // however, the binary heap in rust is a max-heap which makes the key [0,0] swims up since the comparison result is
-            // Ordering::Greater.
-            // hence, we need to reverse the order so that the key [0,100] swims up.
-            (Some(head), Some(other_head)) => match head.partial_cmp(&other_head).unwrap() {
-                Ordering::Less => Some(Ordering::Greater),
-                Ordering::Equal => Some(Ordering::Equal),
-                Ordering::Greater => Some(Ordering::Less),
-            },
-            (Some(_), None) => Some(Ordering::Greater),
-            (None, Some(_)) => Some(Ordering::Less),
-            (None, None) => Some(Ordering::Equal),
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl<'a> Ord for TableKeyIteratorType<'a> {
+    // binary heap in rust is a max-heap, and hence this method is defined such that greater values swim up.
+    // note that the returned order is the reversed table key order.
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         match (self.curr(), other.curr()) {
-            (Some(head), Some(other_head)) => match head.cmp(&other_head) {
-                Ordering::Less => Ordering::Greater,
-                Ordering::Equal => Ordering::Equal,
-                Ordering::Greater => Ordering::Less,
-            },
+            // for two keys [0,0], [0, 100], cmp of TableKey will return Ordering::Greater since
+            // we want to keys with higher sequence numbers to be placed first.
+            // however, the binary heap in rust is a max-heap which makes the key [0,0] swims up since the comparison result is
+            // Ordering::Greater.
+            // hence, we need to reverse the order so that the key [0,100] swims up.
+            (Some(head), Some(other_head)) => other_head.cmp(&head),
             (Some(_), None) => Ordering::Greater,
             (None, Some(_)) => Ordering::Less,
             (None, None) => Ordering::Equal,
@@ -73,10 +255,9 @@ impl<'a> Ord for TableKeyIteratorType<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::storage::sstable::SSTableWriter;
+    use crate::storage::test_util::TempSSTableDir;
     use crate::util::types::*;
     use std::collections::BinaryHeap;
-    use std::fs::{create_dir, remove_dir_all};
 
     /// insert a sequence of keys into an sstable.
     /// insert another sequence of keys into another sstable but with some delete keys.
@@ -84,43 +265,33 @@ mod tests {
     /// emit all keys and check each key is greater than or equal to the last emitted one.
     #[test]
     fn heap_property() {
-        let _ = create_dir("./sstables");
+        let dir = TempSSTableDir::new();
 
         let num_table_keys: i32 = 963;
         let mut heap: BinaryHeap<TableKeyIteratorType> =
             BinaryHeap::with_capacity((num_table_keys * 2) as usize);
 
         // sstable 1.
-        let file_num = 42;
-        let mut writer = SSTableWriter::new(file_num);
-
-        for i in 0..num_table_keys {
-            let table_key = TableKey::new(i, i as usize, WriteType::Put, i);
-            writer.push(table_key);
-        }
-        let sstable = writer.done();
+        let sstable = dir.write_sstable((0..num_table_keys).map(TableKey::identity));
 
-        let mut iter = Box::new(sstable.iter().unwrap());
+        let mut iter = Box::new(sstable.iter(&ReadOptions::default()).unwrap());
         iter.next();
         heap.push(iter);
 
         // sstable 2.
-        let file_num = file_num + 1;
-        let mut writer = SSTableWriter::new(file_num);
+        let num_deletes: i32 = 200;
+        let deletes = (0..num_deletes).map(|i| {
+            TableKey::new(
+                &i.to_be_bytes(),
+                (i + num_table_keys) as usize,
+                WriteType::Delete,
+                &i.to_be_bytes(),
+            )
+        });
+        let puts = (num_deletes..num_table_keys).map(|i| TableKey::identity(i + num_table_keys));
+        let sstable = dir.write_sstable(deletes.chain(puts));
 
-        let num_deletes = 200;
-        for i in 0..num_deletes {
-            let table_key = TableKey::new(i, (i + num_table_keys) as usize, WriteType::Delete, i);
-            writer.push(table_key);
-        }
-        for i in num_deletes..num_table_keys {
-            let i = i + num_table_keys;
-            let table_key = TableKey::new(i, i as usize, WriteType::Put, i);
-            writer.push(table_key);
-        }
-        let sstable = writer.done();
-
-        let mut iter = Box::new(sstable.iter().unwrap());
+        let mut iter = Box::new(sstable.iter(&ReadOptions::default()).unwrap());
         iter.next();
         heap.push(iter);
 
@@ -129,14 +300,11 @@ mod tests {
         while let Some(mut iter) = heap.pop() {
             if iter.valid() {
                 let table_key = iter.curr().unwrap();
-                if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
-                    last_user_key = Some(table_key.user_key);
-                    match table_key.write_type {
-                        WriteType::Put => {
-                            println!("{}", &table_key);
-                            visible_cnt += 1;
-                        }
-                        _ => {}
+                if last_user_key.as_ref() != Some(&table_key.user_key) {
+                    last_user_key = Some(table_key.user_key.clone());
+                    if let WriteType::Put = table_key.write_type {
+                        println!("{}", &table_key);
+                        visible_cnt += 1;
                     }
                 }
 
@@ -147,7 +315,5 @@ mod tests {
 
         println!("visible_cnt = {}", visible_cnt);
         assert_eq!(visible_cnt, num_table_keys * 2 - num_deletes * 2);
-
-        let _ = remove_dir_all("./sstables");
     }
 }
@@ -2,8 +2,31 @@ use crate::util::types::*;
 use integer_encoding::*;
 use std::cmp::Ordering;
 use std::fmt::{Debug, Display};
-use std::io;
-use std::mem;
+use std::io::{self, Read};
+
+/// max size in bytes of a user key.
+// a user key is stored at least three times in an sstable: in a data block, in the index block as a fence pointer
+// and in the footer as the min or max key. this limit ensures such structures fit in one block.
+pub const MAX_USER_KEY_SIZE: usize = 1024;
+
+/// write a length-prefixed byte string.
+fn write_bytes(encoded: &mut Vec<u8>, bytes: &[u8]) {
+    encoded.write_varint(bytes.len()).unwrap();
+    encoded.extend_from_slice(bytes);
+}
+
+/// read a length-prefixed byte string.
+fn read_bytes(reader: &mut &[u8]) -> Result<Vec<u8>, io::Error> {
+    let len: usize = reader.read_varint()?;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// return the size in bytes of a length-prefixed byte string.
+fn bytes_size(bytes: &[u8]) -> usize {
+    bytes.len().required_space() + bytes.len()
+}
 
 /// table key type.
 /// table key = user key + seq num + write type + user val.
@@ -32,68 +55,75 @@ impl Default for TableKey {
     }
 }
 
-pub const TABLE_KEY_SIZE: usize = mem::size_of::<UserKey>()
-    + mem::size_of::<SeqNum>()
-    + mem::size_of::<WriteType>()
-    + mem::size_of::<UserValue>();
-
-pub fn make_table_key_buf() -> Vec<u8> {
-    vec![0; TABLE_KEY_SIZE]
-}
-
 impl TableKey {
-    pub fn new(
-        user_key: UserKey,
-        seq_num: SeqNum,
-        write_type: WriteType,
-        user_val: UserValue,
-    ) -> Self {
+    pub fn new(user_key: &[u8], seq_num: SeqNum, write_type: WriteType, user_val: &[u8]) -> Self {
         Self {
-            user_key,
-            user_val,
+            user_key: user_key.to_vec(),
             seq_num,
             write_type,
+            user_val: user_val.to_vec(),
         }
     }
 
     /// make a table key with all fields set to i.
+    /// the user key and the user value are the big-endian bytes of i, which preserves the order of
+    /// non-negative integers.
     pub fn identity(i: i32) -> Self {
         Self {
-            user_key: i,
+            user_key: i.to_be_bytes().to_vec(),
             seq_num: i as SeqNum,
             write_type: WriteType::Put,
-            user_val: i,
+            user_val: i.to_be_bytes().to_vec(),
         }
     }
 
+    /// return the size in bytes of the encoded table key.
+    pub fn size(&self) -> usize {
+        bytes_size(&self.user_key) + 8 + 1 + bytes_size(&self.user_val)
+    }
+
+    /// the encoded table key is self-delimiting:
+    /// | user key len (varint) | user key | seq num (8B) | write type (1B) | user val len (varint) | user val |
     pub fn encode_to_bytes(&self) -> Vec<u8> {
-        let mut encoded = Vec::new();
-        // decoding is based on the assumption that the an encoded table key is of size TABLE_KEY_SIZE.
-        // hence, write_fixedint instead of write_varint is used here.
-        encoded.write_fixedint(self.user_key).unwrap();
-        encoded.write_fixedint(self.seq_num).unwrap();
+        let mut encoded = Vec::with_capacity(self.size());
+        write_bytes(&mut encoded, &self.user_key);
+        encoded.write_fixedint(self.seq_num as u64).unwrap();
         encoded.write_fixedint(self.write_type as u8).unwrap();
-        encoded.write_fixedint(self.user_val).unwrap();
+        write_bytes(&mut encoded, &self.user_val);
         encoded
     }
 
-    pub fn decode_from_bytes(bytes: &Vec<u8>) -> Result<Self, io::Error> {
-        let mut reader = bytes.as_slice();
-        let mut table_key = TableKey::default();
-        table_key.user_key = reader.read_fixedint()?;
-        table_key.seq_num = reader.read_fixedint()?;
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let mut reader = bytes;
+        TableKey::decode_from_reader(&mut reader)
+    }
+
+    /// decode a table key from the head of the reader and advance the reader past it.
+    pub fn decode_from_reader(reader: &mut &[u8]) -> Result<Self, io::Error> {
+        let user_key = read_bytes(reader)?;
+        let seq_num = reader.read_fixedint::<u64>()? as SeqNum;
         let write_type = match reader.read_fixedint::<u8>()? {
+            0 => WriteType::Empty,
             1 => WriteType::Put,
             2 => WriteType::Delete,
-            other => panic!("Unexpected write type: {}", other),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected write type: {}", other),
+                ))
+            }
         };
-        table_key.write_type = write_type;
-        table_key.user_val = reader.read_fixedint()?;
-        Ok(table_key)
+        let user_val = read_bytes(reader)?;
+        Ok(Self {
+            user_key,
+            seq_num,
+            write_type,
+            user_val,
+        })
     }
 
     pub fn as_lookup_key(&self) -> LookupKey {
-        LookupKey::new(self.user_key, self.seq_num)
+        LookupKey::new(&self.user_key, self.seq_num)
     }
 }
 
@@ -108,35 +138,17 @@ impl Eq for TableKey {}
 
 impl PartialOrd for TableKey {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if let Some(ord) = self.user_key.partial_cmp(&other.user_key) {
-            match ord {
-                Ordering::Equal => {
-                    // keys with higher sequence number are placed first.
-                    if let Some(ord) = self.seq_num.partial_cmp(&other.seq_num) {
-                        match ord {
-                            Ordering::Less => return Some(Ordering::Greater),
-                            Ordering::Equal => return Some(Ordering::Equal),
-                            Ordering::Greater => return Some(Ordering::Less),
-                        }
-                    }
-                }
-                _ => return Some(ord),
-            }
-        }
-        None
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for TableKey {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // user keys are compared byte-wise, i.e. in the lexicographic order.
         let ord = self.user_key.cmp(&other.user_key);
         if ord == Ordering::Equal {
             // keys with higher sequence number are placed first.
-            match self.seq_num.cmp(&other.seq_num) {
-                Ordering::Less => return Ordering::Greater,
-                Ordering::Equal => return Ordering::Equal,
-                Ordering::Greater => return Ordering::Less,
-            }
+            return other.seq_num.cmp(&self.seq_num);
         }
         ord
     }
@@ -144,43 +156,64 @@ impl Ord for TableKey {
 
 impl Display for TableKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{} | {}]", self.user_key, self.seq_num)
+        write!(
+            f,
+            "[{} | {}]",
+            String::from_utf8_lossy(&self.user_key),
+            self.seq_num
+        )
     }
 }
 
 impl Debug for TableKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{} | {}]", self.user_key, self.seq_num)
+        write!(f, "[{:?} | {}]", self.user_key, self.seq_num)
     }
 }
 
 /// lookup key type.
 /// it's literally a table key without user value and write type.
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct LookupKey {
     pub user_key: UserKey,
     pub seq_num: SeqNum,
 }
 
 impl LookupKey {
-    pub fn new(user_key: UserKey, seq_num: SeqNum) -> Self {
-        Self { user_key, seq_num }
+    pub fn new(user_key: &[u8], seq_num: SeqNum) -> Self {
+        Self {
+            user_key: user_key.to_vec(),
+            seq_num,
+        }
     }
 
     pub fn as_table_key(&self) -> TableKey {
         TableKey {
-            user_key: self.user_key,
+            user_key: self.user_key.clone(),
             seq_num: self.seq_num,
             write_type: WriteType::Empty,
             user_val: UserValue::default(),
         }
     }
 
+    /// return the size in bytes of the encoded lookup key.
+    pub fn size(&self) -> usize {
+        bytes_size(&self.user_key) + 8
+    }
+
     pub fn encode_to_bytes(&self) -> Vec<u8> {
-        let mut encoded = Vec::new();
-        encoded.write_fixedint(self.user_key).unwrap();
-        encoded.write_fixedint(self.seq_num).unwrap();
+        let mut encoded = Vec::with_capacity(self.size());
+        write_bytes(&mut encoded, &self.user_key);
+        encoded.write_fixedint(self.seq_num as u64).unwrap();
         encoded
     }
+
+    /// decode a lookup key from the head of the reader and advance the reader past it.
+    pub fn decode_from_reader(reader: &mut &[u8]) -> Result<Self, io::Error> {
+        let user_key = read_bytes(reader)?;
+        let seq_num = reader.read_fixedint::<u64>()? as SeqNum;
+        Ok(Self { user_key, seq_num })
+    }
 }
 
 #[cfg(test)]
@@ -189,35 +222,52 @@ mod tests {
 
     #[test]
     fn table_key_order() {
-        let mut a = TableKey::new(0, 1, WriteType::Put, 0);
-        let mut b = TableKey::new(1, 1, WriteType::Put, 0);
+        let mut a = TableKey::new(b"0", 1, WriteType::Put, b"");
+        let mut b = TableKey::new(b"1", 1, WriteType::Put, b"");
         assert!(a < b);
 
-        a = TableKey::new(1, 1, WriteType::Put, 0);
+        a = TableKey::new(b"1", 1, WriteType::Put, b"");
         assert_eq!(a, b);
 
-        b = TableKey::new(0, 1, WriteType::Put, 0);
+        b = TableKey::new(b"0", 1, WriteType::Put, b"");
         assert!(a > b);
 
-        a = TableKey::new(0, 100, WriteType::Put, 0);
-        b = TableKey::new(0, 1000, WriteType::Put, 0);
+        a = TableKey::new(b"0", 100, WriteType::Put, b"");
+        b = TableKey::new(b"0", 1000, WriteType::Put, b"");
         assert!(a > b);
 
-        b = TableKey::new(1, 1000, WriteType::Put, 0);
-        a = TableKey::new(0, 800, WriteType::Put, 0);
-        let c = TableKey::new(100, 20, WriteType::Put, 0);
+        b = TableKey::new(b"1", 1000, WriteType::Put, b"");
+        a = TableKey::new(b"0", 800, WriteType::Put, b"");
+        let c = TableKey::new(b"100", 20, WriteType::Put, b"");
         assert!(a <= b);
         assert!(b <= c);
+
+        // a user key is ordered before any longer user key it is a prefix of.
+        a = TableKey::new(b"ab", 1, WriteType::Put, b"");
+        b = TableKey::new(b"ab\x00", 1, WriteType::Put, b"");
+        assert!(a < b);
+        b = TableKey::new(b"b", 1, WriteType::Put, b"");
+        assert!(a < b);
     }
 
     #[test]
     fn table_key_encode_decode() {
-        let table_key = TableKey::new(1, 2, WriteType::Put, 3);
+        let table_key = TableKey::new(b"key", 2, WriteType::Put, b"value");
         let bytes = table_key.encode_to_bytes();
+        assert_eq!(bytes.len(), table_key.size());
+
         let decoded_table_key = TableKey::decode_from_bytes(&bytes).unwrap();
         assert_eq!(table_key.user_key, decoded_table_key.user_key);
         assert_eq!(table_key.seq_num, decoded_table_key.seq_num);
         assert_eq!(table_key.write_type, decoded_table_key.write_type);
         assert_eq!(table_key.user_val, decoded_table_key.user_val);
+
+        // a truncated table key is not decodable.
+        assert!(TableKey::decode_from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // a table key with an unknown write type is not decodable.
+        let mut bytes = bytes;
+        bytes[1 + 3 + 8] = 42;
+        assert!(TableKey::decode_from_bytes(&bytes).is_err());
     }
 }
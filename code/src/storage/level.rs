@@ -1,12 +1,12 @@
 use crate::logging::manifest::LevelManifest;
-use crate::storage::run::{Run, RunIterator, RunStats};
+use crate::storage::run::{Run, RunStats};
 use crate::util::types::*;
 use std::cmp;
-use std::collections::binary_heap::BinaryHeap;
 use std::fmt::Display;
 
-use super::iterator::TableKeyIterator;
+use super::iterator::{MergingIterator, TableKeyIteratorType};
 use super::keys::{LookupKey, TableKey};
+use crate::util::error::Error;
 
 /// a level in the lsm tree.
 pub struct Level {
@@ -16,7 +16,8 @@ pub struct Level {
     pub min_table_key: Option<TableKey>,
     /// max table key stored in the level.
     pub max_table_key: Option<TableKey>,
-    /// sorted runs in the level.
+    /// sorted runs in the level, ordered from the newest to the oldest.
+    /// a newer run keeps newer versions of the keys it shares with older runs.
     pub runs: Vec<Run>,
     /// max number of sorted runs this level could hold.
     pub run_capacity: usize,
@@ -37,11 +38,15 @@ impl Level {
         }
     }
 
-    pub fn get(&self, lookup_key: &LookupKey) -> (Option<UserValue>, bool) {
+    pub fn get(
+        &self,
+        lookup_key: &LookupKey,
+        read_options: &ReadOptions,
+    ) -> Result<(Option<UserValue>, bool), Error> {
         // to handle the case that this level has no runs because of a major compaction,
         // i.e. all runs are merged into a new run in the next level.
         if self.min_table_key.is_none() {
-            return (None, false);
+            return Ok((None, false));
         }
 
         // warning: cannot simply use <= or >= to compare the min/max table key,
@@ -50,99 +55,111 @@ impl Level {
         if lookup_key.user_key >= self.min_table_key.as_ref().unwrap().user_key
             && lookup_key.user_key <= self.max_table_key.as_ref().unwrap().user_key
         {
-            // collect table keys having the same user key as the lookup key.
-            // there might be multiple runs having table keys with the same user key as
-            // the lookup key.
-            // since table keys in different runs has no defined order, we have
-            // first collect those table keys having the same user key from all runs,
-            // and then apply a sorting to select the latest table key.
-            let mut table_keys = Vec::new();
+            // runs are probed from the newest to the oldest, and the probing terminates at the
+            // first run having the user key since its version shadows those in older runs.
             for run in self.runs.iter() {
-                match run.get(lookup_key) {
-                    (Some(table_key), _) => table_keys.push(table_key),
-                    (None, _) => {}
-                }
-            }
-
-            if !table_keys.is_empty() {
-                // the latest table key will be placed at the beginning.
-                table_keys.sort_by(|a, b| a.cmp(b));
-                let table_key = table_keys.first().unwrap();
-
-                match table_key.write_type {
-                    WriteType::Put => return (Some(table_key.user_val), false),
-                    WriteType::Delete => return (Some(table_key.user_val), true),
-                    other => panic!("Unexpected write type: {}", other as u8),
+                if let (Some(table_key), _) = run.get(lookup_key, read_options)? {
+                    match table_key.write_type {
+                        WriteType::Put => return Ok((Some(table_key.user_val), false)),
+                        WriteType::Delete => return Ok((Some(table_key.user_val), true)),
+                        other => panic!("Unexpected write type: {}", other as u8),
+                    }
                 }
             }
         }
-        (None, false)
+        Ok((None, false))
     }
 
-    pub fn iter(&self) -> Result<LevelIterator, ()> {
-        let mut run_iters = BinaryHeap::new();
+    /// point query the lookup keys sorted by user keys in the level.
+    /// the same as `get`, each result is the associated value and true if the key is deleted.
+    pub fn multi_get(
+        &self,
+        lookup_keys: &[LookupKey],
+        read_options: &ReadOptions,
+    ) -> Result<Vec<(Option<UserValue>, bool)>, Error> {
+        // the latest table key of each lookup key.
+        let mut latest_table_keys: Vec<Option<TableKey>> = vec![None; lookup_keys.len()];
+        // indexes of the lookup keys not found in newer runs.
+        let mut pending: Vec<usize> = (0..lookup_keys.len()).collect();
         for run in self.runs.iter() {
-            let mut iter = run.iter()?;
-            iter.next();
-            run_iters.push(iter);
-        }
-        Ok(LevelIterator {
-            run_iters,
-            curr_table_key: None,
-        })
-    }
-}
-
-/// a level's iterator.
-pub struct LevelIterator {
-    /// iterators of all runs in the level.
-    run_iters: BinaryHeap<RunIterator>,
-    /// currently pointed-to table key.
-    curr_table_key: Option<TableKey>,
-}
-
-impl TableKeyIterator for LevelIterator {
-    fn seek(&mut self, lookup_key: &LookupKey) {
-        while let Some(table_key) = self.next() {
-            if table_key >= lookup_key.as_table_key() {
+            if pending.is_empty() {
                 break;
             }
+            let pending_lookup_keys: Vec<LookupKey> =
+                pending.iter().map(|i| lookup_keys[*i].clone()).collect();
+            let table_keys = run.multi_get(&pending_lookup_keys, read_options)?;
+
+            // the same as `get`, a lookup key found in the run is not probed in older runs.
+            let mut num_pending = 0;
+            for (j, table_key) in table_keys.into_iter().enumerate() {
+                match table_key {
+                    Some(table_key) => latest_table_keys[pending[j]] = Some(table_key),
+                    None => {
+                        pending[num_pending] = pending[j];
+                        num_pending += 1;
+                    }
+                }
+            }
+            pending.truncate(num_pending);
         }
-    }
-
-    fn next(&mut self) -> Option<TableKey> {
-        let mut last_user_key = None;
-        while let Some(mut run_iter) = self.run_iters.pop() {
-            if run_iter.valid() {
-                let table_key = run_iter.curr().unwrap();
-                run_iter.next();
-                self.run_iters.push(run_iter);
-
-                if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
-                    last_user_key = Some(table_key.user_key);
 
-                    // this line is used to suppress the `unused_assignments` warning.
-                    // FIXME: find a more elegant solution.
-                    let _ = last_user_key.as_ref().unwrap().clone();
+        Ok(latest_table_keys
+            .into_iter()
+            .map(|table_key| match table_key {
+                Some(table_key) => match table_key.write_type {
+                    WriteType::Put => (Some(table_key.user_val), false),
+                    WriteType::Delete => (Some(table_key.user_val), true),
+                    other => panic!("Unexpected write type: {}", other as u8),
+                },
+                None => (None, false),
+            })
+            .collect())
+    }
 
-                    self.curr_table_key = Some(table_key);
-                    return self.curr_table_key.clone();
-                }
+    /// return true if the key range of the level intersects the user key range [start_user_key, end_user_key).
+    pub fn overlaps(&self, start_user_key: &[u8], end_user_key: &[u8]) -> bool {
+        match (self.min_table_key.as_ref(), self.max_table_key.as_ref()) {
+            (Some(min_table_key), Some(max_table_key)) => {
+                min_table_key.user_key.as_slice() < end_user_key
+                    && max_table_key.user_key.as_slice() >= start_user_key
             }
+            _ => false,
         }
-        self.curr_table_key = None;
-        None
     }
 
-    fn curr(&self) -> Option<TableKey> {
-        self.curr_table_key.clone()
+    pub fn iter(&self, read_options: &ReadOptions) -> LevelIterator {
+        LevelIterator::new(
+            self.runs
+                .iter()
+                .map(|run| Box::new(run.iter(read_options)) as TableKeyIteratorType)
+                .collect(),
+        )
     }
 
-    fn valid(&self) -> bool {
-        self.curr_table_key.is_some()
+    /// return an iterator of the runs and sstables in the level whose key ranges intersect the user
+    /// key range [start_user_key, end_user_key). other runs and sstables are skipped.
+    pub fn range_iter(
+        &self,
+        read_options: &ReadOptions,
+        start_user_key: &[u8],
+        end_user_key: &[u8],
+    ) -> LevelIterator {
+        LevelIterator::new(
+            self.runs
+                .iter()
+                .filter(|run| run.overlaps(start_user_key, end_user_key))
+                .map(|run| {
+                    Box::new(run.range_iter(read_options, start_user_key, end_user_key))
+                        as TableKeyIteratorType
+                })
+                .collect(),
+        )
     }
 }
 
+/// a level's iterator which merges the iterators of all runs in the level.
+pub type LevelIterator = MergingIterator<'static>;
+
 pub enum LevelState {
     ExceedSizeCapacity,
     ExceedRunCapacity,
@@ -150,9 +167,9 @@ pub enum LevelState {
 }
 
 impl Level {
-    /// add a run into the level.
+    /// add a run into the level as the newest run.
     pub fn add_run(&mut self, run: Run) {
-        self.runs.push(run);
+        self.runs.insert(0, run);
         self.update_key_range();
     }
 
@@ -181,27 +198,9 @@ impl Level {
             .unwrap()
             .clone();
 
-        for i in 1..self.runs.len() {
-            min_table_key = cmp::min(
-                min_table_key.clone(),
-                self.runs
-                    .get(i)
-                    .unwrap()
-                    .min_table_key
-                    .as_ref()
-                    .unwrap()
-                    .clone(),
-            );
-            max_table_key = cmp::max(
-                max_table_key.clone(),
-                self.runs
-                    .get(i)
-                    .unwrap()
-                    .max_table_key
-                    .as_ref()
-                    .unwrap()
-                    .clone(),
-            );
+        for run in self.runs.iter().skip(1) {
+            min_table_key = cmp::min(min_table_key, run.min_table_key.as_ref().unwrap().clone());
+            max_table_key = cmp::max(max_table_key, run.max_table_key.as_ref().unwrap().clone());
         }
 
         self.min_table_key = Some(min_table_key);
@@ -291,14 +290,8 @@ impl Level {
             run_manifests.push(run.manifest());
         }
 
-        let mut min_table_key = None;
-        let mut max_table_key = None;
-        if self.min_table_key.is_some() {
-            min_table_key = Some(self.min_table_key.as_ref().unwrap().clone());
-        }
-        if self.max_table_key.is_some() {
-            max_table_key = Some(self.max_table_key.as_ref().unwrap().clone());
-        }
+        let min_table_key = self.min_table_key.clone();
+        let max_table_key = self.max_table_key.clone();
 
         LevelManifest {
             level_num: self.level_num,
@@ -312,12 +305,8 @@ impl Level {
     }
 
     pub fn from_manifest(level_manifest: &LevelManifest) -> Self {
-        let mut min_table_key = None;
-        let mut max_table_key = None;
-        if level_manifest.min_table_key.is_some() {
-            min_table_key = Some(level_manifest.min_table_key.as_ref().unwrap().clone());
-            max_table_key = Some(level_manifest.max_table_key.as_ref().unwrap().clone());
-        }
+        let min_table_key = level_manifest.min_table_key.clone();
+        let max_table_key = level_manifest.max_table_key.clone();
 
         let mut runs = Vec::new();
         for run_manifest in level_manifest.run_manifests.iter() {
@@ -338,9 +327,11 @@ impl Level {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::storage::sstable::SSTableWriter;
-    use std::fs::{create_dir, remove_dir_all};
+    use crate::storage::iterator::TableKeyIterator;
+    use crate::storage::table_cache::TableCache;
+    use crate::storage::test_util::TempSSTableDir;
     use std::rc::Rc;
+    use std::sync::Arc;
 
     /// insert a sequence of keys into an sstable.
     /// insert another sequence of keys into another sstable but with some delete keys.
@@ -352,20 +343,13 @@ mod tests {
     /// also check the deleted keys are actually deleted.
     #[test]
     fn level_iterator() {
-        let _ = create_dir("./sstables");
+        let dir = TempSSTableDir::new();
 
         let num_table_keys: i32 = 963;
         let mut level = Level::new(0, 4, 100000);
 
         // sstable 1.
-        let file_num = 42;
-        let mut writer = SSTableWriter::new(file_num);
-
-        for i in 0..num_table_keys {
-            let table_key = TableKey::new(i, i as usize, WriteType::Put, i);
-            writer.push(table_key);
-        }
-        let sstable = writer.done();
+        let sstable = dir.write_sstable((0..num_table_keys).map(TableKey::identity));
         let run = Run::new(
             vec![Rc::new(sstable)],
             TableKey::identity(0),
@@ -374,20 +358,17 @@ mod tests {
         level.add_run(run);
 
         // sstable 2.
-        let file_num = file_num + 1;
-        let mut writer = SSTableWriter::new(file_num);
-
-        let num_deletes = 200;
-        for i in 0..num_deletes {
-            let table_key = TableKey::new(i, (i + num_table_keys) as usize, WriteType::Delete, i);
-            writer.push(table_key);
-        }
-        for i in num_deletes..num_table_keys {
-            let i = i + num_table_keys;
-            let table_key = TableKey::new(i, i as usize, WriteType::Put, i);
-            writer.push(table_key);
-        }
-        let sstable = writer.done();
+        let num_deletes: i32 = 200;
+        let deletes = (0..num_deletes).map(|i| {
+            TableKey::new(
+                &i.to_be_bytes(),
+                (i + num_table_keys) as usize,
+                WriteType::Delete,
+                &i.to_be_bytes(),
+            )
+        });
+        let puts = (num_deletes..num_table_keys).map(|i| TableKey::identity(i + num_table_keys));
+        let sstable = dir.write_sstable(deletes.chain(puts));
         let run = Run::new(
             vec![Rc::new(sstable)],
             TableKey::identity(0),
@@ -397,18 +378,15 @@ mod tests {
 
         let mut visible_cnt = 0;
         let mut last_user_key = None;
-        let mut iter = level.iter().unwrap();
+        let mut iter = level.iter(&ReadOptions::default());
         iter.next();
         while iter.valid() {
             let table_key = iter.curr().unwrap();
-            if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
-                last_user_key = Some(table_key.user_key);
-                match table_key.write_type {
-                    WriteType::Put => {
-                        println!("{}", &table_key);
-                        visible_cnt += 1;
-                    }
-                    _ => {}
+            if last_user_key.as_ref() != Some(&table_key.user_key) {
+                last_user_key = Some(table_key.user_key.clone());
+                if let WriteType::Put = table_key.write_type {
+                    println!("{}", &table_key);
+                    visible_cnt += 1;
                 }
             }
             iter.next();
@@ -416,7 +394,125 @@ mod tests {
 
         println!("visible_cnt = {}", visible_cnt);
         assert_eq!(visible_cnt, num_table_keys * 2 - num_deletes * 2);
+    }
+
+    /// create a level of two runs holding even and odd keys respectively, and seek keys in the level.
+    #[test]
+    fn level_iterator_seek() {
+        let dir = TempSSTableDir::new();
+
+        let num_table_keys: i32 = 1000;
+        let mut level = Level::new(0, 4, 100000);
+        for parity in [0, 1] {
+            let sstable =
+                dir.write_sstable((parity..num_table_keys).step_by(2).map(TableKey::identity));
+            let min_table_key = sstable.min_table_key.clone();
+            let max_table_key = sstable.max_table_key.clone();
+            level.add_run(Run::new(
+                vec![Rc::new(sstable)],
+                min_table_key,
+                max_table_key,
+            ));
+        }
 
-        let _ = remove_dir_all("./sstables");
+        let mut iter = level.iter(&ReadOptions::default());
+        iter.seek(&TableKey::identity(501).as_lookup_key());
+        for i in 501..600 {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.next();
+        }
+
+        // seeking beyond the last table key exhausts the iterator.
+        iter.seek(&TableKey::identity(num_table_keys).as_lookup_key());
+        assert!(!iter.valid());
+        assert!(iter.next().is_none());
+
+        // exhausted runs are repositioned by a later seek.
+        iter.seek(&TableKey::identity(0).as_lookup_key());
+        for i in 0..num_table_keys {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.next();
+        }
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+
+        // iterate backwards and switch the direction in the middle of the scan.
+        iter.seek_to_last();
+        for i in (500..num_table_keys).rev() {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.prev();
+        }
+        assert_eq!(iter.next(), Some(TableKey::identity(500)));
+        assert_eq!(iter.next(), Some(TableKey::identity(501)));
+        assert_eq!(iter.prev(), Some(TableKey::identity(500)));
+        iter.seek_for_prev(&TableKey::identity(num_table_keys).as_lookup_key());
+        assert_eq!(iter.curr(), Some(TableKey::identity(num_table_keys - 1)));
+    }
+
+    /// add runs which overwrite the same keys one after another, and check lookups probe runs from
+    /// the newest to the oldest and stop at the first run having the key.
+    #[test]
+    fn newest_first_get() {
+        let dir = TempSSTableDir::new();
+
+        let num_runs = 4;
+        let num_table_keys = 400;
+        let mut level = Level::new(0, num_runs, 100000);
+        for r in 0..num_runs {
+            // the newest run containing a key i is the run num_runs - 1 - i % num_runs, and the
+            // newest run deletes the key 0.
+            let user_keys: Vec<i32> = (0..num_table_keys)
+                .filter(|i| (i % num_runs as i32) as usize <= num_runs - 1 - r)
+                .collect();
+            let sstable = dir.write_sstable(user_keys.iter().map(|i| {
+                let write_type = if *i == 0 && r == num_runs - 1 {
+                    WriteType::Delete
+                } else {
+                    WriteType::Put
+                };
+                TableKey::new(&i.to_be_bytes(), r, write_type, &r.to_be_bytes())
+            }));
+            let run = Run::new(
+                vec![Rc::new(sstable)],
+                TableKey::identity(*user_keys.first().unwrap()),
+                TableKey::identity(*user_keys.last().unwrap()),
+            );
+            level.add_run(run);
+        }
+
+        let table_cache = Arc::new(TableCache::new(num_runs));
+        let read_options = ReadOptions {
+            table_cache: Some(table_cache.clone()),
+            ..Default::default()
+        };
+        let num_probes = || {
+            let stats = table_cache.stats();
+            stats.hits + stats.misses
+        };
+
+        // a tombstone in the newest run stops the probing.
+        let lookup_key = |i: i32| LookupKey::new(&i.to_be_bytes(), usize::MAX);
+        let (_, deleted) = level.get(&lookup_key(0), &read_options).unwrap();
+        assert!(deleted);
+        assert_eq!(num_probes(), 1);
+
+        // the number of probed runs grows with how old the latest version of the key is.
+        let mut expected_num_probes = 1;
+        for i in [4, 49, 102, 351] {
+            let (user_val, deleted) = level.get(&lookup_key(i), &read_options).unwrap();
+            let r = num_runs - 1 - i as usize % num_runs;
+            assert_eq!(user_val, Some(r.to_be_bytes().to_vec()));
+            assert!(!deleted);
+            expected_num_probes += num_runs - r;
+            assert_eq!(num_probes(), expected_num_probes);
+        }
+
+        // batched lookups probe each run once, and only for the keys not found in newer runs.
+        let lookup_keys: Vec<LookupKey> = [4, 49, 102, 351].into_iter().map(lookup_key).collect();
+        let results = level.multi_get(&lookup_keys, &read_options).unwrap();
+        for (r, (user_val, _)) in results.into_iter().rev().enumerate() {
+            assert_eq!(user_val, Some(r.to_be_bytes().to_vec()));
+        }
+        assert_eq!(num_probes(), expected_num_probes + num_runs);
     }
 }
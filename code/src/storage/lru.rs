@@ -0,0 +1,159 @@
+use crate::util::types::CacheStats;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+struct Entry<V> {
+    value: V,
+    /// the charge of the value against the capacity.
+    charge: usize,
+    /// the tick of the latest access. a smaller tick means a less recently used entry.
+    tick: u64,
+}
+
+/// a least recently used cache whose values are charged against its capacity.
+/// the least recently used entries are evicted once the total charge exceeds the capacity.
+/// the cache is not synchronized, and the caches built on it guard it with a mutex.
+pub struct Lru<K, V> {
+    capacity: usize,
+    /// computes the charge of a value.
+    charge: fn(&V) -> usize,
+    entries: HashMap<K, Entry<V>>,
+    /// keys ordered by their latest access ticks.
+    lru: BTreeMap<u64, K>,
+    /// total charge of cached entries.
+    usage: usize,
+    next_tick: u64,
+    hits: usize,
+    misses: usize,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize, charge: fn(&V) -> usize) -> Self {
+        Self {
+            capacity,
+            charge,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            usage: 0,
+            next_tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        tick
+    }
+
+    /// return the cached value of the key if any, and make it the most recently used entry.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let tick = self.next_tick();
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.lru.remove(&entry.tick);
+        self.lru.insert(tick, key.clone());
+        entry.tick = tick;
+        Some(entry.value.clone())
+    }
+
+    /// cache the value as the most recently used entry, replacing the cached value of the key if any.
+    /// a value charged more than the capacity is not cached.
+    pub fn insert(&mut self, key: K, value: V) {
+        let charge = (self.charge)(&value);
+        if charge > self.capacity {
+            return;
+        }
+        self.remove(&key);
+
+        // evict the least recently used entries until the value fits.
+        while self.usage + charge > self.capacity {
+            let (_, lru_key) = self.lru.pop_first().unwrap();
+            let entry = self.entries.remove(&lru_key).unwrap();
+            self.usage -= entry.charge;
+        }
+
+        let tick = self.next_tick();
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                charge,
+                tick,
+            },
+        );
+        self.usage += charge;
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.tick);
+            self.usage -= entry.charge;
+        }
+    }
+
+    /// remove the entries whose keys satisfy the predicate.
+    pub fn remove_if(&mut self, predicate: impl Fn(&K) -> bool) {
+        let keys: Vec<K> = self
+            .entries
+            .keys()
+            .filter(|key| predicate(key))
+            .cloned()
+            .collect();
+        for key in keys.iter() {
+            self.remove(key);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            usage: self.usage,
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        let mut lru: Lru<i32, String> = Lru::new(10, |value| value.len());
+        lru.insert(1, "aaa".to_string());
+        lru.insert(2, "bbb".to_string());
+        lru.insert(3, "ccc".to_string());
+        assert_eq!(lru.stats().usage, 9);
+
+        // access the key 1 so that the key 2 becomes the lru one.
+        assert_eq!(lru.get(&1), Some("aaa".to_string()));
+        lru.insert(4, "dd".to_string());
+        assert_eq!(lru.stats().usage, 8);
+        assert_eq!(lru.get(&2), None);
+        for key in [1, 3, 4] {
+            assert!(lru.get(&key).is_some());
+        }
+        let stats = lru.stats();
+        assert_eq!((stats.hits, stats.misses), (4, 1));
+
+        // a replaced value is recharged.
+        lru.insert(4, "dddd".to_string());
+        assert_eq!(lru.stats().usage, 10);
+
+        // a value charged more than the capacity is not cached.
+        lru.insert(5, "e".repeat(11));
+        assert_eq!(lru.get(&5), None);
+        assert_eq!(lru.stats().usage, 10);
+
+        lru.remove_if(|key| key % 2 == 1);
+        assert_eq!(lru.stats().usage, 4);
+        assert_eq!(lru.get(&4), Some("dddd".to_string()));
+    }
+}
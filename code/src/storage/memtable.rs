@@ -1,144 +1,69 @@
-use super::iterator::TableKeyIterator;
+use super::iterator::TableKeyIteratorType;
 use super::keys::*;
+use super::memtable_rep::{new_memtable_rep, MemTableRep};
 use crate::util::types::*;
-use std::collections::BTreeSet;
-use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// memtable.
 /// keys are written into the memtable buffer before being flushed to the sstables.
+/// a memtable supports one writer and many readers at the same time.
 pub struct MemTable {
-    /// the set maintains table keys in a specified order.
-    set: BTreeSet<TableKey>,
-}
-
-/// a memtable iterator is simply an wrapper of the underlying set's iterator.
-pub struct MemTableIterator<'a> {
-    /// the iterator of the underlying set structure.
-    set_iter: Box<dyn Iterator<Item = &'a TableKey> + 'a>,
-    curr_table_key: Option<TableKey>,
-}
-
-impl<'a> TableKeyIterator for MemTableIterator<'a> {
-    fn seek(&mut self, lookup_key: &LookupKey) {
-        while let Some(table_key) = self.next() {
-            if table_key >= lookup_key.as_table_key() {
-                break;
-            }
-        }
-    }
-
-    fn next(&mut self) -> Option<TableKey> {
-        if let Some(table_key) = self.set_iter.next() {
-            self.curr_table_key = Some(table_key.clone());
-            return self.curr_table_key.clone();
-        }
-        self.curr_table_key = None;
-        None
-    }
-
-    fn curr(&self) -> Option<TableKey> {
-        // Option<T>::clone will simply be derefed to the T::clone if there's some T.
-        // otherwise, None is returned.
-        self.curr_table_key.clone()
-    }
-
-    fn valid(&self) -> bool {
-        self.curr_table_key.is_some()
-    }
+    /// the rep maintains the table keys and emits them in the order of table keys.
+    rep: Box<dyn MemTableRep>,
+    /// total size in bytes of the encoded table keys stored in the memtable.
+    size: AtomicUsize,
 }
 
 impl MemTable {
-    pub fn new() -> Self {
+    pub fn new(rep_type: MemTableRepType) -> Self {
         Self {
-            set: BTreeSet::new(),
+            rep: new_memtable_rep(rep_type),
+            size: AtomicUsize::new(0),
         }
     }
 
-    pub fn iter(&self) -> MemTableIterator {
-        MemTableIterator {
-            set_iter: Box::new(self.set.iter()),
-            curr_table_key: None,
-        }
+    pub fn iter(&self) -> TableKeyIteratorType<'_> {
+        self.rep.iter()
     }
 
     /// write a table key into the memtable.
-    pub fn put(&mut self, table_key: TableKey) {
+    /// writes are serialized while concurrent reads see either all or none of a write.
+    pub fn put(&self, table_key: TableKey) {
+        self.size.fetch_add(table_key.size(), Ordering::Relaxed);
         // such an insertion must succeed since the sequence number for each write key is unique.
-        assert_eq!(self.set.insert(table_key), true);
+        self.rep.insert(table_key);
     }
 
     /// point query the value associated of the given key.
-    /// the iterator gives us a flatten view of the keys stored in the memtable:
-    /// keys with the same user key are clustered together and form a chunk.
-    /// each chunk contains keys with different sequence numbers and keys with higher
-    /// sequence numbers are iterated first.
+    /// returns the associated value and true if the key is deleted.
     pub fn get(&self, lookup_key: &LookupKey) -> (Option<UserValue>, bool) {
-        let mut iter = self.iter();
-        iter.seek(lookup_key);
-        if let Some(table_key) = iter.curr() {
-            if table_key.user_key == lookup_key.user_key {
-                match table_key.write_type {
-                    WriteType::Put => return (Some(table_key.user_val), false),
-                    WriteType::Delete => return (Some(table_key.user_val), true),
-                    other => panic!("Unexpected write type {}", other as u8),
-                }
-            }
+        match self.rep.get(lookup_key) {
+            Some(table_key) => match table_key.write_type {
+                WriteType::Put => (Some(table_key.user_val), false),
+                WriteType::Delete => (Some(table_key.user_val), true),
+                other => panic!("Unexpected write type {}", other as u8),
+            },
+            None => (None, false),
         }
-        (None, false)
     }
 
-    /// return the total size in bytes of the table keys stored in the memtable.
-    pub fn size(&self) -> usize {
-        self.set.len() * TABLE_KEY_SIZE
+    /// point query the lookup keys sorted by user keys.
+    /// the same as `get`, each result is the associated value and true if the key is deleted.
+    pub fn multi_get(&self, lookup_keys: &[LookupKey]) -> Vec<(Option<UserValue>, bool)> {
+        lookup_keys
+            .iter()
+            .map(|lookup_key| self.get(lookup_key))
+            .collect()
     }
 
-    #[allow(dead_code)]
-    pub fn stats(&self) -> MemTableStats {
-        let mut all_table_keys = Vec::new();
-        let mut visible_table_keys = Vec::new();
-
-        let mut iter = self.iter();
-        let mut last_user_key = None;
-        while let Some(table_key) = iter.next() {
-            if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
-                last_user_key = Some(table_key.user_key);
-                visible_table_keys.push(format!("{}", table_key));
-            }
-            all_table_keys.push(format!("{}", table_key));
-        }
-
-        MemTableStats {
-            all_table_keys,
-            visible_table_keys,
-        }
+    /// return the total size in bytes of the table keys stored in the memtable.
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
     }
-}
 
-pub struct MemTableStats {
-    /// all table keys in the memtable.
-    all_table_keys: Vec<String>,
-    /// keys with higher sequence numbers shadows keys with lower sequence numbers.
-    visible_table_keys: Vec<String>,
-}
-
-impl Display for MemTableStats {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut stats = String::new();
-
-        stats += &format!("all table keys:\n\tcount: {}", self.all_table_keys.len());
-        for table_key in self.all_table_keys.iter() {
-            stats += &format!("\n\t{}", table_key);
-        }
-
-        stats += &format!(
-            "\nvisible table keys:\n\tcount: {}",
-            self.visible_table_keys.len()
-        );
-        for table_key in self.visible_table_keys.iter() {
-            stats += &format!("\n\t{}", table_key);
-        }
-
-        write!(f, "{}", stats)
+    /// return the memory in bytes allocated by the memtable.
+    pub fn memory_usage(&self) -> usize {
+        self.rep.memory_usage()
     }
 }
 
@@ -146,18 +71,22 @@ impl Display for MemTableStats {
 mod tests {
     use super::*;
     use rand::Rng;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
 
     #[test]
     /// on each iteration, randomly generate a number chosen from -1, 0, 1.
-    /// 1 => put (0, i).
-    /// -1 => delete (0).
-    /// 0 => get(0).
+    /// 1 => put (key, i).
+    /// -1 => delete (key).
+    /// 0 => get(key).
     /// if the last op is put, check that the got value equals with the last put value.
     /// if the last op is delete, check that the key is deleted.
     /// if the last op is get, skip.
     fn put_delele_get() {
-        let mut mem = MemTable::new();
-        let num_table_keys = 100;
+        let mem = MemTable::new(MemTableRepType::SkipList);
+        let num_table_keys: usize = 100;
+        let user_key = b"key";
 
         let mut rng = rand::thread_rng();
         let mut last_rand_num = 0;
@@ -165,27 +94,28 @@ mod tests {
         for i in 0..num_table_keys {
             match rng.gen_range(-1..=1) {
                 1 => {
-                    let table_key = TableKey::new(0, i, WriteType::Put, i as UserValue);
+                    let user_val = i.to_be_bytes();
+                    let table_key = TableKey::new(user_key, i, WriteType::Put, &user_val);
                     mem.put(table_key);
                     last_rand_num = 1;
-                    last_put_val = Some(i as UserValue);
+                    last_put_val = Some(user_val.to_vec());
                 }
                 -1 => {
-                    let table_key = TableKey::new(0, i, WriteType::Delete, i as UserValue);
+                    let table_key = TableKey::new(user_key, i, WriteType::Delete, b"");
                     mem.put(table_key);
                     last_rand_num = -1;
                 }
                 0 => {
-                    let lookup_key = LookupKey::new(0, i);
+                    let lookup_key = LookupKey::new(user_key, i);
                     let (val, deleted) = mem.get(&lookup_key);
                     if i > 0 {
                         match last_rand_num {
                             1 => {
-                                assert_eq!(deleted, false);
-                                assert_eq!(val.unwrap(), last_put_val.unwrap());
+                                assert!(!deleted);
+                                assert_eq!(val, last_put_val);
                             }
                             -1 => {
-                                assert_eq!(deleted, true);
+                                assert!(deleted);
                             }
                             _ => {}
                         }
@@ -195,4 +125,113 @@ mod tests {
             }
         }
     }
+
+    /// readers iterate the memtable while a writer inserts into it, and each reader sees a sorted
+    /// prefix of the writes which only grows.
+    #[test]
+    fn concurrent_reads() {
+        let mem = Arc::new(MemTable::new(MemTableRepType::SkipList));
+        let num_table_keys = 20000_i32;
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let mem = mem.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut last_count = 0;
+                    while !done.load(Ordering::Acquire) {
+                        let mut iter = mem.iter();
+                        let mut count = 0;
+                        let mut last_table_key: Option<TableKey> = None;
+                        while let Some(table_key) = iter.next() {
+                            // the user value is the user key, so a torn table key is detected.
+                            assert_eq!(table_key.user_key, table_key.user_val);
+                            if let Some(last_table_key) = last_table_key {
+                                assert!(last_table_key < table_key);
+                            }
+                            last_table_key = Some(table_key);
+                            count += 1;
+                        }
+                        assert!(count >= last_count);
+                        last_count = count;
+                    }
+                })
+            })
+            .collect();
+
+        // the writer inserts keys in a random order so that nodes are linked all over the list.
+        let mut rng = rand::thread_rng();
+        let mut user_keys: Vec<i32> = (0..num_table_keys).collect();
+        for i in (1..user_keys.len()).rev() {
+            user_keys.swap(i, rng.gen_range(0..=i));
+        }
+        for (seq_num, user_key) in user_keys.iter().enumerate() {
+            let user_key = user_key.to_be_bytes();
+            mem.put(TableKey::new(&user_key, seq_num, WriteType::Put, &user_key));
+        }
+        done.store(true, Ordering::Release);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        for i in 0..num_table_keys {
+            let user_key = i.to_be_bytes();
+            let (val, deleted) = mem.get(&LookupKey::new(&user_key, SeqNum::MAX));
+            assert_eq!(val, Some(user_key.to_vec()));
+            assert!(!deleted);
+        }
+        assert!(mem.memory_usage() >= mem.size());
+    }
+
+    #[test]
+    fn seek() {
+        let mem = MemTable::new(MemTableRepType::SkipList);
+        for i in 0..1000 {
+            let user_key = format!("key{:04}", 2 * i);
+            mem.put(TableKey::new(
+                user_key.as_bytes(),
+                i,
+                WriteType::Put,
+                b"old",
+            ));
+            mem.put(TableKey::new(
+                user_key.as_bytes(),
+                i + 1000,
+                WriteType::Put,
+                b"new",
+            ));
+        }
+
+        let mut iter = mem.iter();
+        // a higher sequence number is ordered before a lower one of the same user key.
+        iter.seek(&LookupKey::new(b"key0100", SeqNum::MAX));
+        assert_eq!(iter.curr().unwrap().seq_num, 1050);
+        iter.seek(&LookupKey::new(b"key0100", 1049));
+        assert_eq!(iter.curr().unwrap().seq_num, 50);
+        assert_eq!(iter.next().unwrap().user_key, b"key0102");
+
+        // a missing user key is seeked to the next user key, and a seek may move backwards.
+        iter.seek(&LookupKey::new(b"key0001", SeqNum::MAX));
+        assert_eq!(iter.curr().unwrap().user_key, b"key0002");
+        assert_eq!(iter.next().unwrap().user_key, b"key0002");
+        assert_eq!(iter.next().unwrap().user_key, b"key0004");
+
+        // seeking beyond the last table key exhausts the iterator.
+        iter.seek(&LookupKey::new(b"key9999", 0));
+        assert!(!iter.valid());
+        assert!(iter.next().is_none());
+
+        // iterate backwards and switch the direction in the middle of the scan.
+        iter.seek_to_last();
+        assert_eq!(iter.curr().unwrap().seq_num, 999);
+        assert_eq!(iter.prev().unwrap().seq_num, 1999);
+        assert_eq!(iter.prev().unwrap().seq_num, 998);
+        assert_eq!(iter.next().unwrap().seq_num, 1999);
+        iter.seek_for_prev(&LookupKey::new(b"key0001", SeqNum::MAX));
+        assert_eq!(iter.curr().unwrap().seq_num, 0);
+        assert!(iter.prev().is_some());
+        assert!(iter.prev().is_none());
+        assert!(!iter.valid());
+    }
 }
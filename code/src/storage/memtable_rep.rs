@@ -0,0 +1,570 @@
+use super::iterator::{TableKeyIterator, TableKeyIteratorType};
+use super::keys::{LookupKey, TableKey};
+use super::skiplist::{SkipList, SkipListIterator};
+use crate::util::types::MemTableRepType;
+use std::collections::{BTreeSet, HashMap};
+use std::mem::size_of;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// the in-memory index of the table keys of a memtable.
+/// a rep supports one writer and many readers at the same time.
+pub trait MemTableRep: Send + Sync {
+    /// insert a table key.
+    /// the sequence number of each table key is unique, so no table key is inserted twice.
+    fn insert(&self, table_key: TableKey);
+
+    /// return an iterator over the table keys in the order of table keys.
+    fn iter(&self) -> TableKeyIteratorType<'_>;
+
+    /// return the first table key greater than or equal to the lookup key if it has the same user
+    /// key as the lookup key, i.e. the latest version of the user key visible to the lookup key.
+    fn get(&self, lookup_key: &LookupKey) -> Option<TableKey> {
+        let mut iter = self.iter();
+        iter.seek(lookup_key);
+        iter.curr()
+            .filter(|table_key| table_key.user_key == lookup_key.user_key)
+    }
+
+    /// return the approximate memory in bytes used by the rep.
+    fn memory_usage(&self) -> usize;
+}
+
+/// create an empty rep of the type.
+pub fn new_memtable_rep(rep_type: MemTableRepType) -> Box<dyn MemTableRep> {
+    match rep_type {
+        MemTableRepType::SkipList => Box::new(SkipListRep::new()),
+        MemTableRepType::Tree => Box::new(TreeRep::new()),
+        MemTableRepType::Vector => Box::new(VectorRep::new()),
+        MemTableRepType::Hash => Box::new(HashRep::new()),
+    }
+}
+
+/// return the approximate memory in bytes used by a table key stored in a collection.
+fn table_key_memory_usage(table_key: &TableKey) -> usize {
+    size_of::<TableKey>() + table_key.user_key.len() + table_key.user_val.len()
+}
+
+/// position of the cursor of a rep iterator which does not point into the rep itself.
+enum Cursor<T> {
+    /// the next table key is the first table key. the initial position of an iterator.
+    BeforeFirst,
+    At(T),
+    /// moved past the last table key.
+    AfterLast,
+}
+
+/// a rep backed by an arena-allocated skiplist, whose reads are lock-free.
+pub struct SkipListRep {
+    list: SkipList,
+}
+
+impl SkipListRep {
+    pub fn new() -> Self {
+        Self {
+            list: SkipList::new(),
+        }
+    }
+}
+
+impl MemTableRep for SkipListRep {
+    fn insert(&self, table_key: TableKey) {
+        self.list.insert(&table_key);
+    }
+
+    fn iter(&self) -> TableKeyIteratorType<'_> {
+        Box::new(SkipListRepIterator {
+            iter: self.list.iter(),
+        })
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.list.memory_usage()
+    }
+}
+
+/// a skiplist rep iterator decodes the table keys pointed to by a skiplist iterator.
+pub struct SkipListRepIterator<'a> {
+    iter: SkipListIterator<'a>,
+}
+
+impl<'a> TableKeyIterator for SkipListRepIterator<'a> {
+    fn seek(&mut self, lookup_key: &LookupKey) {
+        // the skiplist is searched from the top level and hence a seek takes logarithmic time.
+        self.iter.seek(&lookup_key.user_key, lookup_key.seq_num);
+    }
+
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+    }
+
+    fn next(&mut self) -> Option<TableKey> {
+        self.iter.next();
+        self.curr()
+    }
+
+    fn prev(&mut self) -> Option<TableKey> {
+        self.iter.prev();
+        self.curr()
+    }
+
+    fn curr(&self) -> Option<TableKey> {
+        if !self.iter.valid() {
+            return None;
+        }
+        Some(TableKey::decode_from_bytes(self.iter.key()).unwrap())
+    }
+
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+}
+
+/// a rep backed by a btree set.
+pub struct TreeRep {
+    set: RwLock<BTreeSet<TableKey>>,
+    memory_usage: AtomicUsize,
+}
+
+impl TreeRep {
+    pub fn new() -> Self {
+        Self {
+            set: RwLock::new(BTreeSet::new()),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableRep for TreeRep {
+    fn insert(&self, table_key: TableKey) {
+        self.memory_usage
+            .fetch_add(table_key_memory_usage(&table_key), Ordering::Relaxed);
+        assert!(self.set.write().unwrap().insert(table_key));
+    }
+
+    fn iter(&self) -> TableKeyIteratorType<'_> {
+        Box::new(TreeRepIterator {
+            rep: self,
+            cursor: Cursor::BeforeFirst,
+        })
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+/// a tree rep iterator holds the current table key rather than a borrow of the tree, so that the
+/// tree is not locked between moves of the cursor. each move searches the tree from the root.
+pub struct TreeRepIterator<'a> {
+    rep: &'a TreeRep,
+    cursor: Cursor<TableKey>,
+}
+
+impl<'a> TreeRepIterator<'a> {
+    /// point the cursor to the table key if any, otherwise to the given position.
+    fn set_cursor(&mut self, table_key: Option<&TableKey>, otherwise: Cursor<TableKey>) {
+        self.cursor = match table_key {
+            Some(table_key) => Cursor::At(table_key.clone()),
+            None => otherwise,
+        };
+    }
+}
+
+impl<'a> TableKeyIterator for TreeRepIterator<'a> {
+    fn seek(&mut self, lookup_key: &LookupKey) {
+        let set = self.rep.set.read().unwrap();
+        let table_key = set.range(lookup_key.as_table_key()..).next();
+        self.set_cursor(table_key, Cursor::AfterLast);
+    }
+
+    fn seek_to_last(&mut self) {
+        let set = self.rep.set.read().unwrap();
+        self.set_cursor(set.last(), Cursor::BeforeFirst);
+    }
+
+    fn next(&mut self) -> Option<TableKey> {
+        let set = self.rep.set.read().unwrap();
+        let table_key = match &self.cursor {
+            Cursor::BeforeFirst => set.first(),
+            Cursor::At(curr) => set.range((Bound::Excluded(curr), Bound::Unbounded)).next(),
+            Cursor::AfterLast => None,
+        };
+        self.set_cursor(table_key, Cursor::AfterLast);
+        self.curr()
+    }
+
+    fn prev(&mut self) -> Option<TableKey> {
+        let set = self.rep.set.read().unwrap();
+        // an invalid iterator stays invalid.
+        if let Cursor::At(curr) = &self.cursor {
+            let table_key = set.range(..curr).next_back();
+            self.set_cursor(table_key, Cursor::BeforeFirst);
+        }
+        self.curr()
+    }
+
+    fn curr(&self) -> Option<TableKey> {
+        match &self.cursor {
+            Cursor::At(table_key) => Some(table_key.clone()),
+            _ => None,
+        }
+    }
+
+    fn valid(&self) -> bool {
+        matches!(self.cursor, Cursor::At(_))
+    }
+}
+
+/// an iterator over a sorted snapshot of the table keys of a rep.
+/// table keys inserted after the snapshot is taken are invisible to the iterator.
+pub struct SortedVecIterator {
+    table_keys: Arc<Vec<TableKey>>,
+    cursor: Cursor<usize>,
+}
+
+impl SortedVecIterator {
+    fn new(table_keys: Arc<Vec<TableKey>>) -> Self {
+        Self {
+            table_keys,
+            cursor: Cursor::BeforeFirst,
+        }
+    }
+}
+
+impl TableKeyIterator for SortedVecIterator {
+    fn seek(&mut self, lookup_key: &LookupKey) {
+        let lookup_key = lookup_key.as_table_key();
+        let idx = self
+            .table_keys
+            .partition_point(|table_key| *table_key < lookup_key);
+        self.cursor = if idx < self.table_keys.len() {
+            Cursor::At(idx)
+        } else {
+            Cursor::AfterLast
+        };
+    }
+
+    fn seek_to_last(&mut self) {
+        self.cursor = match self.table_keys.len() {
+            0 => Cursor::BeforeFirst,
+            len => Cursor::At(len - 1),
+        };
+    }
+
+    fn next(&mut self) -> Option<TableKey> {
+        let idx = match self.cursor {
+            Cursor::BeforeFirst => 0,
+            Cursor::At(idx) => idx + 1,
+            Cursor::AfterLast => return None,
+        };
+        self.cursor = if idx < self.table_keys.len() {
+            Cursor::At(idx)
+        } else {
+            Cursor::AfterLast
+        };
+        self.curr()
+    }
+
+    fn prev(&mut self) -> Option<TableKey> {
+        // an invalid iterator stays invalid.
+        if let Cursor::At(idx) = self.cursor {
+            self.cursor = match idx {
+                0 => Cursor::BeforeFirst,
+                _ => Cursor::At(idx - 1),
+            };
+        }
+        self.curr()
+    }
+
+    fn curr(&self) -> Option<TableKey> {
+        match self.cursor {
+            Cursor::At(idx) => Some(self.table_keys[idx].clone()),
+            _ => None,
+        }
+    }
+
+    fn valid(&self) -> bool {
+        matches!(self.cursor, Cursor::At(_))
+    }
+}
+
+/// the table keys of a rep kept as a sorted run plus the table keys appended since the run is
+/// sorted. the appended table keys are merged into the run only when the rep is iterated.
+struct AppendedRun {
+    /// table keys in the order of table keys.
+    // iterators share the run with the rep, and a merge builds a new run rather than modifying
+    // the shared one.
+    sorted: Arc<Vec<TableKey>>,
+    /// table keys in the order they're inserted.
+    appended: Vec<TableKey>,
+}
+
+impl AppendedRun {
+    fn new() -> Self {
+        Self {
+            sorted: Arc::new(Vec::new()),
+            appended: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, table_key: TableKey) {
+        self.appended.push(table_key);
+    }
+
+    /// return the sorted run of all table keys, merging the appended table keys into it if any.
+    /// a merge takes linear time in the size of the run plus the sort of the appended table keys.
+    fn sorted(&mut self) -> Arc<Vec<TableKey>> {
+        if self.appended.is_empty() {
+            return self.sorted.clone();
+        }
+
+        // table keys are mostly appended in the order of sequence numbers rather than the order
+        // of table keys, so an unstable sort is as good as a stable one.
+        let mut appended = std::mem::take(&mut self.appended);
+        appended.sort_unstable();
+        // the run is moved rather than copied if no iterator holds it.
+        let merged = match Arc::try_unwrap(std::mem::take(&mut self.sorted)) {
+            Ok(sorted) => merge(sorted.into_iter(), appended),
+            Err(sorted) => merge(sorted.iter().cloned(), appended),
+        };
+        self.sorted = Arc::new(merged);
+        self.sorted.clone()
+    }
+
+    /// return the latest version of the user key visible to the lookup key.
+    /// the sorted run is searched in logarithmic time while the appended table keys are scanned.
+    fn get(&self, lookup_key: &LookupKey) -> Option<TableKey> {
+        let table_key = lookup_key.as_table_key();
+        let idx = self.sorted.partition_point(|sorted| *sorted < table_key);
+        let sorted = self
+            .sorted
+            .get(idx)
+            .filter(|sorted| sorted.user_key == lookup_key.user_key);
+        let appended = self
+            .appended
+            .iter()
+            .filter(|appended| {
+                appended.user_key == lookup_key.user_key && appended.seq_num <= lookup_key.seq_num
+            })
+            .max_by_key(|appended| appended.seq_num);
+        sorted
+            .into_iter()
+            .chain(appended)
+            .max_by_key(|table_key| table_key.seq_num)
+            .cloned()
+    }
+}
+
+/// merge two sorted sequences of table keys into a sorted vector.
+fn merge(
+    sorted: impl ExactSizeIterator<Item = TableKey>,
+    appended: Vec<TableKey>,
+) -> Vec<TableKey> {
+    let mut merged = Vec::with_capacity(sorted.len() + appended.len());
+    let mut sorted = sorted.peekable();
+    let mut appended = appended.into_iter().peekable();
+    while let (Some(a), Some(b)) = (sorted.peek(), appended.peek()) {
+        if a < b {
+            merged.push(sorted.next().unwrap());
+        } else {
+            merged.push(appended.next().unwrap());
+        }
+    }
+    merged.extend(sorted);
+    merged.extend(appended);
+    merged
+}
+
+/// a rep backed by an append-only vector, which is sorted only when it's read.
+/// inserts are cheap while every iteration after an insert merges the inserted table keys into
+/// the sorted vector, so it's suitable for write-only phases where the memtable is read only when
+/// it's flushed.
+pub struct VectorRep {
+    run: Mutex<AppendedRun>,
+    memory_usage: AtomicUsize,
+}
+
+impl VectorRep {
+    pub fn new() -> Self {
+        Self {
+            run: Mutex::new(AppendedRun::new()),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableRep for VectorRep {
+    fn insert(&self, table_key: TableKey) {
+        self.memory_usage
+            .fetch_add(table_key_memory_usage(&table_key), Ordering::Relaxed);
+        self.run.lock().unwrap().push(table_key);
+    }
+
+    fn iter(&self) -> TableKeyIteratorType<'_> {
+        let sorted = self.run.lock().unwrap().sorted();
+        Box::new(SortedVecIterator::new(sorted))
+    }
+
+    fn get(&self, lookup_key: &LookupKey) -> Option<TableKey> {
+        // a lookup does not sort the vector.
+        self.run.lock().unwrap().get(lookup_key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+struct HashState {
+    /// all versions of each user key.
+    index: HashMap<Vec<u8>, Vec<TableKey>>,
+    /// all table keys, which are sorted incrementally by iterations.
+    run: AppendedRun,
+}
+
+/// a rep backed by a hash index of user keys plus a sorted vector of table keys.
+/// a point lookup only scans the versions of the user key, while an iteration after an insert
+/// merges the inserted table keys into the sorted vector, so it's suitable for
+/// point-lookup-heavy phases.
+pub struct HashRep {
+    state: RwLock<HashState>,
+    memory_usage: AtomicUsize,
+}
+
+impl HashRep {
+    pub fn new() -> Self {
+        Self {
+            state: RwLock::new(HashState {
+                index: HashMap::new(),
+                run: AppendedRun::new(),
+            }),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableRep for HashRep {
+    fn insert(&self, table_key: TableKey) {
+        let mut state = self.state.write().unwrap();
+        let versions = state.index.entry(table_key.user_key.clone()).or_default();
+        assert!(versions
+            .iter()
+            .all(|version| version.seq_num != table_key.seq_num));
+        // the user key is stored once more as the key of the index, and the table key is stored
+        // once more in the sorted vector.
+        self.memory_usage.fetch_add(
+            2 * table_key_memory_usage(&table_key) + table_key.user_key.len(),
+            Ordering::Relaxed,
+        );
+        versions.push(table_key.clone());
+        state.run.push(table_key);
+    }
+
+    fn iter(&self) -> TableKeyIteratorType<'_> {
+        {
+            let state = self.state.read().unwrap();
+            if state.run.appended.is_empty() {
+                return Box::new(SortedVecIterator::new(state.run.sorted.clone()));
+            }
+        }
+
+        let sorted = self.state.write().unwrap().run.sorted();
+        Box::new(SortedVecIterator::new(sorted))
+    }
+
+    fn get(&self, lookup_key: &LookupKey) -> Option<TableKey> {
+        let state = self.state.read().unwrap();
+        // the latest version not after the lookup key.
+        state
+            .index
+            .get(&lookup_key.user_key)?
+            .iter()
+            .filter(|version| version.seq_num <= lookup_key.seq_num)
+            .max_by_key(|version| version.seq_num)
+            .cloned()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::types::{SeqNum, WriteType};
+    use rand::Rng;
+
+    const REP_TYPES: [MemTableRepType; 4] = [
+        MemTableRepType::SkipList,
+        MemTableRepType::Tree,
+        MemTableRepType::Vector,
+        MemTableRepType::Hash,
+    ];
+
+    /// insert random versions of random user keys, and check every rep iterates, seeks and looks
+    /// up the same table keys as a btree set.
+    #[test]
+    fn reps() {
+        let mut rng = rand::thread_rng();
+        for rep_type in REP_TYPES {
+            let rep = new_memtable_rep(rep_type);
+            let mut expected = BTreeSet::new();
+            for seq_num in 0..3000 {
+                let user_key = rng.gen_range(0..500_i32).to_be_bytes();
+                let table_key = TableKey::new(&user_key, seq_num, WriteType::Put, &user_key);
+                rep.insert(table_key.clone());
+                // lookups interleaved with inserts see the latest inserted version.
+                let lookup_key = LookupKey::new(&user_key, seq_num);
+                assert_eq!(rep.get(&lookup_key).as_ref(), Some(&table_key));
+                expected.insert(table_key);
+
+                // reads interleaved with inserts see all inserted table keys.
+                if seq_num % 1000 == 0 {
+                    assert_eq!(rep.iter().next(), expected.first().cloned());
+                }
+            }
+            assert!(rep.memory_usage() > 0);
+
+            // the output is sorted in both directions.
+            let mut iter = rep.iter();
+            for table_key in expected.iter() {
+                assert_eq!(iter.next().as_ref(), Some(table_key));
+            }
+            assert!(iter.next().is_none());
+            assert!(iter.next().is_none());
+            iter.seek_to_last();
+            for table_key in expected.iter().rev() {
+                assert_eq!(iter.curr().as_ref(), Some(table_key));
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            assert_eq!(iter.next().as_ref(), expected.first());
+
+            for i in (0..550_i32).step_by(3) {
+                let lookup_key = LookupKey::new(&i.to_be_bytes(), rng.gen_range(0..3500));
+                let next = expected.range(lookup_key.as_table_key()..).next();
+                iter.seek(&lookup_key);
+                assert_eq!(iter.curr().as_ref(), next);
+                let visible = next.filter(|table_key| table_key.user_key == lookup_key.user_key);
+                assert_eq!(rep.get(&lookup_key).as_ref(), visible, "{:?}", rep_type);
+            }
+
+            // an iterator does not see table keys inserted after it's created unless it's backed
+            // by the rep itself.
+            let mut iter = rep.iter();
+            rep.insert(TableKey::new(b"", SeqNum::MAX, WriteType::Put, b""));
+            let first = iter.next().unwrap();
+            match rep_type {
+                MemTableRepType::Vector | MemTableRepType::Hash => {
+                    assert_eq!(&first, expected.first().unwrap())
+                }
+                _ => assert_eq!(first.seq_num, SeqNum::MAX),
+            }
+            let lookup_key = LookupKey::new(b"", SeqNum::MAX);
+            assert_eq!(rep.get(&lookup_key).unwrap().seq_num, SeqNum::MAX);
+        }
+    }
+}
@@ -1,11 +1,13 @@
 use crate::logging::manifest::RunManifest;
 use crate::storage::sstable::{SSTable, SSTableIterator, SSTableStats};
-use std::cmp::{self, Ordering};
+use std::cmp;
 use std::fmt::Display;
 use std::rc::Rc;
 
 use super::iterator::TableKeyIterator;
 use super::keys::*;
+use crate::util::error::Error;
+use crate::util::types::ReadOptions;
 
 /// sorted run.
 /// two properties a sorted run must have:
@@ -35,15 +37,60 @@ impl Run {
         }
     }
 
-    pub fn get(&self, lookup_key: &LookupKey) -> (Option<TableKey>, bool) {
+    pub fn get(
+        &self,
+        lookup_key: &LookupKey,
+        read_options: &ReadOptions,
+    ) -> Result<(Option<TableKey>, bool), Error> {
         if lookup_key.user_key >= self.min_table_key.as_ref().unwrap().user_key
             && lookup_key.user_key <= self.max_table_key.as_ref().unwrap().user_key
         {
             if let Some(sstable) = self.binary_search(lookup_key) {
-                return sstable.get(lookup_key);
+                return sstable.get(lookup_key, read_options);
             }
         }
-        (None, false)
+        Ok((None, false))
+    }
+
+    /// point query the lookup keys sorted by user keys in the run.
+    /// the lookup keys are grouped by the sstables they fall into, so that each sstable is opened
+    /// at most once and sstables without lookup keys are never opened.
+    pub fn multi_get(
+        &self,
+        lookup_keys: &[LookupKey],
+        read_options: &ReadOptions,
+    ) -> Result<Vec<Option<TableKey>>, Error> {
+        let mut table_keys = vec![None; lookup_keys.len()];
+        let mut lo = 0;
+        while lo < lookup_keys.len() {
+            // the same as `binary_search`, only the left sstable is searched if adjacent sstables
+            // contain the same user key.
+            let sstable_idx = self.sstables.partition_point(|sstable| {
+                sstable.max_table_key.user_key < lookup_keys[lo].user_key
+            });
+            let Some(sstable) = self.sstables.get(sstable_idx) else {
+                break;
+            };
+
+            // lookup keys in [lo, mid) are less than the min user key of the sstable and hence
+            // they do not exist in the run. lookup keys in [mid, hi) fall into the sstable.
+            let mid = lo
+                + lookup_keys[lo..].partition_point(|lookup_key| {
+                    lookup_key.user_key < sstable.min_table_key.user_key
+                });
+            let hi = lo
+                + lookup_keys[lo..].partition_point(|lookup_key| {
+                    lookup_key.user_key <= sstable.max_table_key.user_key
+                });
+            if mid < hi {
+                let found = sstable.multi_get(&lookup_keys[mid..hi], read_options)?;
+                for (table_key, found) in table_keys[mid..hi].iter_mut().zip(found) {
+                    *table_key = found;
+                }
+            }
+            lo = hi;
+        }
+        Ok(table_keys)
     }
 
     // binary search the first sstable that has a greater max user key than the lookup key's user key.
@@ -80,16 +127,39 @@ impl Run {
         }
     }
 
-    pub fn iter(&self) -> Result<RunIterator, ()> {
-        let mut sstable_iters = Vec::new();
-        for sstable in self.sstables.iter() {
-            sstable_iters.push(sstable.iter()?);
+    /// return true if the key range of the run intersects the user key range [start_user_key, end_user_key).
+    pub fn overlaps(&self, start_user_key: &[u8], end_user_key: &[u8]) -> bool {
+        match (self.min_table_key.as_ref(), self.max_table_key.as_ref()) {
+            (Some(min_table_key), Some(max_table_key)) => {
+                min_table_key.user_key.as_slice() < end_user_key
+                    && max_table_key.user_key.as_slice() >= start_user_key
+            }
+            _ => false,
         }
-        Ok(RunIterator {
-            sstable_iters,
-            curr_table_key: None,
-            curr_sstable_idx: 0,
-        })
+    }
+
+    /// return an iterator of the run.
+    /// sstables are opened lazily, one at a time, as the iterator proceeds.
+    pub fn iter(&self, read_options: &ReadOptions) -> RunIterator {
+        RunIterator::new(self.sstables.clone(), read_options)
+    }
+
+    /// return an iterator of the sstables in the run whose key ranges intersect the user key range
+    /// [start_user_key, end_user_key). other sstables are never opened by the iterator.
+    pub fn range_iter(
+        &self,
+        read_options: &ReadOptions,
+        start_user_key: &[u8],
+        end_user_key: &[u8],
+    ) -> RunIterator {
+        // sstables are sorted and non-overlapping, so both their min and max keys are sorted.
+        let lo = self
+            .sstables
+            .partition_point(|sstable| sstable.max_table_key.user_key.as_slice() < start_user_key);
+        let hi = self
+            .sstables
+            .partition_point(|sstable| sstable.min_table_key.user_key.as_slice() < end_user_key);
+        RunIterator::new(self.sstables[lo..hi.max(lo)].to_vec(), read_options)
     }
 }
 
@@ -115,15 +185,9 @@ impl Run {
         let mut min_table_key = self.sstables.first().unwrap().min_table_key.clone();
         let mut max_table_key = self.sstables.first().unwrap().max_table_key.clone();
 
-        for i in 1..self.sstables.len() {
-            min_table_key = cmp::min(
-                min_table_key.clone(),
-                self.sstables.get(i).unwrap().min_table_key.clone(),
-            );
-            max_table_key = cmp::max(
-                max_table_key.clone(),
-                self.sstables.get(i).unwrap().max_table_key.clone(),
-            );
+        for sstable in self.sstables.iter().skip(1) {
+            min_table_key = cmp::min(min_table_key, sstable.min_table_key.clone());
+            max_table_key = cmp::max(max_table_key, sstable.max_table_key.clone());
         }
 
         self.min_table_key = Some(min_table_key);
@@ -132,26 +196,134 @@ impl Run {
 }
 
 pub struct RunIterator {
-    sstable_iters: Vec<SSTableIterator>,
+    sstables: Vec<Rc<SSTable>>,
+    read_options: ReadOptions,
+    /// the iterator of the sstable with index curr_sstable_idx, if opened.
+    sstable_iter: Option<SSTableIterator>,
     curr_table_key: Option<TableKey>,
     curr_sstable_idx: usize,
+    /// the error encountered while opening an sstable if any.
+    status: Result<(), Error>,
+}
+
+impl RunIterator {
+    fn new(sstables: Vec<Rc<SSTable>>, read_options: &ReadOptions) -> Self {
+        Self {
+            sstables,
+            read_options: read_options.clone(),
+            sstable_iter: None,
+            curr_table_key: None,
+            curr_sstable_idx: 0,
+            status: Ok(()),
+        }
+    }
+
+    /// open the iterator of the sstable with index curr_sstable_idx.
+    /// return false if an error is encountered.
+    fn open_sstable_iter(&mut self) -> bool {
+        match self.sstables[self.curr_sstable_idx].iter(&self.read_options) {
+            Ok(sstable_iter) => {
+                self.sstable_iter = Some(sstable_iter);
+                true
+            }
+            Err(err) => {
+                self.sstable_iter = None;
+                self.status = Err(err);
+                false
+            }
+        }
+    }
+
+    /// move the cursor to the last table key of the sstable with index curr_sstable_idx, or of the
+    /// sstables before it if the sstable is empty.
+    fn seek_to_last_backward(&mut self) {
+        loop {
+            if self.sstable_iter.is_none() && !self.open_sstable_iter() {
+                return;
+            }
+            let sstable_iter = self.sstable_iter.as_mut().unwrap();
+            sstable_iter.seek_to_last();
+            if sstable_iter.valid() {
+                self.curr_table_key = sstable_iter.curr();
+                return;
+            }
+            if sstable_iter.status().is_err() || self.curr_sstable_idx == 0 {
+                return;
+            }
+            self.sstable_iter = None;
+            self.curr_sstable_idx -= 1;
+        }
+    }
 }
 
 impl TableKeyIterator for RunIterator {
     fn seek(&mut self, lookup_key: &LookupKey) {
-        while let Some(table_key) = self.next() {
-            if table_key >= lookup_key.as_table_key() {
-                break;
-            }
+        if self.status.is_err() {
+            return;
+        }
+
+        // binary search the first sstable whose max table key is not less than the target.
+        // sstables before it only contain table keys less than the target.
+        let target = lookup_key.as_table_key();
+        let sstable_idx = self
+            .sstables
+            .partition_point(|sstable| sstable.max_table_key < target);
+        // the opened sstable iterator is reused if the target is in the same sstable.
+        if sstable_idx != self.curr_sstable_idx {
+            self.sstable_iter = None;
+        }
+        self.curr_sstable_idx = sstable_idx;
+        self.curr_table_key = None;
+        if self.curr_sstable_idx >= self.sstables.len()
+            || (self.sstable_iter.is_none() && !self.open_sstable_iter())
+        {
+            self.sstable_iter = None;
+            return;
+        }
+
+        let sstable_iter = self.sstable_iter.as_mut().unwrap();
+        sstable_iter.seek(lookup_key);
+        if sstable_iter.valid() {
+            self.curr_table_key = sstable_iter.curr();
+        } else if sstable_iter.status().is_ok() {
+            // the target is beyond the table keys of the sstable, so proceed to the next sstable.
+            self.sstable_iter = None;
+            self.curr_sstable_idx += 1;
+            self.next();
         }
     }
 
+    fn seek_to_last(&mut self) {
+        if self.status.is_err() {
+            return;
+        }
+        self.curr_table_key = None;
+        if self.sstables.is_empty() {
+            return;
+        }
+
+        let sstable_idx = self.sstables.len() - 1;
+        if sstable_idx != self.curr_sstable_idx {
+            self.sstable_iter = None;
+        }
+        self.curr_sstable_idx = sstable_idx;
+        self.seek_to_last_backward();
+    }
+
     fn next(&mut self) -> Option<TableKey> {
-        while self.curr_sstable_idx < self.sstable_iters.len() {
-            if let Some(table_key) = self.sstable_iters[self.curr_sstable_idx].next() {
+        while self.status.is_ok() && self.curr_sstable_idx < self.sstables.len() {
+            if self.sstable_iter.is_none() && !self.open_sstable_iter() {
+                break;
+            }
+            let sstable_iter = self.sstable_iter.as_mut().unwrap();
+            if let Some(table_key) = sstable_iter.next() {
                 self.curr_table_key = Some(table_key);
                 return self.curr_table_key.clone();
+            } else if sstable_iter.status().is_err() {
+                // stop at the erroneous sstable.
+                break;
             } else {
+                self.sstable_iter = None;
                 self.curr_sstable_idx += 1;
             }
         }
@@ -159,6 +331,24 @@ impl TableKeyIterator for RunIterator {
         None
     }
 
+    fn prev(&mut self) -> Option<TableKey> {
+        if self.status.is_err() || self.curr_table_key.is_none() {
+            return None;
+        }
+        self.curr_table_key = None;
+
+        let sstable_iter = self.sstable_iter.as_mut().unwrap();
+        if let Some(table_key) = sstable_iter.prev() {
+            self.curr_table_key = Some(table_key);
+        } else if sstable_iter.status().is_ok() && self.curr_sstable_idx > 0 {
+            // move to the last table key of the previous sstable.
+            self.sstable_iter = None;
+            self.curr_sstable_idx -= 1;
+            self.seek_to_last_backward();
+        }
+        self.curr()
+    }
+
     fn curr(&self) -> Option<TableKey> {
         self.curr_table_key.clone()
     }
@@ -166,52 +356,12 @@ impl TableKeyIterator for RunIterator {
     fn valid(&self) -> bool {
         self.curr_table_key.is_some()
     }
-}
 
-// the following four traits are implemented for BinaryHeap<RunIterator>.
-impl PartialEq for RunIterator {
-    fn eq(&self, other: &Self) -> bool {
-        match (self.curr(), other.curr()) {
-            (Some(head), Some(other_head)) => return head == other_head,
-            (Some(_), None) | (None, Some(_)) => return false,
-            (None, None) => return true,
-        }
-    }
-}
-
-impl Eq for RunIterator {}
-
-impl PartialOrd for RunIterator {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self.curr(), other.curr()) {
-            // for two keys [0,0], [0, 100], partial_cmp of TableKey will return Ordering::Greater since
-            // we want to keys with higher sequence numbers to be placed first.
-            // however, the binary heap in rust is a max-heap which makes the key [0,0] swims up since the comparison result is
-            // Ordering::Greater.
-            // hence, we need to reverse the order so that the key [0,100] swims up.
-            (Some(head), Some(other_head)) => match head.partial_cmp(&other_head).unwrap() {
-                Ordering::Less => Some(Ordering::Greater),
-                Ordering::Equal => Some(Ordering::Equal),
-                Ordering::Greater => Some(Ordering::Less),
-            },
-            (Some(_), None) => Some(Ordering::Greater),
-            (None, Some(_)) => Some(Ordering::Less),
-            (None, None) => Some(Ordering::Equal),
-        }
-    }
-}
-
-impl Ord for RunIterator {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match (self.curr(), other.curr()) {
-            (Some(head), Some(other_head)) => match head.cmp(&other_head) {
-                Ordering::Less => Ordering::Greater,
-                Ordering::Equal => Ordering::Equal,
-                Ordering::Greater => Ordering::Less,
-            },
-            (Some(_), None) => Ordering::Greater,
-            (None, Some(_)) => Ordering::Less,
-            (None, None) => Ordering::Equal,
+    fn status(&self) -> Result<(), Error> {
+        self.status.clone()?;
+        match self.sstable_iter.as_ref() {
+            Some(sstable_iter) => sstable_iter.status(),
+            None => Ok(()),
         }
     }
 }
@@ -281,3 +431,92 @@ impl Run {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::TempSSTableDir;
+    use crate::util::types::WriteType;
+
+    /// create a run of three sstables where the last version of the key 299 is put into the
+    /// second sstable, and seek table keys in the run.
+    #[test]
+    fn run_iterator_seek() {
+        let dir = TempSSTableDir::new();
+        let old_299 = TableKey::new(&299_i32.to_be_bytes(), 1, WriteType::Put, b"old");
+        let sstables = vec![
+            Rc::new(dir.write_sstable((0..300).map(TableKey::identity))),
+            Rc::new(dir.write_sstable(
+                std::iter::once(old_299.clone()).chain((300..600).map(TableKey::identity)),
+            )),
+            Rc::new(dir.write_sstable((600..900).map(TableKey::identity))),
+        ];
+        let run = Run::new(sstables, TableKey::identity(0), TableKey::identity(899));
+        let mut iter = run.iter(&ReadOptions::default());
+
+        // seek an existing table key and iterate across sstables.
+        iter.seek(&TableKey::identity(598).as_lookup_key());
+        for i in 598..602 {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.next();
+        }
+
+        // a seek may move backwards.
+        iter.seek(&TableKey::identity(10).as_lookup_key());
+        assert_eq!(iter.curr(), Some(TableKey::identity(10)));
+
+        // a version older than the lookup key is located in the next sstable.
+        iter.seek(&LookupKey::new(&299_i32.to_be_bytes(), 100));
+        assert_eq!(iter.curr(), Some(old_299.clone()));
+        assert_eq!(iter.next(), Some(TableKey::identity(300)));
+
+        // seeking beyond the last table key exhausts the iterator.
+        iter.seek(&TableKey::identity(900).as_lookup_key());
+        assert!(!iter.valid());
+        assert!(iter.next().is_none());
+        assert!(iter.status().is_ok());
+
+        // point query a batch of sorted lookup keys spanning all sstables.
+        let lookup_keys: Vec<LookupKey> = [0, 299, 300, 450, 899, 1000]
+            .iter()
+            .map(|i| TableKey::identity(*i).as_lookup_key())
+            .collect();
+        let table_keys = run
+            .multi_get(&lookup_keys, &ReadOptions::default())
+            .unwrap();
+        let expected = [Some(0), Some(299), Some(300), Some(450), Some(899), None];
+        for (table_key, expected) in table_keys.into_iter().zip(expected) {
+            assert_eq!(table_key, expected.map(TableKey::identity));
+        }
+
+        // iterate backwards across sstables.
+        iter.seek_to_last();
+        for i in (300..900).rev() {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.prev();
+        }
+        assert_eq!(iter.curr(), Some(old_299.clone()));
+        assert_eq!(iter.prev(), Some(TableKey::identity(299)));
+        assert_eq!(iter.next(), Some(old_299.clone()));
+        iter.seek(&TableKey::identity(0).as_lookup_key());
+        assert!(iter.prev().is_none());
+        assert!(!iter.valid());
+
+        // a range iterator only covers the sstables intersecting the range.
+        let start_user_key = 350_i32.to_be_bytes();
+        let end_user_key = 650_i32.to_be_bytes();
+        assert!(run.overlaps(&start_user_key, &end_user_key));
+        let mut iter = run.range_iter(&ReadOptions::default(), &start_user_key, &end_user_key);
+        assert_eq!(iter.sstables.len(), 2);
+        iter.seek(&TableKey::identity(350).as_lookup_key());
+        assert_eq!(iter.curr(), Some(TableKey::identity(350)));
+
+        let iter = run.range_iter(
+            &ReadOptions::default(),
+            &300_i32.to_be_bytes(),
+            &600_i32.to_be_bytes(),
+        );
+        assert_eq!(iter.sstables.len(), 1);
+        assert!(!run.overlaps(&900_i32.to_be_bytes(), &1000_i32.to_be_bytes()));
+    }
+}
@@ -0,0 +1,364 @@
+use super::arena::Arena;
+use super::keys::TableKey;
+use crate::util::types::SeqNum;
+use integer_encoding::*;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Mutex;
+
+/// max height of a node.
+const MAX_HEIGHT: usize = 12;
+/// the height of a node is increased with a probability of 1 / BRANCHING.
+const BRANCHING: u32 = 4;
+
+/// the fixed-size head of a node.
+/// a node is laid out in the arena as:
+/// | node | next pointers (height * 8B) | encoded table key |
+/// where the next pointer at level i points to the next node at level i.
+#[repr(C)]
+struct Node {
+    height: usize,
+    key_len: usize,
+}
+
+/// return the next pointer of the node at the level.
+/// safety: the node must be allocated by `SkipList::new_node` and the level must be less than its height.
+unsafe fn tower<'a>(node: *const Node, level: usize) -> &'a AtomicPtr<Node> {
+    &*((node as *const u8).add(size_of::<Node>()) as *const AtomicPtr<Node>).add(level)
+}
+
+/// return the next node of the node at the level, or null if it's the last node at the level.
+/// safety: the same as `tower`.
+unsafe fn next(node: *const Node, level: usize) -> *const Node {
+    // pairs with the release store in `SkipList::insert` so that a reader sees a fully
+    // initialized node.
+    tower(node, level).load(AtomicOrdering::Acquire)
+}
+
+/// return the encoded table key of the node.
+/// safety: the node must be allocated by `SkipList::new_node`.
+unsafe fn key<'a>(node: *const Node) -> &'a [u8] {
+    let height = (*node).height;
+    let key_ptr =
+        (node as *const u8).add(size_of::<Node>() + height * size_of::<AtomicPtr<Node>>());
+    std::slice::from_raw_parts(key_ptr, (*node).key_len)
+}
+
+/// return the user key and the sequence number of an encoded table key without decoding it as a whole.
+fn decode_user_key_and_seq_num(encoded: &[u8]) -> (&[u8], SeqNum) {
+    let (user_key_len, n) = usize::decode_var(encoded).unwrap();
+    let user_key = &encoded[n..n + user_key_len];
+    let seq_num = u64::decode_fixed(&encoded[n + user_key_len..n + user_key_len + 8]) as SeqNum;
+    (user_key, seq_num)
+}
+
+/// compare an encoded table key against the table key with the user key and the sequence number.
+/// table keys are ordered by user keys and then by sequence numbers in the descending order, the
+/// same as `TableKey::cmp`.
+fn compare(encoded: &[u8], user_key: &[u8], seq_num: SeqNum) -> Ordering {
+    let (encoded_user_key, encoded_seq_num) = decode_user_key_and_seq_num(encoded);
+    encoded_user_key
+        .cmp(user_key)
+        .then_with(|| seq_num.cmp(&encoded_seq_num))
+}
+
+/// a skiplist of encoded table keys whose nodes are allocated in an arena.
+/// writes are serialized by a mutex while reads are lock-free: a node is fully initialized before
+/// it's linked into the list by release stores, and nodes are never removed or moved until the
+/// skiplist is dropped. hence a reader concurrent with a write either sees the new node or not.
+pub struct SkipList {
+    /// the head node of max height, which holds no table key.
+    head: *const Node,
+    /// the max height of all nodes in the list.
+    height: AtomicUsize,
+    /// the arena in which nodes are allocated. it's locked by writes.
+    arena: Mutex<Arena>,
+}
+
+// safety: nodes are only mutated through atomics by the writer holding the arena lock, and the
+// arena outlives all nodes since they're dropped together.
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
+impl SkipList {
+    pub fn new() -> Self {
+        let mut arena = Arena::new();
+        let head = Self::new_node(&mut arena, MAX_HEIGHT, &[]);
+        Self {
+            head,
+            height: AtomicUsize::new(1),
+            arena: Mutex::new(arena),
+        }
+    }
+
+    /// insert the table key into the skiplist.
+    /// panics if a table key with the same user key and sequence number exists.
+    pub fn insert(&self, table_key: &TableKey) {
+        let mut arena = self.arena.lock().unwrap();
+
+        let mut prevs = [self.head; MAX_HEIGHT];
+        let node =
+            self.find_greater_or_equal(&table_key.user_key, table_key.seq_num, Some(&mut prevs));
+        // safety: a non-null node returned by the search is a node of the list.
+        assert!(
+            node.is_null()
+                || compare(unsafe { key(node) }, &table_key.user_key, table_key.seq_num)
+                    != Ordering::Equal,
+            "insert a duplicate table key"
+        );
+
+        let height = Self::random_height();
+        let list_height = self.height.load(AtomicOrdering::Relaxed);
+        if height > list_height {
+            // the levels above the list height are only linked from the head.
+            // a reader seeing the new height before the new node is linked finds null pointers at
+            // the new levels of the head, which is fine.
+            self.height.store(height, AtomicOrdering::Relaxed);
+        }
+
+        let node = Self::new_node(&mut arena, height, &table_key.encode_to_bytes());
+        for (level, prev) in prevs.iter().enumerate().take(height) {
+            // safety: both nodes are nodes of the list and their heights are greater than the level.
+            unsafe {
+                // the new node is not visible to readers yet, and hence a relaxed store suffices.
+                tower(node, level).store(next(*prev, level) as *mut Node, AtomicOrdering::Relaxed);
+                tower(*prev, level).store(node as *mut Node, AtomicOrdering::Release);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> SkipListIterator<'_> {
+        SkipListIterator {
+            list: self,
+            node: self.head,
+        }
+    }
+
+    /// return the memory allocated for the nodes.
+    pub fn memory_usage(&self) -> usize {
+        self.arena.lock().unwrap().memory_usage()
+    }
+
+    /// allocate a node with the height and the encoded table key in the arena.
+    /// all next pointers of the node are null.
+    fn new_node(arena: &mut Arena, height: usize, encoded: &[u8]) -> *const Node {
+        let tower_size = height * size_of::<AtomicPtr<Node>>();
+        let ptr = arena.allocate(size_of::<Node>() + tower_size + encoded.len());
+        // safety: the allocation is 8-byte aligned and large enough to hold the node, and the
+        // zeroed next pointers are null pointers.
+        unsafe {
+            ptr::write(
+                ptr as *mut Node,
+                Node {
+                    height,
+                    key_len: encoded.len(),
+                },
+            );
+            ptr::copy_nonoverlapping(
+                encoded.as_ptr(),
+                ptr.add(size_of::<Node>() + tower_size),
+                encoded.len(),
+            );
+        }
+        ptr as *const Node
+    }
+
+    fn random_height() -> usize {
+        let mut rng = rand::thread_rng();
+        let mut height = 1;
+        while height < MAX_HEIGHT && rng.gen_range(0..BRANCHING) == 0 {
+            height += 1;
+        }
+        height
+    }
+
+    /// return the first node whose table key is greater than or equal to the table key with the
+    /// user key and the sequence number, or null if there's no such node.
+    /// if `prevs` is given, it's filled with the last node before the returned node at each level.
+    fn find_greater_or_equal(
+        &self,
+        user_key: &[u8],
+        seq_num: SeqNum,
+        mut prevs: Option<&mut [*const Node; MAX_HEIGHT]>,
+    ) -> *const Node {
+        let mut node = self.head;
+        let mut level = self.height.load(AtomicOrdering::Relaxed) - 1;
+        loop {
+            // safety: the node is a node of the list whose height is greater than the level.
+            let next_node = unsafe { next(node, level) };
+            if !next_node.is_null()
+                && compare(unsafe { key(next_node) }, user_key, seq_num) == Ordering::Less
+            {
+                node = next_node;
+                continue;
+            }
+            if let Some(prevs) = prevs.as_mut() {
+                prevs[level] = node;
+            }
+            if level == 0 {
+                return next_node;
+            }
+            level -= 1;
+        }
+    }
+
+    /// return the last node whose table key is less than the table key with the user key and the
+    /// sequence number, or the head if there's no such node.
+    fn find_less_than(&self, user_key: &[u8], seq_num: SeqNum) -> *const Node {
+        let mut node = self.head;
+        let mut level = self.height.load(AtomicOrdering::Relaxed) - 1;
+        loop {
+            // safety: the node is a node of the list whose height is greater than the level.
+            let next_node = unsafe { next(node, level) };
+            if !next_node.is_null()
+                && compare(unsafe { key(next_node) }, user_key, seq_num) == Ordering::Less
+            {
+                node = next_node;
+                continue;
+            }
+            if level == 0 {
+                return node;
+            }
+            level -= 1;
+        }
+    }
+
+    /// return the last node, or the head if the list is empty.
+    fn find_last(&self) -> *const Node {
+        let mut node = self.head;
+        let mut level = self.height.load(AtomicOrdering::Relaxed) - 1;
+        loop {
+            // safety: the node is a node of the list whose height is greater than the level.
+            let next_node = unsafe { next(node, level) };
+            if !next_node.is_null() {
+                node = next_node;
+                continue;
+            }
+            if level == 0 {
+                return node;
+            }
+            level -= 1;
+        }
+    }
+}
+
+/// a cursor over the encoded table keys of a skiplist.
+/// before the first `next`, or after a `prev` past the first node, the cursor points to the head
+/// and the next node is the first node. after a `next` past the last node, the cursor is exhausted.
+pub struct SkipListIterator<'a> {
+    list: &'a SkipList,
+    /// the current node, the head, or null if exhausted.
+    node: *const Node,
+}
+
+impl<'a> SkipListIterator<'a> {
+    /// return true if the cursor points to a node holding a table key.
+    pub fn valid(&self) -> bool {
+        !self.node.is_null() && self.node != self.list.head
+    }
+
+    /// return the encoded table key of the current node.
+    /// panics if the iterator is invalid.
+    pub fn key(&self) -> &'a [u8] {
+        assert!(self.valid());
+        // safety: a valid cursor points to a node of the list.
+        unsafe { key(self.node) }
+    }
+
+    /// move the cursor to the first node whose table key is greater than or equal to the table key
+    /// with the user key and the sequence number.
+    pub fn seek(&mut self, user_key: &[u8], seq_num: SeqNum) {
+        self.node = self.list.find_greater_or_equal(user_key, seq_num, None);
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.node = self.list.find_last();
+    }
+
+    pub fn next(&mut self) {
+        if !self.node.is_null() {
+            // safety: a non-null cursor points to the head or a node of the list.
+            self.node = unsafe { next(self.node, 0) };
+        }
+    }
+
+    /// move the cursor to the previous node.
+    // nodes have no backward pointers, so the previous node is searched from the head.
+    pub fn prev(&mut self) {
+        if !self.valid() {
+            return;
+        }
+        let (user_key, seq_num) = decode_user_key_and_seq_num(self.key());
+        self.node = self.list.find_less_than(user_key, seq_num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::types::WriteType;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn insert_and_iterate() {
+        let list = SkipList::new();
+        let mut expected = BTreeSet::new();
+        let mut rng = rand::thread_rng();
+        for seq_num in 0..5000 {
+            let user_key = rng.gen_range(0..1000_i32).to_be_bytes();
+            let table_key = TableKey::new(&user_key, seq_num, WriteType::Put, b"val");
+            list.insert(&table_key);
+            expected.insert(table_key);
+        }
+
+        // forward.
+        let mut iter = list.iter();
+        assert!(!iter.valid());
+        for table_key in expected.iter() {
+            iter.next();
+            assert_eq!(&TableKey::decode_from_bytes(iter.key()).unwrap(), table_key);
+        }
+        iter.next();
+        assert!(!iter.valid());
+        // an exhausted iterator stays exhausted.
+        iter.next();
+        assert!(!iter.valid());
+
+        // backward.
+        iter.seek_to_last();
+        for table_key in expected.iter().rev() {
+            assert_eq!(&TableKey::decode_from_bytes(iter.key()).unwrap(), table_key);
+            iter.prev();
+        }
+        assert!(!iter.valid());
+        // a prev past the first node points to the head, whose next node is the first node.
+        iter.next();
+        assert_eq!(
+            &TableKey::decode_from_bytes(iter.key()).unwrap(),
+            expected.first().unwrap()
+        );
+
+        // seek.
+        for i in (0..1100_i32).step_by(7) {
+            let lookup = TableKey::new(&i.to_be_bytes(), 2500, WriteType::Empty, b"");
+            iter.seek(&lookup.user_key, lookup.seq_num);
+            match expected.range(lookup..).next() {
+                Some(table_key) => {
+                    assert_eq!(&TableKey::decode_from_bytes(iter.key()).unwrap(), table_key)
+                }
+                None => assert!(!iter.valid()),
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "insert a duplicate table key")]
+    fn duplicate_insert() {
+        let list = SkipList::new();
+        list.insert(&TableKey::identity(1));
+        list.insert(&TableKey::identity(1));
+    }
+}
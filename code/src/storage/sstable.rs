@@ -1,24 +1,31 @@
-use super::block::*;
+use super::block::{self, *};
+use super::block_cache::BlockCache;
+use super::bloom_filter::BloomFilter;
 use super::iterator::TableKeyIterator;
 use super::keys::*;
+use super::table_file::TableFile;
 use crate::logging::manifest::SSTableManifest;
+use crate::util::error::Error;
 use crate::util::types::*;
 use std::cmp;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::{remove_file, File};
+use std::io::{BufWriter, Write};
 use std::panic;
 use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 
 pub fn sstable_file_name(file_num: FileNum) -> String {
+    // tests other than database tests place sstable files in temporary directories of their own.
+    #[cfg(test)]
+    if let Some(dir) = super::test_util::sstable_dir() {
+        return format!("{}/sstable_file_{}", dir, file_num);
+    }
     format!("sstables/sstable_file_{}", file_num)
 }
 
-fn make_block_buf() -> Vec<u8> {
-    vec![0; BLOCK_SIZE]
-}
-
 /// in-memory sstable metadata.
+#[derive(Clone)]
 pub struct SSTable {
     /// sstable file number from which the corresponding sstable file could be located.
     pub file_num: FileNum,
@@ -46,30 +53,58 @@ impl SSTable {
         }
     }
 
-    pub fn get(&self, lookup_key: &LookupKey) -> (Option<TableKey>, bool) {
+    pub fn get(
+        &self,
+        lookup_key: &LookupKey,
+        read_options: &ReadOptions,
+    ) -> Result<(Option<TableKey>, bool), Error> {
         if lookup_key.user_key >= self.min_table_key.user_key
             && lookup_key.user_key <= self.max_table_key.user_key
         {
-            let mut iter = self.iter().unwrap();
-            iter.seek(lookup_key);
+            let mut iter = self.iter(read_options)?;
+            iter.point_seek(lookup_key);
+            iter.status()?;
             if let Some(table_key) = iter.curr() {
                 if table_key.user_key == lookup_key.user_key {
                     match table_key.write_type {
-                        WriteType::Put => return (Some(table_key), false),
-                        WriteType::Delete => return (Some(table_key), true),
+                        WriteType::Put => return Ok((Some(table_key), false)),
+                        WriteType::Delete => return Ok((Some(table_key), true)),
                         other => panic!("Unexpected write type: {}", other as u8),
                     }
                 }
             }
         }
-        (None, false)
+        Ok((None, false))
     }
 
-    pub fn iter(&self) -> Result<SSTableIterator, ()> {
-        let reader = SSTableReader::new(self.file_num);
+    /// point query the lookup keys sorted by user keys in the sstable.
+    /// the sstable is opened once and hence its filter and index blocks are loaded once for all
+    /// lookup keys.
+    pub fn multi_get(
+        &self,
+        lookup_keys: &[LookupKey],
+        read_options: &ReadOptions,
+    ) -> Result<Vec<Option<TableKey>>, Error> {
+        let mut table_keys = vec![None; lookup_keys.len()];
+        let mut iter = self.iter(read_options)?;
+        for (lookup_key, table_key) in lookup_keys.iter().zip(table_keys.iter_mut()) {
+            iter.point_seek(lookup_key);
+            iter.status()?;
+            if let Some(curr) = iter.curr() {
+                if curr.user_key == lookup_key.user_key {
+                    *table_key = Some(curr);
+                }
+            }
+        }
+        Ok(table_keys)
+    }
+
+    pub fn iter(&self, read_options: &ReadOptions) -> Result<SSTableIterator, Error> {
+        let reader = SSTableReader::new(self.file_num, read_options)?;
         Ok(SSTableIterator {
             reader,
             data_block_iter: None,
+            status: Ok(()),
         })
     }
 }
@@ -77,263 +112,855 @@ impl SSTable {
 pub struct SSTableIterator {
     reader: SSTableReader,
     data_block_iter: Option<DataBlockIterator>,
+    /// the error encountered while reading blocks if any.
+    /// the iterator becomes invalid once an error is encountered.
+    status: Result<(), Error>,
+}
+
+impl SSTableIterator {
+    /// read the next data block and reset the data block iterator.
+    /// return true if the reading is successful.
+    fn read_next_data_block(&mut self) -> bool {
+        let result = self.reader.next();
+        self.reset_data_block_iter(result)
+    }
+
+    /// read the previous data block and reset the data block iterator.
+    /// return true if the reading is successful.
+    fn read_prev_data_block(&mut self) -> bool {
+        let result = self.reader.prev();
+        self.reset_data_block_iter(result)
+    }
+
+    /// reset the data block iterator to iterate the data block loaded by the reader if any.
+    fn reset_data_block_iter(&mut self, result: Result<bool, Error>) -> bool {
+        match result {
+            Ok(true) => {
+                self.data_block_iter = Some(self.reader.data_block.as_ref().unwrap().iter());
+                true
+            }
+            Ok(false) => {
+                self.data_block_iter = None;
+                false
+            }
+            Err(err) => {
+                self.data_block_iter = None;
+                self.status = Err(err);
+                false
+            }
+        }
+    }
+
+    /// set the status if the data block iterator stops at a malformed entry.
+    /// return true if the data block iterator is corrupted.
+    fn check_data_block_iter(&mut self) -> bool {
+        if !self
+            .data_block_iter
+            .as_ref()
+            .is_some_and(|data_block_iter| data_block_iter.is_corrupted())
+        {
+            return false;
+        }
+        self.data_block_iter = None;
+        self.status = Err(corruption(
+            self.reader.file_num(),
+            self.reader.data_block_offset,
+            "malformed data block entry",
+        ));
+        true
+    }
+}
+
+impl SSTableIterator {
+    /// seek the lookup key for a point lookup.
+    /// different from `seek`, the iterator becomes invalid without reading any data block if the
+    /// filter tells the sstable definitely does not contain the user key of the lookup key.
+    pub fn point_seek(&mut self, lookup_key: &LookupKey) {
+        if self.status.is_err() {
+            return;
+        }
+
+        // if the key definitely not in the sstable, terminates searching.
+        match self.reader.maybe_contain(lookup_key) {
+            Ok(true) => self.seek(lookup_key),
+            Ok(false) => self.data_block_iter = None,
+            Err(err) => {
+                self.data_block_iter = None;
+                self.status = Err(err);
+            }
+        }
+    }
 }
 
 impl TableKeyIterator for SSTableIterator {
     fn seek(&mut self, lookup_key: &LookupKey) {
-        // if the key definitely not in the sstable, terminates searching.
-        if !self.reader.filter_block.maybe_contain(lookup_key.user_key) {
+        if self.status.is_err() {
             return;
         }
+        self.data_block_iter = None;
 
         // binary search the lookup key by fence pointers.
-        if let Some(data_block_idx) = self.reader.index_block.binary_search(lookup_key) {
-            self.reader.advance_to(data_block_idx);
-            self.reader.next();
-            self.data_block_iter = Some(self.reader.data_block.as_ref().unwrap().iter());
-            self.data_block_iter.as_mut().unwrap().seek(lookup_key);
+        let location = self
+            .reader
+            .find_data_block(lookup_key)
+            .and_then(|location| {
+                if let Some((index_block_idx, data_block_idx)) = location {
+                    self.reader.advance_to(index_block_idx, data_block_idx)?;
+                }
+                Ok(location)
+            });
+        match location {
+            Ok(Some(_)) => {
+                if self.read_next_data_block() {
+                    self.data_block_iter.as_mut().unwrap().seek(lookup_key);
+                    // fence pointers are compared by user keys, so the data block may end with
+                    // versions of the user key newer than the lookup key. the target is then the
+                    // first table key of the next data block.
+                    if !self.check_data_block_iter() && !self.valid() {
+                        self.next();
+                    }
+                }
+            }
+            // the lookup key is greater than all table keys in the sstable.
+            Ok(None) => {
+                if let Err(err) = self.reader.advance_to_end() {
+                    self.status = Err(err);
+                }
+            }
+            Err(err) => self.status = Err(err),
+        }
+    }
+
+    fn seek_to_last(&mut self) {
+        if self.status.is_err() {
+            return;
+        }
+        self.data_block_iter = None;
+
+        match self.reader.advance_to_last() {
+            Ok(true) => {
+                if self.read_next_data_block() {
+                    self.data_block_iter.as_mut().unwrap().seek_to_last();
+                    self.check_data_block_iter();
+                }
+            }
+            Ok(false) => {}
+            Err(err) => self.status = Err(err),
         }
     }
 
     fn next(&mut self) -> Option<TableKey> {
-        if self.data_block_iter.is_some() {
-            if let Some(table_key) = self.data_block_iter.as_mut().unwrap().next() {
+        if self.status.is_err() {
+            return None;
+        }
+
+        if let Some(data_block_iter) = self.data_block_iter.as_mut() {
+            if let Some(table_key) = data_block_iter.next() {
                 // this data block is not exhausted.
                 return Some(table_key);
             }
         }
+        if self.check_data_block_iter() {
+            return None;
+        }
         // reach here if either the data block iter is some but exhausted,
         // or the data block iter is none which could only happen on the init.
 
-        if !self.reader.next() {
-            // all data blocks are read over.
+        if !self.read_next_data_block() {
+            // all data blocks are read over or an error is encountered.
             return None;
         }
         // successfully read the next data block.
-        self.data_block_iter = Some(self.reader.data_block.as_ref().unwrap().iter());
-        // this next must succeed, i.e. some table key must be returned.
-        self.data_block_iter.as_mut().unwrap().next()
+        // this next must succeed, i.e. some table key must be returned, unless the data block is malformed.
+        let table_key = self.data_block_iter.as_mut().unwrap().next();
+        self.check_data_block_iter();
+        table_key
+    }
+
+    fn prev(&mut self) -> Option<TableKey> {
+        if self.status.is_err() || !self.valid() {
+            return None;
+        }
+
+        if let Some(table_key) = self.data_block_iter.as_mut().unwrap().prev() {
+            // this data block is not exhausted.
+            return Some(table_key);
+        }
+        if self.check_data_block_iter() {
+            return None;
+        }
+
+        // move to the last table key of the previous data block.
+        if !self.read_prev_data_block() {
+            return None;
+        }
+        self.data_block_iter.as_mut().unwrap().seek_to_last();
+        self.check_data_block_iter();
+        self.curr()
     }
 
     fn curr(&self) -> Option<TableKey> {
-        if self.data_block_iter.is_some() {
-            self.data_block_iter.as_ref().unwrap().curr()
+        self.data_block_iter
+            .as_ref()
+            .and_then(|data_block_iter| data_block_iter.curr())
+    }
+
+    fn valid(&self) -> bool {
+        self.data_block_iter
+            .as_ref()
+            .is_some_and(|data_block_iter| data_block_iter.valid())
+    }
+
+    fn status(&self) -> Result<(), Error> {
+        self.status.clone()
+    }
+}
+
+fn corruption(file_num: FileNum, block_offset: usize, reason: impl Display) -> Error {
+    Error::Corruption {
+        file_num,
+        block_offset,
+        reason: reason.to_string(),
+    }
+}
+
+fn io_error(file_num: FileNum, err: impl Display) -> Error {
+    Error::Io {
+        file_num,
+        reason: err.to_string(),
+    }
+}
+
+/// read the block of `size` bytes at `offset` of the sstable file.
+/// the checksum at the tail of the block is verified if `verify_checksum` is true.
+fn read_block(
+    file: &TableFile,
+    file_num: FileNum,
+    offset: usize,
+    size: usize,
+    verify_checksum: bool,
+) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0; size];
+    file.read_at(offset, &mut buf)
+        .map_err(|err| io_error(file_num, err))?;
+
+    if verify_checksum && !block::verify_checksum(&buf) {
+        return Err(corruption(file_num, offset, "checksum mismatch"));
+    }
+    Ok(buf)
+}
+
+/// the bloom filters of an sstable.
+pub(crate) enum TableFilter {
+    Full(FilterBlock),
+    /// filter partitions are loaded lazily by readers and hence only the filter index block is kept.
+    Partitioned(FilterIndexBlock),
+}
+
+/// an opened sstable file with its parsed metadata blocks.
+/// a table is immutable once opened and hence it could be shared by readers, e.g. by the table cache.
+pub(crate) struct Table {
+    file_num: FileNum,
+    file: TableFile,
+    filter: TableFilter,
+    top_index_block: TopIndexBlock,
+    /// index blocks parsed on first use.
+    index_blocks: Vec<OnceLock<Arc<IndexBlock>>>,
+    /// size in bytes of each block in the sstable, read from the footer.
+    block_size: usize,
+    format_version: u32,
+    /// number of table keys in the sstable, read from the footer.
+    total_num_table_keys: usize,
+}
+
+impl Table {
+    /// open the sstable file with the file number and read its metadata blocks.
+    pub fn open(file_num: FileNum, use_mmap: bool) -> Result<Self, Error> {
+        let file = TableFile::open(file_num, use_mmap).map_err(|err| io_error(file_num, err))?;
+        let file_size = file.size();
+
+        // read the tail of the footer to identify the file and its format.
+        if file_size < FOOTER_TAIL_SIZE {
+            return Err(corruption(file_num, 0, "file too short"));
+        }
+        let footer_tail_offset = file_size - FOOTER_TAIL_SIZE;
+        let buf = read_block(&file, file_num, footer_tail_offset, FOOTER_TAIL_SIZE, false)?;
+        let tail = Footer::decode_tail(&buf)
+            .map_err(|err| corruption(file_num, footer_tail_offset, err))?;
+        if tail.magic != SSTABLE_MAGIC {
+            return Err(Error::NotSSTable { file_num });
+        }
+
+        // dispatch on the format version so that sstables written by older builds remain readable.
+        match tail.format_version {
+            1..=4 => Self::open_v1_to_v4(
+                file_num,
+                tail.format_version,
+                file,
+                file_size,
+                tail.block_size,
+            ),
+            format_version => Err(Error::UnsupportedFormatVersion {
+                file_num,
+                format_version,
+            }),
+        }
+    }
+
+    /// open an sstable of format version 1 to 4.
+    /// the versions differ only in the encoding of the filter block, the filter type in the footer
+    /// and the encoding of compressed data blocks.
+    fn open_v1_to_v4(
+        file_num: FileNum,
+        format_version: u32,
+        file: TableFile,
+        file_size: usize,
+        block_size: usize,
+    ) -> Result<Self, Error> {
+        let footer_tail_offset = file_size - FOOTER_TAIL_SIZE;
+        if block_size < MIN_BLOCK_SIZE || block_size > file_size {
+            return Err(corruption(
+                file_num,
+                footer_tail_offset,
+                format!("invalid block size {}", block_size),
+            ));
+        }
+
+        // metadata blocks are always verified since they're read once and they locate all other blocks.
+        let footer_offset = file_size - block_size;
+        let buf = read_block(&file, file_num, footer_offset, block_size, true)?;
+        let footer = Footer::decode_from_bytes(&buf)
+            .map_err(|err| corruption(file_num, footer_offset, err))?;
+        if footer.filter_block_offset >= footer_offset || footer.index_block_offset >= footer_offset
+        {
+            return Err(corruption(file_num, footer_offset, "invalid block offsets"));
+        }
+
+        // read the top-level index block.
+        // index blocks are read lazily on lookups and iterations.
+        let buf = read_block(
+            &file,
+            file_num,
+            footer.index_block_offset,
+            footer_offset - footer.index_block_offset,
+            true,
+        )?;
+        let top_index_block = TopIndexBlock::decode_from_bytes(&buf, footer.num_index_blocks)
+            .map_err(|err| corruption(file_num, footer.index_block_offset, err))?;
+
+        // read the filter block which spans the blocks up to the first index block.
+        let filter_block_end = if top_index_block.num_index_blocks() > 0 {
+            top_index_block.get(0).offset
         } else {
-            None
+            footer.index_block_offset
+        };
+        if footer.filter_block_offset >= filter_block_end {
+            return Err(corruption(file_num, footer_offset, "invalid block offsets"));
         }
+        let filter_block_offset = footer.filter_block_offset;
+        let buf = read_block(
+            &file,
+            file_num,
+            filter_block_offset,
+            filter_block_end - filter_block_offset,
+            true,
+        )?;
+        let filter = match (footer.filter_type, format_version) {
+            (FilterType::Full, 1) => FilterBlock::decode_v1(&buf).map(TableFilter::Full),
+            (FilterType::Full, _) => FilterBlock::decode_from_bytes(&buf).map(TableFilter::Full),
+            (FilterType::Partitioned, _) => {
+                FilterIndexBlock::decode_from_bytes(&buf).map(TableFilter::Partitioned)
+            }
+        }
+        .map_err(|err| corruption(file_num, filter_block_offset, err))?;
+        if let TableFilter::Partitioned(filter_index_block) = &filter {
+            if filter_index_block.num_partitions() != footer.num_index_blocks {
+                return Err(corruption(
+                    file_num,
+                    filter_block_offset,
+                    "mismatched number of filter partitions",
+                ));
+            }
+        }
+
+        Ok(Self {
+            file_num,
+            file,
+            filter,
+            index_blocks: (0..top_index_block.num_index_blocks())
+                .map(|_| OnceLock::new())
+                .collect(),
+            top_index_block,
+            block_size,
+            format_version,
+            total_num_table_keys: footer.num_table_keys,
+        })
     }
 
-    fn valid(&self) -> bool {
-        self.data_block_iter.is_some() && self.data_block_iter.as_ref().unwrap().valid()
+    /// return the index block with index index_block_idx.
+    /// the index block is read and parsed on first use and then kept along with the table.
+    fn index_block(&self, index_block_idx: usize) -> Result<Arc<IndexBlock>, Error> {
+        let cell = &self.index_blocks[index_block_idx];
+        if let Some(index_block) = cell.get() {
+            return Ok(index_block.clone());
+        }
+
+        let handle = self.top_index_block.get(index_block_idx);
+        let (offset, num_data_blocks) = (handle.offset, handle.num_data_blocks);
+        let buf = read_block(&self.file, self.file_num, offset, self.block_size, true)?;
+        let index_block = IndexBlock::decode_from_bytes(&buf, num_data_blocks)
+            .map_err(|err| corruption(self.file_num, offset, err))?;
+        // concurrent readers may parse the same index block and only the first one is kept.
+        Ok(cell.get_or_init(|| Arc::new(index_block)).clone())
     }
 }
 
 /// a reader for reading an sstable file.
 struct SSTableReader {
-    reader: BufReader<File>,
+    table: Arc<Table>,
     data_block: Option<DataBlock>,
-    filter_block: FilterBlock,
-    index_block: IndexBlock,
-    total_num_table_keys: usize,
+    /// offset of the loaded data block.
+    data_block_offset: usize,
+    /// the loaded filter partition and its index, if the filter is partitioned.
+    filter_partition: Option<(usize, FilterBlock)>,
+    /// the index block indexing the next data block, if loaded.
+    index_block: Option<Arc<IndexBlock>>,
+    /// index of the loaded index block in the top-level index block.
+    index_block_idx: usize,
+    /// index of the next data block in the loaded index block.
     next_data_block_idx: usize,
-    num_data_blocks: usize,
+    /// true to verify the checksums of data blocks.
+    verify_checksums: bool,
+    /// the cache of data blocks if any.
+    block_cache: Option<Arc<BlockCache>>,
 }
 
 impl SSTableReader {
-    pub fn new(file_num: FileNum) -> Self {
-        let file = File::open(sstable_file_name(file_num)).unwrap();
-        let file_size = file.metadata().unwrap().len();
-        assert_eq!(file_size as usize % BLOCK_SIZE, 0);
+    /// create a reader of the sstable with the file number.
+    /// the opened table is taken from the table cache if any, or else the sstable file is opened.
+    pub fn new(file_num: FileNum, read_options: &ReadOptions) -> Result<Self, Error> {
+        let table = match read_options.table_cache.as_ref() {
+            Some(table_cache) => table_cache.get_or_open(file_num, read_options.use_mmap)?,
+            None => Arc::new(Table::open(file_num, read_options.use_mmap)?),
+        };
+        Ok(Self {
+            table,
+            data_block: None,
+            data_block_offset: 0,
+            filter_partition: None,
+            index_block: None,
+            index_block_idx: 0,
+            next_data_block_idx: 0,
+            verify_checksums: read_options.verify_checksums,
+            block_cache: read_options.block_cache.clone(),
+        })
+    }
 
-        let mut reader = BufReader::new(file);
+    fn file_num(&self) -> FileNum {
+        self.table.file_num
+    }
 
-        // read the footer.
-        let footer_offset = file_size as usize - BLOCK_SIZE;
-        reader.seek_relative(footer_offset as i64).unwrap();
-        let mut buf = make_block_buf();
-        reader.read_exact(&mut buf).unwrap();
-        let footer = Footer::decode_from_bytes(&buf).unwrap();
+    /// load the index block with index index_block_idx if it's not loaded.
+    fn load_index_block(&mut self, index_block_idx: usize) -> Result<(), Error> {
+        if self.index_block.is_some() && self.index_block_idx == index_block_idx {
+            return Ok(());
+        }
 
-        // reset the seek cursor and read the filter block.
-        reader
-            .seek_relative(-((footer_offset + BLOCK_SIZE) as i64))
-            .unwrap();
-        buf = make_block_buf();
-        reader
-            .seek_relative(footer.filter_block_offset as i64)
-            .unwrap();
-        reader.read_exact(&mut buf).unwrap();
-        let filter_block = FilterBlock::decode_from_bytes(&buf);
+        self.index_block = Some(self.table.index_block(index_block_idx)?);
+        self.index_block_idx = index_block_idx;
+        Ok(())
+    }
 
-        // reset the seek cursor and read the index block.
-        reader
-            .seek_relative(-((footer.filter_block_offset + BLOCK_SIZE) as i64))
-            .unwrap();
-        buf = make_block_buf();
-        reader
-            .seek_relative(footer.index_block_offset as i64)
-            .unwrap();
-        reader.read_exact(&mut buf).unwrap();
-        let num_data_blocks = table_keys_to_blocks(footer.num_table_keys);
-        let index_block = IndexBlock::decode_from_bytes(&buf, num_data_blocks).unwrap();
+    /// return false if the sstable definitely does not contain the user key of the lookup key.
+    /// the filter partition of the index block which might contain the key is loaded if necessary.
+    fn maybe_contain(&mut self, lookup_key: &LookupKey) -> Result<bool, Error> {
+        let table = &self.table;
+        let filter_index_block = match &table.filter {
+            TableFilter::Full(filter_block) => {
+                return Ok(filter_block.maybe_contain(&lookup_key.user_key))
+            }
+            TableFilter::Partitioned(filter_index_block) => filter_index_block,
+        };
+
+        let Some(index_block_idx) = table.top_index_block.binary_search(lookup_key) else {
+            return Ok(false);
+        };
+        if self
+            .filter_partition
+            .as_ref()
+            .is_none_or(|(partition_idx, _)| *partition_idx != index_block_idx)
+        {
+            let handle = filter_index_block.get(index_block_idx);
+            let buf = read_block(
+                &table.file,
+                table.file_num,
+                handle.offset,
+                handle.size,
+                true,
+            )?;
+            let filter_block = FilterBlock::decode_from_bytes(&buf)
+                .map_err(|err| corruption(table.file_num, handle.offset, err))?;
+            self.filter_partition = Some((index_block_idx, filter_block));
+        }
 
-        // reset the seek cursor to prepare for reading data blocks.
-        reader
-            .seek_relative(-((footer.index_block_offset + BLOCK_SIZE) as i64))
-            .unwrap();
+        let (_, filter_block) = self.filter_partition.as_ref().unwrap();
+        Ok(filter_block.maybe_contain(&lookup_key.user_key))
+    }
 
-        Self {
-            reader,
-            data_block: None,
-            filter_block,
-            index_block,
-            total_num_table_keys: footer.num_table_keys,
-            next_data_block_idx: 0,
-            num_data_blocks,
-        }
+    /// return the location of the data block which might contain the lookup key if any,
+    /// i.e. the index of the index block and the index of the data block in the index block.
+    fn find_data_block(&mut self, lookup_key: &LookupKey) -> Result<Option<(usize, usize)>, Error> {
+        // binary search the index block by the top-level index block.
+        let Some(index_block_idx) = self.table.top_index_block.binary_search(lookup_key) else {
+            return Ok(None);
+        };
+
+        // read the index block and binary search the data block by fence pointers.
+        self.load_index_block(index_block_idx)?;
+        let location = self
+            .index_block
+            .as_ref()
+            .unwrap()
+            .binary_search(lookup_key)
+            .map(|data_block_idx| (index_block_idx, data_block_idx));
+
+        Ok(location)
     }
 
     /// advance to the next data block if any.
     /// return true if the advancing is successful.
-    fn next(&mut self) -> bool {
-        if self.next_data_block_idx >= self.num_data_blocks {
-            return false;
+    fn next(&mut self) -> Result<bool, Error> {
+        // move to the next index block if the loaded one is exhausted.
+        loop {
+            if let Some(index_block) = self.index_block.as_ref() {
+                if self.next_data_block_idx < index_block.len() {
+                    break;
+                }
+            }
+
+            let next_index_block_idx = if self.index_block.is_none() {
+                0
+            } else {
+                self.index_block_idx + 1
+            };
+            if next_index_block_idx >= self.table.top_index_block.num_index_blocks() {
+                // all data blocks are read over.
+                return Ok(false);
+            }
+            self.load_index_block(next_index_block_idx)?;
+            self.next_data_block_idx = 0;
         }
 
         // read the next data block into the buffer.
-        let block_offset = self.next_data_block_idx * BLOCK_SIZE;
-        self.reader.seek_relative(block_offset as i64).unwrap();
-        let mut buf = make_block_buf();
-        self.reader.read_exact(&mut buf).unwrap();
-        // reset cursor to the file start.
-        self.reader
-            .seek_relative(-((block_offset + BLOCK_SIZE) as i64))
-            .unwrap();
-
-        // #table keys in the next data block.
-        let remaining_num_table_keys =
-            self.total_num_table_keys - KEYS_PER_BLOCK * self.next_data_block_idx;
-        let num_table_keys = cmp::min(KEYS_PER_BLOCK, remaining_num_table_keys);
-
-        let data_block = DataBlock::decode_from_bytes(&buf, num_table_keys).unwrap();
+        let block_handle = self
+            .index_block
+            .as_ref()
+            .unwrap()
+            .block_handle(self.next_data_block_idx);
+        let offset = block_handle.offset;
+        let size = block_handle.size;
+
+        // only data blocks whose checksums match are cached, so cached data blocks are served
+        // without verifying them again.
+        let cached = self
+            .block_cache
+            .as_ref()
+            .and_then(|block_cache| block_cache.get(self.file_num(), offset));
+        let data_block = match cached {
+            Some(data_block) => data_block,
+            None => {
+                let buf = read_block(
+                    &self.table.file,
+                    self.table.file_num,
+                    offset,
+                    size + BLOCK_TRAILER_SIZE,
+                    self.verify_checksums,
+                )?;
+                let data_block = decompress_block(&buf, self.table.format_version)
+                    .and_then(|bytes| DataBlock::decode_from_bytes(&bytes))
+                    .map_err(|err| corruption(self.file_num(), offset, err))?;
+                if let Some(block_cache) = self.block_cache.as_ref() {
+                    // a block read without verification is verified before it's cached, since
+                    // the cached block may be served to readers verifying checksums.
+                    if self.verify_checksums || block::verify_checksum(&buf) {
+                        block_cache.insert(self.file_num(), offset, data_block.clone());
+                    }
+                }
+                data_block
+            }
+        };
         self.data_block = Some(data_block);
+        self.data_block_offset = offset;
 
         self.next_data_block_idx += 1;
 
-        true
+        Ok(true)
     }
 
-    /// advance the cursor to the start of the data block with index data_block_idx.
-    fn advance_to(&mut self, data_block_idx: usize) {
-        while self.next_data_block_idx < data_block_idx {
-            self.next();
+    /// move back to the data block before the loaded one if any.
+    /// return true if the moving is successful.
+    fn prev(&mut self) -> Result<bool, Error> {
+        // the loaded data block is the one right before the next data block.
+        let data_block_idx = self.next_data_block_idx - 1;
+        if data_block_idx > 0 {
+            self.next_data_block_idx = data_block_idx - 1;
+        } else if self.index_block_idx > 0 {
+            self.load_index_block(self.index_block_idx - 1)?;
+            self.next_data_block_idx = self.index_block.as_ref().unwrap().len() - 1;
+        } else {
+            return Ok(false);
         }
+        self.next()
+    }
+
+    /// move the cursor to the start of the last data block.
+    /// return false if the sstable has no data blocks.
+    fn advance_to_last(&mut self) -> Result<bool, Error> {
+        let num_index_blocks = self.table.top_index_block.num_index_blocks();
+        if num_index_blocks == 0 {
+            return Ok(false);
+        }
+        self.load_index_block(num_index_blocks - 1)?;
+        self.next_data_block_idx = self.index_block.as_ref().unwrap().len() - 1;
+        Ok(true)
+    }
+
+    /// move the cursor past the last data block.
+    fn advance_to_end(&mut self) -> Result<(), Error> {
+        let num_index_blocks = self.table.top_index_block.num_index_blocks();
+        if num_index_blocks > 0 {
+            self.load_index_block(num_index_blocks - 1)?;
+            self.next_data_block_idx = self.index_block.as_ref().unwrap().len();
+        }
+        Ok(())
+    }
+
+    /// move the cursor to the start of the data_block_idx-th data block in the index_block_idx-th index block.
+    // data blocks are located by their handles and hence could be read without reading the preceding ones.
+    fn advance_to(&mut self, index_block_idx: usize, data_block_idx: usize) -> Result<(), Error> {
+        self.load_index_block(index_block_idx)?;
+        self.next_data_block_idx = data_block_idx;
+        Ok(())
     }
 }
 
 /// a writer for writing table keys into a sstable file.
 pub struct SSTableWriter {
     file_num: FileNum,
+    /// size in bytes of each block in the sstable.
+    block_size: usize,
+    /// compression type of data blocks.
+    compression_type: CompressionType,
     writer: BufWriter<File>,
-    data_block: Option<DataBlock>,
-    filter_block: FilterBlock,
+    data_block: Option<DataBlockBuilder>,
+    filter_type: FilterType,
+    bits_per_key: usize,
+    /// keys of the pending data block.
+    data_block_filter: FilterBlockBuilder,
+    /// keys of flushed data blocks, or keys of the data blocks indexed by the latest index block
+    /// if filters are partitioned.
+    filter_block: FilterBlockBuilder,
+    /// encoded filter partitions of full index blocks.
+    // filter partitions are written after all data blocks and hence they're buffered in memory.
+    filter_partitions: Vec<Vec<u8>>,
+    /// the index block indexing the latest data blocks.
     index_block: IndexBlock,
+    /// full index blocks.
+    // index blocks are written after the filter block and hence they're buffered in memory.
+    index_blocks: Vec<IndexBlock>,
+    /// the top-level index block which is built on `done`.
+    top_index_block: TopIndexBlock,
+    /// size in bytes of the top-level index block with handles of all full index blocks.
+    top_index_block_size: usize,
     num_table_keys: usize,
+    num_data_blocks: usize,
+    /// total size in bytes of flushed data blocks, i.e. the offset of the next data block.
+    data_blocks_size: usize,
     min_table_key: Option<TableKey>,
     max_table_key: Option<TableKey>,
 }
 
 impl SSTableWriter {
-    pub fn new(file_num: FileNum) -> Self {
+    pub fn new(
+        file_num: FileNum,
+        block_size: usize,
+        compression_type: CompressionType,
+        bits_per_key: usize,
+        filter_type: FilterType,
+    ) -> Self {
+        assert!(block_size >= MIN_BLOCK_SIZE);
         let file = File::create(sstable_file_name(file_num)).unwrap();
         SSTableWriter {
             file_num,
+            block_size,
+            compression_type,
             writer: BufWriter::new(file),
             data_block: None,
-            filter_block: FilterBlock::new(),
+            filter_type,
+            bits_per_key,
+            data_block_filter: FilterBlockBuilder::new(bits_per_key),
+            filter_block: FilterBlockBuilder::new(bits_per_key),
+            filter_partitions: Vec::new(),
             index_block: IndexBlock::new(),
+            index_blocks: Vec::new(),
+            top_index_block: TopIndexBlock::new(),
+            top_index_block_size: 0,
             num_table_keys: 0,
+            num_data_blocks: 0,
+            data_blocks_size: 0,
             min_table_key: None,
             max_table_key: None,
         }
     }
 
     pub fn push(&mut self, table_key: TableKey) {
+        // the min and max table keys are stored in the footer and the manifest,
+        // so the user value is stripped to keep them small.
+        let key_bound = table_key.as_lookup_key().as_table_key();
         if self.min_table_key.is_none() {
-            self.min_table_key = Some(table_key.clone());
+            self.min_table_key = Some(key_bound.clone());
         } else {
             self.min_table_key = Some(cmp::min(
                 self.min_table_key.as_ref().unwrap().clone(),
-                table_key.clone(),
+                key_bound.clone(),
             ));
         }
 
         if self.max_table_key.is_none() {
-            self.max_table_key = Some(table_key.clone());
+            self.max_table_key = Some(key_bound);
         } else {
             self.max_table_key = Some(cmp::max(
                 self.max_table_key.as_ref().unwrap().clone(),
-                table_key.clone(),
+                key_bound,
             ));
         }
 
-        self.filter_block.insert(table_key.user_key);
+        // ensure the flushing happens before the data block overflows.
+        if let Some(data_block) = self.data_block.as_ref() {
+            if data_block.size() + table_key.size() > self.block_size {
+                self.flush_data_block();
+            }
+        }
+
+        self.data_block_filter.insert(&table_key.user_key);
         if self.data_block.is_none() {
-            self.data_block = Some(DataBlock::new());
+            self.data_block = Some(DataBlockBuilder::new());
+            self.num_data_blocks += 1;
         }
         self.data_block.as_mut().unwrap().add(table_key);
         self.num_table_keys += 1;
-
-        // ensure the flushing happens before the data block is full.
-        if self.data_block.as_ref().unwrap().size() > BLOCK_SIZE - TABLE_KEY_SIZE {
-            self.flush_data_block();
-        }
     }
 
     fn flush_data_block(&mut self) {
         // println!(
         //     "flush data block containing {} keys",
-        //     self.data_block.as_ref().unwrap().table_keys.len()
+        //     self.data_block.as_ref().unwrap().num_table_keys
         // );
 
-        self.writer
-            .write(&self.data_block.as_ref().unwrap().encode_to_bytes())
-            .unwrap();
+        let data_block = self.data_block.as_ref().unwrap();
+        let block = compress_block(&data_block.encode_to_bytes(), self.compression_type);
+        self.writer.write_all(&block).unwrap();
         self.writer.flush().unwrap();
 
-        // add a fence pointer for the data block.
-        self.index_block
-            .add(self.data_block.as_ref().unwrap().fence_pointer());
+        let block_handle = BlockHandle {
+            offset: self.data_blocks_size,
+            size: block.len() - BLOCK_TRAILER_SIZE,
+        };
+        self.data_blocks_size += block.len();
+
+        // add a fence pointer and a handle for the data block.
+        // start a new index block if the entry does not fit in the current one.
+        let fence_pointer = data_block.fence_pointer();
+        if self.index_block.size() + IndexBlock::entry_size(&fence_pointer) + CHECKSUM_SIZE
+            > self.block_size
+        {
+            self.seal_index_block();
+        }
+        self.index_block.add(fence_pointer, block_handle);
+        self.filter_block.append(&mut self.data_block_filter);
 
         self.data_block = None;
     }
 
+    fn seal_index_block(&mut self) {
+        let index_block = std::mem::replace(&mut self.index_block, IndexBlock::new());
+        self.top_index_block_size += IndexBlockHandle::size_of(index_block.fence_pointer());
+        self.index_blocks.push(index_block);
+
+        if self.filter_type == FilterType::Partitioned {
+            let filter_block = std::mem::replace(
+                &mut self.filter_block,
+                FilterBlockBuilder::new(self.bits_per_key),
+            );
+            self.filter_partitions
+                .push(filter_block.finish().encode_to_bytes(self.block_size));
+        }
+    }
+
     pub fn done(&mut self) -> SSTable {
         if self.data_block.is_some() {
             self.flush_data_block();
         }
 
+        if !self.index_block.is_empty() {
+            self.seal_index_block();
+        }
+
         // flush other blocks.
         // padding is done inside `encode_to_bytes`.
-        self.writer
-            .write(&self.filter_block.encode_to_bytes())
-            .unwrap();
+        let mut offset = self.data_blocks_size;
+        let filter_block = match self.filter_type {
+            FilterType::Full => self.filter_block.finish().encode_to_bytes(self.block_size),
+            FilterType::Partitioned => {
+                // flush filter partitions and collect their handles into the filter index block.
+                let mut filter_index_block = FilterIndexBlock::new();
+                for filter_partition in self.filter_partitions.iter() {
+                    self.writer.write_all(filter_partition).unwrap();
+                    filter_index_block.add(BlockHandle {
+                        offset,
+                        size: filter_partition.len(),
+                    });
+                    offset += filter_partition.len();
+                }
+                filter_index_block.encode_to_bytes(self.block_size)
+            }
+        };
+        self.writer.write_all(&filter_block).unwrap();
+
+        // flush index blocks and collect their handles into the top-level index block.
+        let filter_block_offset = offset;
+        offset += filter_block.len();
+        for index_block in self.index_blocks.iter() {
+            self.writer
+                .write_all(&index_block.encode_to_bytes(self.block_size))
+                .unwrap();
+            self.top_index_block.add(IndexBlockHandle {
+                fence_pointer: index_block.fence_pointer().clone(),
+                offset,
+                num_data_blocks: index_block.len(),
+            });
+            offset += self.block_size;
+        }
+        assert_eq!(self.top_index_block.size(), self.top_index_block_size);
 
         self.writer
-            .write(&self.index_block.encode_to_bytes())
+            .write_all(&self.top_index_block.encode_to_bytes(self.block_size))
             .unwrap();
 
-        let num_data_blocks = table_keys_to_blocks(self.num_table_keys);
-        let filter_block_offset = num_data_blocks * BLOCK_SIZE;
-        let index_block_offset = filter_block_offset + BLOCK_SIZE;
-        let footer = Footer::new(
-            self.num_table_keys,
+        let index_block_offset = offset;
+        let footer = Footer {
+            format_version: FORMAT_VERSION,
+            filter_type: self.filter_type,
+            block_size: self.block_size,
+            num_table_keys: self.num_table_keys,
+            num_data_blocks: self.num_data_blocks,
+            num_index_blocks: self.index_blocks.len(),
             filter_block_offset,
             index_block_offset,
-            self.min_table_key.as_ref().unwrap().clone(),
-            self.max_table_key.as_ref().unwrap().clone(),
-        );
-        self.writer.write(&footer.encode_to_bytes()).unwrap();
+            min_table_key: self.min_table_key.as_ref().unwrap().clone(),
+            max_table_key: self.max_table_key.as_ref().unwrap().clone(),
+        };
+        self.writer.write_all(&footer.encode_to_bytes()).unwrap();
         self.writer.flush().unwrap();
 
         println!(
@@ -354,8 +981,40 @@ impl SSTableWriter {
     }
 
     pub fn file_size(&self) -> usize {
-        let num_data_blocks = table_keys_to_blocks(self.num_table_keys);
-        (num_data_blocks + 3) * BLOCK_SIZE
+        // the index block indexing the latest data blocks, if any, is not sealed yet.
+        let has_pending_index_block = !self.index_block.is_empty() || self.data_block.is_some();
+        let num_index_blocks = self.index_blocks.len() + usize::from(has_pending_index_block);
+        let num_top_index_blocks = num_blocks_of(self.top_index_block_size, self.block_size);
+        // the keys of the pending data block are not added to the filter block yet.
+        let pending_filter_block_size = BloomFilter::encoded_size(
+            self.filter_block.num_keys() + self.data_block_filter.num_keys(),
+            self.bits_per_key,
+        );
+        let num_filter_blocks = match self.filter_type {
+            FilterType::Full => num_blocks_of(pending_filter_block_size, self.block_size),
+            FilterType::Partitioned => {
+                let sealed_partitions_size: usize =
+                    self.filter_partitions.iter().map(Vec::len).sum();
+                // the filter partition of the pending index block is not sealed yet.
+                let num_pending_partition_blocks = if has_pending_index_block {
+                    num_blocks_of(pending_filter_block_size, self.block_size)
+                } else {
+                    0
+                };
+                sealed_partitions_size / self.block_size
+                    + num_pending_partition_blocks
+                    + num_blocks_of(FilterIndexBlock::size_of(num_index_blocks), self.block_size)
+            }
+        };
+        // the pending data block, if any, is estimated by its uncompressed size.
+        let pending_data_block_size = self
+            .data_block
+            .as_ref()
+            .map_or(0, |data_block| data_block.size() + BLOCK_TRAILER_SIZE);
+        // the extra block corresponds to the footer.
+        self.data_blocks_size
+            + pending_data_block_size
+            + (num_filter_blocks + num_index_blocks + num_top_index_blocks + 1) * self.block_size
     }
 }
 
@@ -363,6 +1022,10 @@ pub struct SSTableWriterBatch {
     sstable_writer: Option<SSTableWriter>,
     next_file_num: FileNum,
     sstable_size_capacity: usize,
+    block_size: usize,
+    compression_type: CompressionType,
+    bits_per_key: usize,
+    filter_type: FilterType,
     outputs: Vec<Rc<SSTable>>,
     pub min_table_key: Option<TableKey>,
     pub max_table_key: Option<TableKey>,
@@ -370,11 +1033,22 @@ pub struct SSTableWriterBatch {
 
 /// receives table keys and write them into a batch of sstable files.
 impl SSTableWriterBatch {
-    pub fn new(next_file_num: FileNum, sstable_size_capacity: usize) -> Self {
+    pub fn new(
+        next_file_num: FileNum,
+        sstable_size_capacity: usize,
+        block_size: usize,
+        compression_type: CompressionType,
+        bits_per_key: usize,
+        filter_type: FilterType,
+    ) -> Self {
         Self {
             sstable_writer: None,
             next_file_num,
             sstable_size_capacity,
+            block_size,
+            compression_type,
+            bits_per_key,
+            filter_type,
             outputs: Vec::new(),
             min_table_key: None,
             max_table_key: None,
@@ -389,17 +1063,29 @@ impl SSTableWriterBatch {
 
     /// push a table key into the active sstable writer.
     pub fn push(&mut self, table_key: TableKey) {
+        // versions of a user key are never split across sstables, otherwise a compaction may move
+        // the newer versions of the user key to the next level while leaving the older ones.
+        // hence a full sstable is harnessed only when a table key of another user key arrives.
+        if let Some(sstable_writer) = self.sstable_writer.as_ref() {
+            if sstable_writer.file_size() >= self.sstable_size_capacity
+                && sstable_writer.max_table_key.as_ref().unwrap().user_key != table_key.user_key
+            {
+                self.harness();
+            }
+        }
+
         if self.sstable_writer.is_none() {
             let file_num = self.alloc_file_num();
-            self.sstable_writer = Some(SSTableWriter::new(file_num));
+            self.sstable_writer = Some(SSTableWriter::new(
+                file_num,
+                self.block_size,
+                self.compression_type,
+                self.bits_per_key,
+                self.filter_type,
+            ));
         }
 
         self.sstable_writer.as_mut().unwrap().push(table_key);
-        if self.sstable_writer.as_ref().unwrap().file_size()
-            > self.sstable_size_capacity - TABLE_KEY_SIZE
-        {
-            self.harness();
-        }
     }
 
     /// harness an sstable.
@@ -419,15 +1105,9 @@ impl SSTableWriterBatch {
         let mut min_table_key = self.outputs.first().unwrap().min_table_key.clone();
         let mut max_table_key = self.outputs.first().unwrap().max_table_key.clone();
 
-        for i in 1..self.outputs.len() {
-            min_table_key = cmp::min(
-                min_table_key.clone(),
-                self.outputs.get(i).unwrap().min_table_key.clone(),
-            );
-            max_table_key = cmp::max(
-                max_table_key.clone(),
-                self.outputs.get(i).unwrap().max_table_key.clone(),
-            );
+        for output in self.outputs.iter().skip(1) {
+            min_table_key = cmp::min(min_table_key, output.min_table_key.clone());
+            max_table_key = cmp::max(max_table_key, output.max_table_key.clone());
         }
 
         self.min_table_key = Some(min_table_key);
@@ -435,15 +1115,32 @@ impl SSTableWriterBatch {
 
         (self.outputs.clone(), self.next_file_num)
     }
+
+    /// discard the sstables written so far and delete their files.
+    /// return the next file number to allocate.
+    pub fn abort(&mut self) -> FileNum {
+        let mut file_nums: Vec<FileNum> = self
+            .outputs
+            .iter()
+            .map(|sstable| sstable.file_num)
+            .collect();
+        if let Some(sstable_writer) = self.sstable_writer.take() {
+            file_nums.push(sstable_writer.file_num);
+        }
+        self.outputs.clear();
+        for file_num in file_nums {
+            remove_file(sstable_file_name(file_num)).unwrap();
+        }
+        self.next_file_num
+    }
 }
 
 pub struct SSTableStats {
     indent: usize,
     pub file_num: FileNum,
-    #[allow(dead_code)]
-    all_table_keys: Vec<String>,
-    #[allow(dead_code)]
-    visible_table_keys: Vec<String>,
+    num_table_keys: usize,
+    /// table keys not shadowed by newer versions of the same user keys in the sstable.
+    num_visible_table_keys: usize,
     min_table_key: TableKey,
     max_table_key: TableKey,
 }
@@ -454,20 +1151,11 @@ impl Display for SSTableStats {
 
         stats += "  ".repeat(self.indent).as_str();
         stats += &format!("Min = {}    ", self.min_table_key);
-        stats += &format!("Max = {}\n", self.max_table_key);
-
-        // stats += &format!("all table keys:\n\tcount: {}", self.all_table_keys.len());
-        // for table_key in self.all_table_keys.iter() {
-        //     stats += &format!("\n\t{}", table_key);
-        // }
-
-        // stats += &format!(
-        //     "\nvisible table keys:\n\tcount: {}",
-        //     self.visible_table_keys.len()
-        // );
-        // for table_key in self.visible_table_keys.iter() {
-        //     stats += &format!("\n\t{}", table_key);
-        // }
+        stats += &format!("Max = {}    ", self.max_table_key);
+        stats += &format!(
+            "table keys = {}  visible table keys = {}\n",
+            self.num_table_keys, self.num_visible_table_keys
+        );
 
         write!(f, "{}", stats)
     }
@@ -475,24 +1163,22 @@ impl Display for SSTableStats {
 
 impl SSTable {
     pub fn stats(&self, indent: usize) -> SSTableStats {
-        let mut all_table_keys = Vec::new();
-        let mut visible_table_keys = Vec::new();
-
-        let mut iter = self.iter().unwrap();
+        let mut iter = self.iter(&ReadOptions::default()).unwrap();
+        let num_table_keys = iter.reader.table.total_num_table_keys;
+        let mut num_visible_table_keys = 0;
         let mut last_user_key = None;
         while let Some(table_key) = iter.next() {
-            if last_user_key.is_none() || last_user_key.unwrap() != table_key.user_key {
+            if last_user_key.as_ref() != Some(&table_key.user_key) {
                 last_user_key = Some(table_key.user_key);
-                visible_table_keys.push(format!("{}", table_key));
+                num_visible_table_keys += 1;
             }
-            all_table_keys.push(format!("{}", table_key));
         }
 
         SSTableStats {
             indent,
             file_num: self.file_num,
-            all_table_keys,
-            visible_table_keys,
+            num_table_keys,
+            num_visible_table_keys,
             min_table_key: self.min_table_key.clone(),
             max_table_key: self.max_table_key.clone(),
         }
@@ -522,68 +1208,530 @@ impl SSTable {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::{create_dir, remove_dir_all};
+    use crate::storage::test_util::TempSSTableDir;
+
+    fn file_size(file_num: FileNum) -> usize {
+        let file = File::open(sstable_file_name(file_num)).unwrap();
+        file.metadata().unwrap().len() as usize
+    }
 
     #[test]
     fn writer_reader() {
-        let _ = create_dir("./sstables");
-        let file_num = 42;
-        let mut writer = SSTableWriter::new(file_num);
+        let dir = TempSSTableDir::new();
+        let file_num = dir.next_file_num();
+        let mut writer = SSTableWriter::new(
+            file_num,
+            DEFAULT_BLOCK_SIZE,
+            CompressionType::None,
+            DEFAULT_BITS_PER_KEY,
+            FilterType::Full,
+        );
 
         let num_table_keys = 963;
         for i in 0..num_table_keys {
-            let table_key = TableKey::new(i, i as usize, WriteType::Put, i);
-            writer.push(table_key);
+            writer.push(TableKey::identity(i));
         }
         let sstable = writer.done();
-        assert_eq!(sstable.file_size, 8 * BLOCK_SIZE);
+        assert_eq!(sstable.file_size, file_size(file_num));
 
-        let reader = SSTableReader::new(file_num);
+        let reader = SSTableReader::new(file_num, &ReadOptions::default()).unwrap();
 
         // check num_table_keys.
-        assert_eq!(writer.num_table_keys, reader.total_num_table_keys);
+        assert_eq!(writer.num_table_keys, reader.table.total_num_table_keys);
 
         // check filter block.
-        {
-            let writer_bytes = writer.filter_block.encode_to_bytes();
-            let reader_bytes = reader.filter_block.encode_to_bytes();
-            for i in 0..BLOCK_SIZE {
-                assert_eq!(writer_bytes[i], reader_bytes[i]);
-            }
-        }
+        assert_eq!(
+            writer
+                .filter_block
+                .finish()
+                .encode_to_bytes(DEFAULT_BLOCK_SIZE),
+            full_filter_block(&reader).encode_to_bytes(DEFAULT_BLOCK_SIZE)
+        );
 
-        // check index block.
-        {
-            let writer_bytes = writer.index_block.encode_to_bytes();
-            let reader_bytes = reader.index_block.encode_to_bytes();
-            for i in 0..BLOCK_SIZE {
-                assert_eq!(writer_bytes[i], reader_bytes[i]);
-            }
-        }
-        let _ = remove_dir_all("./sstables");
+        // check top-level index block.
+        assert_eq!(
+            writer.top_index_block.encode_to_bytes(DEFAULT_BLOCK_SIZE),
+            reader
+                .table
+                .top_index_block
+                .encode_to_bytes(DEFAULT_BLOCK_SIZE)
+        );
     }
 
     #[test]
-    fn sstable_iterator() {
-        let _ = create_dir("./sstables");
-        let file_num = 42;
-        let mut writer = SSTableWriter::new(file_num);
+    fn partitioned_index() {
+        let dir = TempSSTableDir::new();
+        let file_num = dir.next_file_num();
+        let mut writer = SSTableWriter::new(
+            file_num,
+            DEFAULT_BLOCK_SIZE,
+            CompressionType::None,
+            DEFAULT_BITS_PER_KEY,
+            FilterType::Full,
+        );
 
-        let num_table_keys = 963;
+        // large keys make both the index and the top-level index span multiple blocks.
+        // keys differ early so that prefix compression does not shrink them.
+        let make_key = |i: usize| format!("{:08}{}", i, "x".repeat(504)).into_bytes();
+        let num_table_keys = 1000;
         for i in 0..num_table_keys {
-            let table_key = TableKey::new(i, i as usize, WriteType::Put, i);
-            writer.push(table_key);
+            writer.push(TableKey::new(&make_key(i), i, WriteType::Put, b""));
         }
         let sstable = writer.done();
-        assert_eq!(sstable.file_size, 8 * BLOCK_SIZE);
+        assert!(writer.index_blocks.len() > 1);
+        assert!(writer.top_index_block.num_blocks(DEFAULT_BLOCK_SIZE) > 1);
+
+        assert_eq!(sstable.file_size, file_size(file_num));
+
+        // check every key could be located through the partitioned index.
+        for i in 0..num_table_keys {
+            let lookup_key = LookupKey::new(&make_key(i), i);
+            let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+            iter.seek(&lookup_key);
+            assert!(iter.valid());
+            assert_eq!(iter.curr().unwrap().user_key, make_key(i));
+        }
+
+        // check a key greater than all keys could not be located.
+        let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+        iter.seek(&LookupKey::new(&make_key(num_table_keys), 0));
+        assert!(!iter.valid());
+    }
 
-        let mut iter = sstable.iter().unwrap();
+    #[test]
+    fn sstable_iterator() {
+        let dir = TempSSTableDir::new();
+        let num_table_keys = 963;
+        let sstable = dir.write_sstable((0..num_table_keys).map(TableKey::identity));
+        assert_eq!(sstable.file_size, file_size(sstable.file_num));
+
+        let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
         let mut i = 0;
         while let Some(table_key) = iter.next() {
-            assert_eq!(table_key, TableKey::new(i, i as usize, WriteType::Put, i));
+            assert_eq!(table_key, TableKey::identity(i));
             i += 1;
         }
         assert_eq!(i, num_table_keys);
-        let _ = remove_dir_all("./sstables");
+
+        // iterate backwards across data blocks.
+        iter.seek_to_last();
+        for i in (0..num_table_keys).rev() {
+            assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            iter.prev();
+        }
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+
+        // the direction could be switched in the middle of a scan, including at data block boundaries.
+        iter.seek(&TableKey::identity(500).as_lookup_key());
+        for i in (0..500).rev() {
+            assert_eq!(iter.prev(), Some(TableKey::identity(i)));
+            assert_eq!(iter.next(), Some(TableKey::identity(i + 1)));
+            assert_eq!(iter.prev(), Some(TableKey::identity(i)));
+        }
+        iter.seek_for_prev(&LookupKey::new(&700_i32.to_be_bytes(), usize::MAX));
+        assert_eq!(iter.curr(), Some(TableKey::identity(699)));
+    }
+
+    #[test]
+    fn mixed_block_sizes() {
+        let dir = TempSSTableDir::new();
+
+        // sstables written with different block sizes could be read side by side.
+        let num_table_keys = 963;
+        let mut sstables = Vec::new();
+        for block_size in [DEFAULT_BLOCK_SIZE, 16 * 1024, 64 * 1024] {
+            let file_num = dir.next_file_num();
+            let mut writer = SSTableWriter::new(
+                file_num,
+                block_size,
+                CompressionType::None,
+                DEFAULT_BITS_PER_KEY,
+                FilterType::Full,
+            );
+            for i in 0..num_table_keys {
+                writer.push(TableKey::identity(i));
+            }
+            let sstable = writer.done();
+            assert_eq!(sstable.file_size, file_size(file_num));
+            sstables.push(sstable);
+        }
+
+        for sstable in sstables.iter() {
+            let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+            let mut i = 0;
+            while let Some(table_key) = iter.next() {
+                assert_eq!(table_key, TableKey::identity(i));
+                i += 1;
+            }
+            assert_eq!(i, num_table_keys);
+
+            let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+            iter.seek(&TableKey::identity(500).as_lookup_key());
+            assert_eq!(iter.curr().unwrap(), TableKey::identity(500));
+        }
+    }
+
+    #[test]
+    fn compressed_data_blocks() {
+        let dir = TempSSTableDir::new();
+
+        let make_table_key = |i: usize| {
+            TableKey::new(
+                format!("key{:08}", i).as_bytes(),
+                i,
+                WriteType::Put,
+                format!("value{:08}", i).as_bytes(),
+            )
+        };
+
+        // write the same table keys with and without compression.
+        let num_table_keys = 5000;
+        let mut sstables = Vec::new();
+        for compression_type in [CompressionType::None, CompressionType::Lz4] {
+            let file_num = dir.next_file_num();
+            let mut writer = SSTableWriter::new(
+                file_num,
+                DEFAULT_BLOCK_SIZE,
+                compression_type,
+                DEFAULT_BITS_PER_KEY,
+                FilterType::Full,
+            );
+            for i in 0..num_table_keys {
+                writer.push(make_table_key(i));
+            }
+            let sstable = writer.done();
+            assert_eq!(sstable.file_size, file_size(file_num));
+            sstables.push(sstable);
+        }
+        assert!(sstables[1].file_size < sstables[0].file_size);
+
+        for sstable in sstables.iter() {
+            let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+            let mut i = 0;
+            while let Some(table_key) = iter.next() {
+                assert_eq!(table_key, make_table_key(i));
+                assert_eq!(table_key.user_val, make_table_key(i).user_val);
+                i += 1;
+            }
+            assert_eq!(i, num_table_keys);
+
+            let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+            iter.seek(&make_table_key(4321).as_lookup_key());
+            assert_eq!(iter.curr().unwrap(), make_table_key(4321));
+        }
+    }
+
+    /// flip a byte at the offset of the sstable file.
+    fn full_filter_block(reader: &SSTableReader) -> &FilterBlock {
+        match &reader.table.filter {
+            TableFilter::Full(filter_block) => filter_block,
+            TableFilter::Partitioned { .. } => panic!("Unexpected partitioned filter"),
+        }
+    }
+
+    fn corrupt(file_num: FileNum, offset: usize) {
+        let file_name = sstable_file_name(file_num);
+        let mut bytes = std::fs::read(&file_name).unwrap();
+        bytes[offset] ^= 0xFF;
+        std::fs::write(&file_name, bytes).unwrap();
+    }
+
+    #[test]
+    fn checksums() {
+        let dir = TempSSTableDir::new();
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..Default::default()
+        };
+
+        let write_sstable = || dir.write_sstable((0..963).map(TableKey::identity));
+
+        // a corrupted data block is detected only if checksums are verified.
+        let sstable = write_sstable();
+        let file_num = sstable.file_num;
+        // flip a key byte of the first entry.
+        corrupt(file_num, 5);
+
+        let mut iter = sstable.iter(&verify).unwrap();
+        assert!(iter.next().is_none());
+        assert!(!iter.valid());
+        assert_eq!(
+            iter.status(),
+            Err(Error::Corruption {
+                file_num,
+                block_offset: 0,
+                reason: "checksum mismatch".to_string(),
+            })
+        );
+        let lookup_key = TableKey::identity(0).as_lookup_key();
+        assert!(sstable.get(&lookup_key, &verify).is_err());
+
+        let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+        iter.next();
+        assert!(iter.status().is_ok());
+
+        // a corrupted data block read without verification is not cached, so that it's still
+        // detected by the reads verifying checksums through the cache.
+        let block_cache = Arc::new(BlockCache::new(1 << 20));
+        let cached = ReadOptions {
+            block_cache: Some(block_cache.clone()),
+            ..Default::default()
+        };
+        let mut iter = sstable.iter(&cached).unwrap();
+        while iter.next().is_some() {}
+        assert!(iter.status().is_ok());
+        assert!(block_cache.get(file_num, 0).is_none());
+        assert!(block_cache.stats().usage > 0);
+
+        let verify_cached = ReadOptions {
+            verify_checksums: true,
+            ..cached
+        };
+        let mut iter = sstable.iter(&verify_cached).unwrap();
+        assert!(iter.next().is_none());
+        assert!(iter.status().is_err());
+
+        // a corrupted footer is always detected.
+        let sstable = write_sstable();
+        let file_num = sstable.file_num;
+        let footer_offset = sstable.file_size - DEFAULT_BLOCK_SIZE;
+        corrupt(file_num, footer_offset + 10);
+        assert_eq!(
+            sstable.iter(&ReadOptions::default()).err(),
+            Some(Error::Corruption {
+                file_num,
+                block_offset: footer_offset,
+                reason: "checksum mismatch".to_string(),
+            })
+        );
+
+        // a corrupted index block is always detected.
+        let sstable = write_sstable();
+        let file_num = sstable.file_num;
+        // the only index block is placed between the filter block and the top-level index block.
+        let index_block_offset = sstable.file_size - 3 * DEFAULT_BLOCK_SIZE;
+        corrupt(file_num, index_block_offset);
+        let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+        iter.seek(&TableKey::identity(500).as_lookup_key());
+        assert!(!iter.valid());
+        assert_eq!(
+            iter.status(),
+            Err(Error::Corruption {
+                file_num,
+                block_offset: index_block_offset,
+                reason: "checksum mismatch".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn magic_and_format_version() {
+        let dir = TempSSTableDir::new();
+
+        // a file not ending with the magic number is not an sstable.
+        let file_num = dir.next_file_num();
+        std::fs::write(sstable_file_name(file_num), vec![7; 2 * DEFAULT_BLOCK_SIZE]).unwrap();
+        assert_eq!(
+            SSTableReader::new(file_num, &ReadOptions::default()).err(),
+            Some(Error::NotSSTable { file_num })
+        );
+
+        // a missing file fails to open rather than panics.
+        let file_num = dir.next_file_num();
+        assert!(matches!(
+            SSTableReader::new(file_num, &ReadOptions::default()).err(),
+            Some(Error::Io { file_num: f, .. }) if f == file_num
+        ));
+
+        // an sstable written in an unknown format version is rejected before its footer is decoded.
+        let sstable = dir.write_sstable((0..100).map(TableKey::identity));
+        let file_num = sstable.file_num;
+        assert!(sstable.iter(&ReadOptions::default()).is_ok());
+
+        let file_name = sstable_file_name(file_num);
+        let mut bytes = std::fs::read(&file_name).unwrap();
+        let format_version_offset = bytes.len() - FOOTER_TAIL_SIZE;
+        bytes[format_version_offset..format_version_offset + 4]
+            .copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&file_name, bytes).unwrap();
+        assert_eq!(
+            sstable.iter(&ReadOptions::default()).err(),
+            Some(Error::UnsupportedFormatVersion {
+                file_num,
+                format_version: FORMAT_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn sized_filter_block() {
+        let dir = TempSSTableDir::new();
+        let num_table_keys = 20000;
+        let sstable = dir.write_sstable((0..num_table_keys).map(TableKey::identity));
+        let file_num = sstable.file_num;
+        assert_eq!(sstable.file_size, file_size(file_num));
+
+        // the filter is sized by the number of keys and hence spans multiple blocks.
+        let reader = SSTableReader::new(file_num, &ReadOptions::default()).unwrap();
+        let filter_block = full_filter_block(&reader).encode_to_bytes(DEFAULT_BLOCK_SIZE);
+        assert!(filter_block.len() > DEFAULT_BLOCK_SIZE);
+
+        for i in 0..num_table_keys {
+            assert!(full_filter_block(&reader).maybe_contain(&TableKey::identity(i).user_key));
+        }
+        let num_false_positives = (num_table_keys..2 * num_table_keys)
+            .filter(|i| full_filter_block(&reader).maybe_contain(&TableKey::identity(*i).user_key))
+            .count();
+        assert!(num_false_positives < num_table_keys as usize / 50);
+    }
+
+    #[test]
+    fn partitioned_filter() {
+        let dir = TempSSTableDir::new();
+        let file_num = dir.next_file_num();
+        let mut writer = SSTableWriter::new(
+            file_num,
+            DEFAULT_BLOCK_SIZE,
+            CompressionType::None,
+            DEFAULT_BITS_PER_KEY,
+            FilterType::Partitioned,
+        );
+
+        // large keys make the index span multiple index blocks and hence multiple filter partitions.
+        let make_key = |i: usize| format!("{:08}{}", i, "x".repeat(504)).into_bytes();
+        let num_table_keys = 1000;
+        for i in 0..num_table_keys {
+            writer.push(TableKey::new(&make_key(2 * i), i, WriteType::Put, b""));
+        }
+        let sstable = writer.done();
+        assert!(writer.index_blocks.len() > 1);
+        assert_eq!(sstable.file_size, file_size(file_num));
+
+        let reader = SSTableReader::new(file_num, &ReadOptions::default()).unwrap();
+        let TableFilter::Partitioned(filter_index_block) = &reader.table.filter else {
+            panic!("Unexpected full filter");
+        };
+        assert_eq!(
+            filter_index_block.num_partitions(),
+            writer.index_blocks.len()
+        );
+        // filter partitions are loaded lazily.
+        assert!(reader.filter_partition.is_none());
+
+        // every key passes the filter partition of its index block.
+        for i in 0..num_table_keys {
+            let lookup_key = LookupKey::new(&make_key(2 * i), i);
+            let (table_key, _) = sstable.get(&lookup_key, &ReadOptions::default()).unwrap();
+            assert_eq!(table_key.unwrap().user_key, make_key(2 * i));
+        }
+
+        // most missing keys are rejected by filter partitions without reading index blocks.
+        let mut num_rejected = 0;
+        for i in 0..num_table_keys - 1 {
+            let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+            iter.point_seek(&LookupKey::new(&make_key(2 * i + 1), 0));
+            assert!(iter.status().is_ok());
+            if iter.reader.index_block.is_none() {
+                assert!(!iter.valid());
+                num_rejected += 1;
+            }
+        }
+        assert!(num_rejected > num_table_keys * 9 / 10);
+
+        // a corrupted filter partition is always detected.
+        let first_partition_offset = filter_index_block.get(0).offset;
+        corrupt(file_num, first_partition_offset);
+        let mut iter = sstable.iter(&ReadOptions::default()).unwrap();
+        iter.point_seek(&LookupKey::new(&make_key(0), 0));
+        assert!(!iter.valid());
+        assert_eq!(
+            iter.status(),
+            Err(Error::Corruption {
+                file_num,
+                block_offset: first_partition_offset,
+                reason: "checksum mismatch".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn mmap_reads() {
+        let dir = TempSSTableDir::new();
+        let file_num = dir.next_file_num();
+        let mut writer = SSTableWriter::new(
+            file_num,
+            DEFAULT_BLOCK_SIZE,
+            CompressionType::Lz4,
+            DEFAULT_BITS_PER_KEY,
+            FilterType::Partitioned,
+        );
+        let num_table_keys = 2000;
+        for i in 0..num_table_keys {
+            writer.push(TableKey::identity(i));
+        }
+        let sstable = writer.done();
+
+        let mmap = ReadOptions {
+            verify_checksums: true,
+            use_mmap: true,
+            ..Default::default()
+        };
+
+        // memory-mapped reads see the same table keys as positioned reads.
+        let mut iter = sstable.iter(&mmap).unwrap();
+        for i in 0..num_table_keys {
+            assert_eq!(iter.next(), Some(TableKey::identity(i)));
+        }
+        assert!(iter.next().is_none());
+        assert!(iter.status().is_ok());
+
+        for i in (0..num_table_keys).step_by(7) {
+            let lookup_key = TableKey::identity(i).as_lookup_key();
+            let (table_key, _) = sstable.get(&lookup_key, &mmap).unwrap();
+            assert_eq!(table_key, Some(TableKey::identity(i)));
+        }
+
+        // a corrupted data block is detected through the memory map as well.
+        corrupt(file_num, 5);
+        let mut iter = sstable.iter(&mmap).unwrap();
+        assert!(iter.next().is_none());
+        assert!(iter.status().is_err());
+    }
+
+    #[test]
+    fn writer_batch_keeps_versions_together() {
+        let dir = TempSSTableDir::new();
+        let file_num = dir.next_file_num();
+        let mut sstable_writer_batch = SSTableWriterBatch::new(
+            file_num,
+            64 * 1024,
+            DEFAULT_BLOCK_SIZE,
+            CompressionType::None,
+            DEFAULT_BITS_PER_KEY,
+            FilterType::Full,
+        );
+        // many versions of each user key, from the latest to the oldest.
+        for i in 0..100_i32 {
+            for seq_num in (0..30).rev() {
+                sstable_writer_batch.push(TableKey::new(
+                    &i.to_be_bytes(),
+                    seq_num,
+                    WriteType::Put,
+                    &[0; 64],
+                ));
+            }
+        }
+        let (sstables, next_file_num) = sstable_writer_batch.done();
+        assert!(sstables.len() > 1 && next_file_num < file_num + 10);
+
+        // the versions of a user key are never split across sstables.
+        for pair in sstables.windows(2) {
+            assert!(pair[0].max_table_key.user_key < pair[1].min_table_key.user_key);
+        }
+        for sstable in sstables.iter() {
+            assert_eq!(sstable.min_table_key.seq_num, 29);
+            assert_eq!(sstable.max_table_key.seq_num, 0);
+        }
     }
 }
@@ -0,0 +1,146 @@
+use super::lru::Lru;
+use super::sstable::Table;
+use crate::util::error::Error;
+use crate::util::types::{CacheStats, FileNum};
+use std::sync::{Arc, Mutex};
+
+/// a cached table is identified by the file number of its sstable and whether the file is memory
+/// mapped, since a table reads its file in the mode it's opened with.
+type CacheKey = (FileNum, bool);
+
+/// a table cache shared by all sstables of a database.
+/// opened sstables, i.e. the open files along with their parsed filter and index blocks, are cached
+/// and the least recently used ones are closed once the number of open files exceeds the capacity.
+// a table is shared by readers through an `Arc` and hence an evicted table is closed only after
+// all readers of it are dropped.
+pub struct TableCache {
+    /// each table is charged 1 against the max number of open files.
+    lru: Mutex<Lru<CacheKey, Arc<Table>>>,
+}
+
+impl TableCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Mutex::new(Lru::new(capacity, |_| 1)),
+        }
+    }
+
+    /// return the cached table of the sstable file opened in the mode, or open the sstable file and
+    /// cache its table.
+    pub fn get_or_open(&self, file_num: FileNum, use_mmap: bool) -> Result<Arc<Table>, Error> {
+        let key = (file_num, use_mmap);
+        if let Some(table) = self.lru.lock().unwrap().get(&key) {
+            return Ok(table);
+        }
+
+        // the file is opened without holding the lock so that lookups into other tables are not blocked.
+        // tables failed to open are not cached.
+        let table = Arc::new(Table::open(file_num, use_mmap)?);
+        self.lru.lock().unwrap().insert(key, table.clone());
+        Ok(table)
+    }
+
+    /// evict the tables of the sstable file if they're cached.
+    /// called when the sstable file is deleted.
+    pub fn evict(&self, file_num: FileNum) {
+        self.lru
+            .lock()
+            .unwrap()
+            .remove_if(|(key_file_num, _)| *key_file_num == file_num);
+    }
+
+    /// return the statistics of the cache where the usage is the number of open files.
+    pub fn stats(&self) -> CacheStats {
+        self.lru.lock().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::iterator::TableKeyIterator;
+    use crate::storage::keys::TableKey;
+    use crate::storage::sstable::sstable_file_name;
+    use crate::storage::test_util::TempSSTableDir;
+    use crate::util::types::ReadOptions;
+    use std::fs::remove_file;
+
+    #[test]
+    fn lru_eviction() {
+        let dir = TempSSTableDir::new();
+        let [a, b, c] =
+            [(); 3].map(|_| dir.write_sstable((0..100).map(TableKey::identity)).file_num);
+
+        let cache = TableCache::new(2);
+        cache.get_or_open(a, false).unwrap();
+        cache.get_or_open(b, false).unwrap();
+        // access the table a so that the table b becomes the lru one.
+        cache.get_or_open(a, false).unwrap();
+        cache.get_or_open(c, false).unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.usage), (1, 3, 2));
+
+        cache.get_or_open(a, false).unwrap();
+        cache.get_or_open(c, false).unwrap();
+        assert_eq!(cache.stats().hits, 3);
+        cache.get_or_open(b, false).unwrap();
+        assert_eq!(cache.stats().misses, 4);
+        assert_eq!(cache.stats().usage, 2);
+
+        // a file opened in the other mode is cached as another table, and both are evicted along
+        // with the file.
+        let cache = TableCache::new(2);
+        let table = cache.get_or_open(a, false).unwrap();
+        let mmap_table = cache.get_or_open(a, true).unwrap();
+        assert!(!Arc::ptr_eq(&table, &mmap_table));
+        assert!(Arc::ptr_eq(
+            &mmap_table,
+            &cache.get_or_open(a, true).unwrap()
+        ));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.usage), (1, 2, 2));
+        cache.evict(a);
+        assert_eq!(cache.stats().usage, 0);
+
+        // a cache with zero capacity keeps no file open.
+        let cache = TableCache::new(0);
+        cache.get_or_open(a, false).unwrap();
+        cache.get_or_open(a, false).unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.usage), (0, 2, 0));
+    }
+
+    #[test]
+    fn evict_deleted_file() {
+        let dir = TempSSTableDir::new();
+        let num_table_keys = 1000;
+        for use_mmap in [false, true] {
+            let sstable = dir.write_sstable((0..num_table_keys).map(TableKey::identity));
+            let file_num = sstable.file_num;
+            let cache = Arc::new(TableCache::new(4));
+            let read_options = ReadOptions {
+                table_cache: Some(cache.clone()),
+                use_mmap,
+                ..Default::default()
+            };
+            let mut iter = sstable.iter(&read_options).unwrap();
+            assert_eq!(cache.stats().usage, 1);
+
+            // a new reader reuses the cached table.
+            sstable.iter(&read_options).unwrap();
+            assert_eq!(cache.stats().hits, 1);
+
+            // the evicted table stays readable by the readers holding it after the file is deleted.
+            cache.evict(file_num);
+            assert_eq!(cache.stats().usage, 0);
+            remove_file(sstable_file_name(file_num)).unwrap();
+            for i in 0..num_table_keys {
+                iter.next();
+                assert_eq!(iter.curr(), Some(TableKey::identity(i)));
+            }
+            iter.next();
+            assert!(!iter.valid());
+            assert!(iter.status().is_ok());
+        }
+    }
+}
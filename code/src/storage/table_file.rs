@@ -0,0 +1,163 @@
+use crate::util::types::FileNum;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+use super::sstable::sstable_file_name;
+
+/// a read-only memory map of a whole file.
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn map(file: &File, len: usize) -> Result<Self, io::Error> {
+        // mmap rejects zero-length mappings.
+        if len == 0 {
+            return Ok(Self {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+
+        // safety: the mapping is private and read-only, and the file is never modified after it's
+        // written since sstables are immutable.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // safety: the mapping is valid for len bytes until it's unmapped on drop.
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // safety: the mapping is created by mmap with the same length.
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+// safety: the mapping is read-only and hence it could be shared across threads.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+enum Source {
+    /// blocks are read with positioned reads which do not move any file cursor.
+    File(File),
+    /// blocks are copied out of a memory map of the file.
+    Mmap(Mmap),
+}
+
+/// an sstable file opened for random reads.
+/// reads take `&self` and do not share any cursor or buffer state, so an opened file could serve
+/// concurrent reads.
+pub struct TableFile {
+    source: Source,
+    size: usize,
+}
+
+impl TableFile {
+    /// open the sstable file with the file number.
+    /// the file is memory-mapped if `use_mmap` is true.
+    pub fn open(file_num: FileNum, use_mmap: bool) -> Result<Self, io::Error> {
+        let file = File::open(sstable_file_name(file_num))?;
+        let size = file.metadata()?.len() as usize;
+        let source = if use_mmap {
+            // the mapping stays valid after the file is closed.
+            Source::Mmap(Mmap::map(&file, size)?)
+        } else {
+            Source::File(file)
+        };
+        Ok(Self { source, size })
+    }
+
+    /// return the size in bytes of the file.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// read exactly `buf.len()` bytes starting at the offset of the file.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), io::Error> {
+        match &self.source {
+            Source::File(file) => file.read_exact_at(buf, offset as u64),
+            Source::Mmap(mmap) => {
+                let bytes = offset
+                    .checked_add(buf.len())
+                    .and_then(|end| mmap.as_slice().get(offset..end))
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "read beyond the end of file")
+                    })?;
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::TempSSTableDir;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn positioned_and_mmap_reads() {
+        let dir = TempSSTableDir::new();
+        let file_num = dir.next_file_num();
+        let bytes: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(sstable_file_name(file_num), &bytes).unwrap();
+
+        for use_mmap in [false, true] {
+            let file = Arc::new(TableFile::open(file_num, use_mmap).unwrap());
+            assert_eq!(file.size(), bytes.len());
+
+            // reads at arbitrary offsets, in any order and from multiple threads, do not interfere.
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    let file = file.clone();
+                    let bytes = bytes.clone();
+                    thread::spawn(move || {
+                        for offset in (t * 7..bytes.len() - 100).step_by(997).rev() {
+                            let mut buf = vec![0; 100];
+                            file.read_at(offset, &mut buf).unwrap();
+                            assert_eq!(buf, bytes[offset..offset + 100]);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            // a read beyond the end of file fails.
+            let mut buf = vec![0; 100];
+            assert!(file.read_at(bytes.len() - 50, &mut buf).is_err());
+        }
+    }
+}
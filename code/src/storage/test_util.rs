@@ -0,0 +1,69 @@
+use super::block::{DEFAULT_BITS_PER_KEY, DEFAULT_BLOCK_SIZE};
+use super::keys::TableKey;
+use super::sstable::{SSTable, SSTableWriter};
+use crate::util::types::{CompressionType, FileNum, FilterType};
+use std::cell::{Cell, RefCell};
+use tempfile::TempDir;
+
+thread_local! {
+    /// the sstable directory of the test running on the thread if any.
+    static SSTABLE_DIR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// return the sstable directory of the test running on the current thread if any.
+pub fn sstable_dir() -> Option<String> {
+    SSTABLE_DIR.with(|dir| dir.borrow().clone())
+}
+
+/// a temporary directory of the sstable files written by a test, so that tests running in
+/// parallel do not share sstable files.
+/// sstable files are located in the directory on the thread creating it until it's dropped, and the
+/// directory is removed along with the files once it's dropped.
+/// databases write sstables on background threads, so database tests keep using the default
+/// sstable directory.
+pub struct TempSSTableDir {
+    /// the directory is removed when it's dropped.
+    _dir: TempDir,
+    next_file_num: Cell<FileNum>,
+}
+
+impl TempSSTableDir {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        SSTABLE_DIR.with(|sstable_dir| *sstable_dir.borrow_mut() = Some(path));
+        Self {
+            _dir: dir,
+            next_file_num: Cell::new(0),
+        }
+    }
+
+    /// return a file number not used by any sstable file in the directory.
+    pub fn next_file_num(&self) -> FileNum {
+        let file_num = self.next_file_num.get();
+        self.next_file_num.set(file_num + 1);
+        file_num
+    }
+
+    /// write the table keys into a new sstable with the default block size and filter type and
+    /// without compression.
+    pub fn write_sstable(&self, table_keys: impl IntoIterator<Item = TableKey>) -> SSTable {
+        let mut writer = SSTableWriter::new(
+            self.next_file_num(),
+            DEFAULT_BLOCK_SIZE,
+            CompressionType::None,
+            DEFAULT_BITS_PER_KEY,
+            FilterType::Full,
+        );
+        for table_key in table_keys {
+            writer.push(table_key);
+        }
+        writer.done()
+    }
+}
+
+impl Drop for TempSSTableDir {
+    fn drop(&mut self) {
+        SSTABLE_DIR.with(|sstable_dir| *sstable_dir.borrow_mut() = None);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::util::types::FileNum;
+use std::fmt::Display;
+
+/// database error type.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// a block of an sstable file fails the checksum verification or could not be decoded.
+    Corruption {
+        /// file number of the corrupted sstable.
+        file_num: FileNum,
+        /// offset of the corrupted block in the sstable file.
+        block_offset: usize,
+        /// what's wrong with the block.
+        reason: String,
+    },
+    /// an sstable file could not be opened or read, e.g. it's missing.
+    Io {
+        file_num: FileNum,
+        /// the underlying io error.
+        reason: String,
+    },
+    /// a file is not an sstable, i.e. its footer does not end with the sstable magic number.
+    NotSSTable { file_num: FileNum },
+    /// an sstable is written in a format version this build could not read.
+    UnsupportedFormatVersion {
+        file_num: FileNum,
+        format_version: u32,
+    },
+    /// an argument of a call is invalid, e.g. a user key exceeds the size limit.
+    InvalidArgument { reason: String },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Corruption {
+                file_num,
+                block_offset,
+                reason,
+            } => write!(
+                f,
+                "corruption in sstable {} at block offset {}: {}",
+                file_num, block_offset, reason
+            ),
+            Error::Io { file_num, reason } => {
+                write!(f, "io error on sstable {}: {}", file_num, reason)
+            }
+            Error::NotSSTable { file_num } => write!(f, "file {} is not an sstable", file_num),
+            Error::UnsupportedFormatVersion {
+                file_num,
+                format_version,
+            } => write!(
+                f,
+                "sstable {} has unsupported format version {}",
+                file_num, format_version
+            ),
+            Error::InvalidArgument { reason } => write!(f, "invalid argument: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,4 +1,7 @@
+use crate::storage::block_cache::BlockCache;
+use crate::storage::table_cache::TableCache;
 use std::fmt::{Debug, Display};
+use std::sync::Arc;
 
 /// write type.
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -28,6 +31,104 @@ impl Debug for WriteType {
     }
 }
 
+/// compression type of sstable data blocks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionType {
+    /// no compression.
+    None,
+    /// lz4 block format. fast but with a moderate compression ratio.
+    Lz4,
+}
+
+/// layout of the bloom filters of an sstable.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterType {
+    /// a single filter of all keys in the sstable, which is loaded when the sstable is opened.
+    Full,
+    /// a filter partition per index block. only the index of filter partitions is loaded when the
+    /// sstable is opened, and a filter partition is loaded on lookups into its index block.
+    Partitioned,
+}
+
+/// the in-memory index backing a memtable.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemTableRepType {
+    /// an arena-backed skiplist which supports lock-free reads concurrent with writes.
+    SkipList,
+    /// an ordered tree, i.e. a btree.
+    Tree,
+    /// an append-only vector which is sorted only when it's read, e.g. on flush.
+    /// suitable for write-only phases like bulk loading.
+    Vector,
+    /// a hash index of user keys for point lookups plus a vector sorted when it's iterated.
+    /// suitable for point-lookup-heavy phases.
+    Hash,
+}
+
+/// options of reads.
+#[derive(Clone, Default)]
+pub struct ReadOptions {
+    /// the cache of data blocks shared by reads, set by the database.
+    pub(crate) block_cache: Option<Arc<BlockCache>>,
+    /// the cache of opened sstables shared by reads, set by the database.
+    pub(crate) table_cache: Option<Arc<TableCache>>,
+    /// true to verify the checksums of data blocks read from sstables.
+    /// metadata blocks, i.e. the footer, the filter block and the index blocks, are always verified.
+    pub verify_checksums: bool,
+    /// true to read sstables through memory maps instead of positioned reads.
+    pub use_mmap: bool,
+    /// if set, reads see the database as of the snapshot. otherwise, reads see the latest writes.
+    pub snapshot: Option<Snapshot>,
+}
+
+/// a consistent view of the database as of the time the snapshot is taken.
+/// table keys written after the snapshot is taken are invisible to reads with the snapshot.
+/// a snapshot must be released by `Db::release_snapshot` once it's no longer used, otherwise
+/// compactions keep the versions visible to it forever.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+    /// table keys with higher sequence numbers are invisible to the snapshot.
+    pub(crate) seq_num: SeqNum,
+}
+
+/// aggregate operations over the user entries in a key range.
+/// sum, min and max interpret user values as decimal integers and skip entries whose user values
+/// are not decimal integers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AggregateOp {
+    /// number of live keys.
+    Count,
+    /// sum of values. the sum saturates at the integer bounds.
+    Sum,
+    /// min value.
+    Min,
+    /// max value.
+    Max,
+}
+
+/// statistics of a cache.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// total charge of cached entries, in the unit of the cache, e.g. bytes for the block cache and
+    /// open files for the table cache.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+/// the usage and the capacity are displayed without a unit, so that the unit is appended by the
+/// caller knowing the cache.
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hits = {}  misses = {}  usage = {} / {}",
+            self.hits, self.misses, self.usage, self.capacity
+        )
+    }
+}
+
 /// sequence number type.
 pub type SeqNum = usize;
 
@@ -38,9 +139,10 @@ pub type FileNum = usize;
 pub type LevelNum = usize;
 
 /// user key type.
-pub type UserKey = i32;
+/// user keys are arbitrary byte strings ordered byte-wise.
+pub type UserKey = Vec<u8>;
 /// user value type.
-pub type UserValue = i32;
+pub type UserValue = Vec<u8>;
 /// user key-value entry type.
 pub struct UserEntry {
     pub key: UserKey,